@@ -0,0 +1,349 @@
+use crate::auth::{AuthUser, Scope};
+use crate::routes::{http_url, AppState};
+use crate::save_link;
+use crate::storage::{Article, ArticleFilter, ArticleStatus};
+use actix_web::http::StatusCode;
+use actix_web::*;
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::*;
+use serde_json::json;
+use std::fmt;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// Error of the json api, always rendered as `{"error": {"code": ..., "message": ...}}`.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_found() -> ApiError {
+        ApiError::new(StatusCode::NOT_FOUND, "Article not found")
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    fn code(&self) -> String {
+        self.status
+            .canonical_reason()
+            .unwrap_or("error")
+            .to_lowercase()
+            .replace(' ', "_")
+    }
+
+    pub(crate) fn body(&self) -> serde_json::Value {
+        json!({
+            "error": {
+                "code": self.code(),
+                "message": self.message,
+            }
+        })
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(self.body())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> ApiError {
+        error!("{:?}", e);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+    }
+}
+
+impl From<actix_web::Error> for ApiError {
+    fn from(e: actix_web::Error) -> ApiError {
+        ApiError::new(e.as_response_error().status_code(), e.to_string())
+    }
+}
+
+/// Clients of compatible apis send either json or form encoded bodies.
+pub(crate) fn parse_body<T: DeserializeOwned>(req: &HttpRequest, body: &[u8]) -> Result<T, String> {
+    let is_json = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.contains("json"))
+        .unwrap_or(false);
+    if is_json {
+        serde_json::from_slice(body).map_err(|e| e.to_string())
+    } else {
+        serde_urlencoded::from_bytes(body).map_err(|e| e.to_string())
+    }
+}
+
+/// Numbers are sent as strings by many clients and always so in form encoded bodies.
+pub(crate) fn lenient_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<i64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Number {
+        Int(i64),
+        Str(String),
+    }
+    match Option::<Number>::deserialize(deserializer)? {
+        Some(Number::Int(i)) => Ok(Some(i)),
+        Some(Number::Str(s)) if s.is_empty() => Ok(None),
+        Some(Number::Str(s)) => s.parse().map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+/// Auth failures are taken as a value to render them in the api error format.
+pub type ApiUser = std::result::Result<AuthUser, actix_web::Error>;
+
+pub(crate) fn authorize(user: ApiUser, scope: Scope) -> Result<AuthUser, ApiError> {
+    let user = user?;
+    user.require(scope)?;
+    Ok(user)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArticleJson {
+    pub id: i64,
+    pub url: String,
+    pub title: Option<String>,
+    pub status: String,
+    pub tags: Vec<String>,
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub starred: bool,
+    #[serde(default)]
+    pub word_count: Option<i64>,
+    #[serde(default)]
+    pub reading_minutes: Option<i64>,
+}
+
+impl From<Article> for ArticleJson {
+    fn from(a: Article) -> ArticleJson {
+        ArticleJson {
+            id: a.id,
+            url: a.data.url.to_string(),
+            title: a.data.title,
+            status: a.status.as_str().to_string(),
+            tags: a.tags,
+            created_at: a.created_at,
+            starred: a.starred,
+            word_count: a.word_count,
+            reading_minutes: a.reading_minutes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArticleList {
+    pub articles: Vec<ArticleJson>,
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListQuery {
+    pub status: Option<String>,
+    pub tag: Option<String>,
+    pub starred: Option<bool>,
+    pub max_minutes: Option<i64>,
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewArticle {
+    pub url: String,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ArticleUpdate {
+    pub title: Option<String>,
+    pub status: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub starred: Option<bool>,
+}
+
+fn parse_status(status: &str) -> Result<ArticleStatus, ApiError> {
+    ArticleStatus::parse(status)
+        .ok_or_else(|| ApiError::bad_request(format!("Unknown status {}", status)))
+}
+
+pub fn scope() -> actix_web::Scope {
+    web::scope("/api/v1")
+        .app_data(
+            web::JsonConfig::default()
+                .error_handler(|err, _| ApiError::bad_request(err.to_string()).into()),
+        )
+        .app_data(
+            web::QueryConfig::default()
+                .error_handler(|err, _| ApiError::bad_request(err.to_string()).into()),
+        )
+        .service(list_articles)
+        .service(get_article)
+        .service(create_article)
+        .service(update_article)
+        .service(delete_article)
+}
+
+#[get("/articles")]
+pub async fn list_articles(
+    web::Query(q): web::Query<ListQuery>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Read)?;
+    let limit = q.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::bad_request(format!(
+            "Limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let filter = ArticleFilter {
+        status: q.status.as_deref().map(parse_status).transpose()?,
+        tags: q.tag.into_iter().collect(),
+        starred: q.starred,
+        max_minutes: q.max_minutes,
+        before: q.cursor,
+        limit,
+        ..ArticleFilter::default()
+    };
+    let articles: Vec<ArticleJson> = data
+        .storage
+        .articles(&user.user_id, &filter)
+        .await?
+        .into_iter()
+        .map(ArticleJson::from)
+        .collect();
+    let next_cursor = if articles.len() as i64 == limit {
+        articles.last().map(|a| a.id)
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(ArticleList {
+        articles,
+        next_cursor,
+    }))
+}
+
+#[get("/articles/{id}")]
+pub async fn get_article(
+    web::Path(id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Read)?;
+    match data.storage.get_article(&user.user_id, &id).await? {
+        Some(article) => Ok(HttpResponse::Ok().json(ArticleJson::from(article))),
+        None => Err(ApiError::not_found()),
+    }
+}
+
+#[post("/articles")]
+pub async fn create_article(
+    web::Json(new_article): web::Json<NewArticle>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Write)?;
+    let url = http_url(&new_article.url)
+        .ok_or_else(|| ApiError::bad_request("Only http and https urls are saved"))?;
+    let id = save_link(&data.storage, user.user_id, url, new_article.title).await?;
+    if !new_article.tags.is_empty() {
+        data.storage
+            .set_tags(&user.user_id, &id, &new_article.tags)
+            .await?;
+    }
+    match data.storage.get_article(&user.user_id, &id).await? {
+        Some(article) => Ok(HttpResponse::Created().json(ArticleJson::from(article))),
+        None => Err(ApiError::not_found()),
+    }
+}
+
+#[patch("/articles/{id}")]
+pub async fn update_article(
+    web::Path(id): web::Path<i64>,
+    web::Json(update): web::Json<ArticleUpdate>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Write)?;
+    let storage = &data.storage;
+    let article = storage
+        .get_article(&user.user_id, &id)
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let mut id = article.id;
+    if let Some(status) = update.status.as_deref().map(parse_status).transpose()? {
+        let moved = match (article.status, status) {
+            (ArticleStatus::Pending, ArticleStatus::Archived) => {
+                storage.archive(&user.user_id, &id).await?
+            }
+            (ArticleStatus::Archived, ArticleStatus::Pending) => {
+                storage.unarchive(&user.user_id, &id).await?
+            }
+            _ => Some(id),
+        };
+        id = moved.ok_or_else(ApiError::not_found)?;
+    }
+    if let Some(title) = update.title {
+        let title = Some(title).filter(|t| !t.trim().is_empty());
+        storage.update_title(&user.user_id, &id, title).await?;
+    }
+    if let Some(tags) = update.tags {
+        storage.set_tags(&user.user_id, &id, &tags).await?;
+    }
+    if let Some(starred) = update.starred {
+        storage.set_starred(&user.user_id, &id, starred).await?;
+    }
+    match storage.get_article(&user.user_id, &id).await? {
+        Some(article) => Ok(HttpResponse::Ok().json(ArticleJson::from(article))),
+        None => Err(ApiError::not_found()),
+    }
+}
+
+#[delete("/articles/{id}")]
+pub async fn delete_article(
+    web::Path(id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Write)?;
+    let storage = &data.storage;
+    match storage.get_article(&user.user_id, &id).await? {
+        Some(article) => {
+            match article.status {
+                ArticleStatus::Pending => storage.delete_pending(&user.user_id, &id).await?,
+                ArticleStatus::Archived => storage.delete_archived(&user.user_id, &id).await?,
+            }
+            Ok(HttpResponse::NoContent().finish())
+        }
+        None => Err(ApiError::not_found()),
+    }
+}
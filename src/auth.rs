@@ -1,6 +1,13 @@
+use crate::routes::AppState;
+use crate::storage::{ApiToken, Storage};
+use actix_session::UserSession as _;
+use actix_web::{dev::Payload, http, web, FromRequest, HttpRequest};
 use anyhow::{bail, Result};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
 use std::{collections::HashMap, time::Instant};
 use tokio::sync::Mutex;
 
@@ -53,13 +60,237 @@ impl TokenStorage {
     }
 }
 
+/// Request token of the Pocket oauth flow with the client it was issued to.
+#[derive(Clone, Debug)]
+pub struct OauthCode {
+    pub consumer_key: String,
+    /// Where the user goes back after confirming, only this one is used.
+    pub redirect_uri: Option<String>,
+    /// User who confirmed the request on the authorize page.
+    pub user_id: Option<i64>,
+    created_at: Instant,
+}
+
+/// Request tokens issued by `/v3/oauth/request` until they are exchanged or expire.
+pub struct OauthCodes {
+    data: Mutex<HashMap<String, OauthCode>>,
+    code_ttl_secs: u64,
+}
+
+impl OauthCodes {
+    pub fn new(code_ttl_secs: u64) -> OauthCodes {
+        OauthCodes {
+            data: Mutex::new(HashMap::new()),
+            code_ttl_secs,
+        }
+    }
+
+    pub async fn issue(&self, consumer_key: &str, redirect_uri: Option<String>) -> String {
+        let code = generate_token();
+        let mut data = self.data.lock().await;
+        self.clean(&mut data);
+        data.insert(
+            code.clone(),
+            OauthCode {
+                consumer_key: consumer_key.to_string(),
+                redirect_uri,
+                user_id: None,
+                created_at: Instant::now(),
+            },
+        );
+        code
+    }
+
+    pub async fn get(&self, code: &str) -> Option<OauthCode> {
+        let mut data = self.data.lock().await;
+        self.clean(&mut data);
+        data.get(code).cloned()
+    }
+
+    /// Confirms the request for the user, returns none if it's unknown or confirmed already.
+    pub async fn confirm(&self, code: &str, user_id: i64) -> Option<OauthCode> {
+        let mut data = self.data.lock().await;
+        self.clean(&mut data);
+        match data.get_mut(code) {
+            Some(request) if request.user_id.is_none() => {
+                request.user_id = Some(user_id);
+                Some(request.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// User who confirmed the request, the code is used up then.
+    /// Codes are only exchanged by the client they were issued to.
+    pub async fn exchange(&self, code: &str, consumer_key: &str) -> Option<i64> {
+        let mut data = self.data.lock().await;
+        self.clean(&mut data);
+        match data.get(code) {
+            Some(request) if request.consumer_key == consumer_key && request.user_id.is_some() => {
+                data.remove(code).and_then(|r| r.user_id)
+            }
+            _ => None,
+        }
+    }
+
+    fn clean(&self, data: &mut HashMap<String, OauthCode>) {
+        let ttl = self.code_ttl_secs;
+        data.retain(|_, request| request.created_at.elapsed().as_secs() < ttl);
+    }
+}
+
 pub fn generate_token() -> String {
     thread_rng().sample_iter(&Alphanumeric).take(30).collect()
 }
 
+/// Hex encoded sha256 of the token, the only form personal tokens are stored in.
+pub fn hash_token(token: &str) -> String {
+    openssl::sha::sha256(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    Read,
+    Write,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+        }
+    }
+
+    pub fn parse_list(scopes: &str) -> Vec<Scope> {
+        scopes
+            .split(',')
+            .filter_map(|s| match s.trim() {
+                "read" => Some(Scope::Read),
+                "write" => Some(Scope::Write),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn join(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<&str>>()
+            .join(",")
+    }
+}
+
+/// Creates new personal access token and returns its plain value,
+/// which must be shown to the user once and never stored.
+pub async fn issue_api_token(
+    storage: &Storage,
+    user_id: &i64,
+    name: &str,
+    scopes: &[Scope],
+) -> Result<String> {
+    let token = generate_token();
+    storage
+        .add_api_token(user_id, name, &hash_token(&token), &Scope::join(scopes))
+        .await?;
+    Ok(token)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct UserSession {
+    pub(crate) user_id: i64,
+}
+
+/// Authenticated user of the request, either by the session cookie
+/// or by `Authorization: Bearer <personal token>` header.
+#[derive(Debug)]
+pub struct AuthUser {
+    pub user_id: i64,
+    pub token_id: Option<i64>,
+    scopes: Vec<Scope>,
+}
+
+impl AuthUser {
+    pub(crate) fn from_token(token: &ApiToken) -> AuthUser {
+        AuthUser {
+            user_id: token.user_id,
+            token_id: Some(token.id),
+            scopes: Scope::parse_list(&token.scopes),
+        }
+    }
+
+    pub fn require(&self, scope: Scope) -> std::result::Result<(), actix_web::Error> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(actix_web::error::ErrorForbidden(format!(
+                "Token has no {} scope",
+                scope.as_str()
+            )))
+        }
+    }
+
+    /// Some actions, like managing tokens itself, are allowed for the browser session only.
+    pub fn require_session(&self) -> std::result::Result<(), actix_web::Error> {
+        match self.token_id {
+            None => Ok(()),
+            Some(_) => Err(actix_web::error::ErrorForbidden(
+                "Action is not allowed with api token",
+            )),
+        }
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<Self, Self::Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let bearer = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .map(|t| t.trim().to_string());
+        let session_user = req.get_session().get::<UserSession>("user");
+        let data = req.app_data::<web::Data<AppState<'static>>>().cloned();
+        Box::pin(async move {
+            match bearer {
+                Some(token) => {
+                    let data = data.ok_or_else(|| {
+                        actix_web::error::ErrorInternalServerError("No app state configured")
+                    })?;
+                    match data
+                        .storage
+                        .use_api_token(&hash_token(&token))
+                        .await
+                        .map_err(actix_web::error::ErrorInternalServerError)?
+                    {
+                        Some(t) => Ok(AuthUser::from_token(&t)),
+                        None => Err(actix_web::error::ErrorUnauthorized("Unknown api token")),
+                    }
+                }
+                None => match session_user? {
+                    Some(user) => Ok(AuthUser {
+                        user_id: user.user_id,
+                        token_id: None,
+                        scopes: vec![Scope::Read, Scope::Write],
+                    }),
+                    None => Err(actix_web::error::ErrorForbidden("Not authorized")),
+                },
+            }
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::TokenStorage;
+    use super::{hash_token, Scope, TokenStorage};
 
     #[actix_rt::test]
     async fn test_push_double_pop() {
@@ -88,4 +319,18 @@ mod tests {
         token_storage.push(1, "token".to_string()).await.unwrap();
         assert_eq!(token_storage.pop("token").await.ok(), Some(None));
     }
+
+    #[test]
+    fn test_scopes_round_trip() {
+        let scopes = Scope::parse_list(&Scope::join(&[Scope::Read, Scope::Write]));
+        assert_eq!(scopes, vec![Scope::Read, Scope::Write]);
+        assert_eq!(Scope::parse_list("read, unknown"), vec![Scope::Read]);
+    }
+
+    #[test]
+    fn test_hash_token() {
+        assert_eq!(hash_token("token"), hash_token("token"));
+        assert_ne!(hash_token("token"), hash_token("token1"));
+        assert_eq!(hash_token("token").len(), 64);
+    }
 }
@@ -0,0 +1,391 @@
+use crate::auth::{generate_token, AuthUser, Scope};
+use crate::routes::{is_htmx, see_other, AppState, APP_NAME, MAX_NAME_LENGTH};
+use crate::storage::{Article, Collection};
+use actix_web::*;
+use serde::*;
+use serde_json::json;
+
+#[derive(Serialize, Debug)]
+struct CollectionView {
+    id: i64,
+    name: String,
+    public_token: Option<String>,
+}
+
+impl From<Collection> for CollectionView {
+    fn from(c: Collection) -> CollectionView {
+        CollectionView {
+            id: c.id,
+            name: c.name,
+            public_token: c.public_token,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ItemView {
+    id: i64,
+    url: String,
+    title: String,
+    reading_minutes: Option<i64>,
+}
+
+impl From<Article> for ItemView {
+    fn from(a: Article) -> ItemView {
+        let url = a.data.url.to_string();
+        ItemView {
+            id: a.id,
+            title: a.data.title.unwrap_or_else(|| url.clone()),
+            url,
+            reading_minutes: a.reading_minutes,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct CollectionsTemplate<'a> {
+    app_name: &'a str,
+    collections: Vec<CollectionView>,
+    user_id: i64,
+    page: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+struct CollectionTemplate<'a> {
+    app_name: &'a str,
+    collection: CollectionView,
+    items: Vec<ItemView>,
+    user_id: i64,
+    page: &'a str,
+}
+
+fn collection_name(name: &str) -> Option<String> {
+    let name: String = name.trim().chars().take(MAX_NAME_LENGTH).collect();
+    Some(name).filter(|n| !n.is_empty())
+}
+
+#[get("/collections")]
+pub async fn collections_page(
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let collections = data
+        .storage
+        .collections(&user.user_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(CollectionView::from)
+        .collect();
+    let json = json!(CollectionsTemplate {
+        app_name: APP_NAME,
+        collections,
+        user_id: user.user_id,
+        page: "collections"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CollectionForm {
+    name: String,
+}
+
+#[post("/collections")]
+pub async fn create_collection(
+    form: web::Form<CollectionForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let name = match collection_name(&form.name) {
+        Some(name) => name,
+        None => return Ok(HttpResponse::BadRequest().body("Collection name is empty")),
+    };
+    let id = data
+        .storage
+        .create_collection(&user.user_id, &name)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(see_other(&format!("/collections/{}", id)))
+}
+
+/// Articles of the collection in their order, reordered by dragging them.
+#[get("/collections/{collection_id}")]
+pub async fn collection_page(
+    web::Path(collection_id): web::Path<i64>,
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    render_collection(&req, &data, user.user_id, collection_id).await
+}
+
+/// Whole page of the collection, or only its items for htmx.
+async fn render_collection(
+    req: &HttpRequest,
+    data: &AppState<'_>,
+    user_id: i64,
+    collection_id: i64,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let storage = &data.storage;
+    let collection = match storage
+        .get_collection(&user_id, &collection_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(collection) => collection,
+        None => return Ok(HttpResponse::NotFound().body("Collection not found")),
+    };
+    let items = storage
+        .collection_articles(&user_id, &collection_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(ItemView::from)
+        .collect();
+    let json = json!(CollectionTemplate {
+        app_name: APP_NAME,
+        collection: collection.into(),
+        items,
+        user_id,
+        page: "collection"
+    });
+    let template = if is_htmx(req) {
+        "collection_items"
+    } else {
+        "index"
+    };
+    let rendered = &data
+        .hb
+        .render(template, &json)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[post("/collections/{collection_id}")]
+pub async fn rename_collection(
+    web::Path(collection_id): web::Path<i64>,
+    form: web::Form<CollectionForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let name = match collection_name(&form.name) {
+        Some(name) => name,
+        None => return Ok(HttpResponse::BadRequest().body("Collection name is empty")),
+    };
+    if data
+        .storage
+        .rename_collection(&user.user_id, &collection_id, &name)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Ok(see_other(&format!("/collections/{}", collection_id)))
+    } else {
+        Ok(HttpResponse::NotFound().body("Collection not found"))
+    }
+}
+
+#[post("/collections/{collection_id}/delete")]
+pub async fn delete_collection(
+    web::Path(collection_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    if data
+        .storage
+        .delete_collection(&user.user_id, &collection_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Ok(see_other("/collections"))
+    } else {
+        Ok(HttpResponse::NotFound().body("Collection not found"))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CollectionItemForm {
+    article_id: i64,
+}
+
+/// Adds the article to the end of the collection, from the reader view.
+#[post("/collections/{collection_id}/articles")]
+pub async fn add_to_collection(
+    web::Path(collection_id): web::Path<i64>,
+    form: web::Form<CollectionItemForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    if data
+        .storage
+        .add_to_collection(&user.user_id, &collection_id, &form.article_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Ok(see_other(&format!("/collections/{}", collection_id)))
+    } else {
+        Ok(HttpResponse::NotFound().body("Collection or article not found"))
+    }
+}
+
+#[post("/collections/{collection_id}/articles/{article_id}/remove")]
+pub async fn remove_from_collection(
+    web::Path((collection_id, article_id)): web::Path<(i64, i64)>,
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    if !data
+        .storage
+        .remove_from_collection(&user.user_id, &collection_id, &article_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        return Ok(HttpResponse::NotFound().body("Article not found in the collection"));
+    }
+    if is_htmx(&req) {
+        render_collection(&req, &data, user.user_id, collection_id).await
+    } else {
+        Ok(see_other(&format!("/collections/{}", collection_id)))
+    }
+}
+
+/// Saves the order of the items after a drag, the form repeats `ids` in the new order.
+#[post("/collections/{collection_id}/reorder")]
+pub async fn reorder_collection(
+    web::Path(collection_id): web::Path<i64>,
+    body: web::Bytes,
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let mut ids = vec![];
+    for (key, value) in url::form_urlencoded::parse(&body) {
+        if key == "ids" {
+            ids.push(value.parse::<i64>().map_err(error::ErrorBadRequest)?);
+        }
+    }
+    let storage = &data.storage;
+    if storage
+        .get_collection(&user.user_id, &collection_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().body("Collection not found"));
+    }
+    if !storage
+        .reorder_collection(&user.user_id, &collection_id, &ids)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        return Ok(
+            HttpResponse::BadRequest().body("Order must list all articles of the collection")
+        );
+    }
+    if is_htmx(&req) {
+        render_collection(&req, &data, user.user_id, collection_id).await
+    } else {
+        Ok(see_other(&format!("/collections/{}", collection_id)))
+    }
+}
+
+/// Makes the collection readable by anyone with the link of its public page.
+#[post("/collections/{collection_id}/publish")]
+pub async fn publish_collection(
+    web::Path(collection_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let storage = &data.storage;
+    let collection = match storage
+        .get_collection(&user.user_id, &collection_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(collection) => collection,
+        None => return Ok(HttpResponse::NotFound().body("Collection not found")),
+    };
+    // Publishing again keeps the link already shared.
+    if collection.public_token.is_none() {
+        storage
+            .set_collection_token(&user.user_id, &collection_id, Some(&generate_token()))
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+    }
+    Ok(see_other(&format!("/collections/{}", collection_id)))
+}
+
+/// Makes the collection private, its public link stops working for good.
+#[post("/collections/{collection_id}/unpublish")]
+pub async fn unpublish_collection(
+    web::Path(collection_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    if data
+        .storage
+        .set_collection_token(&user.user_id, &collection_id, None)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Ok(see_other(&format!("/collections/{}", collection_id)))
+    } else {
+        Ok(HttpResponse::NotFound().body("Collection not found"))
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct PublicCollectionTemplate<'a> {
+    app_name: &'a str,
+    name: String,
+    items: Vec<ItemView>,
+}
+
+/// Read-only page of a published collection, no login needed.
+#[get("/c/{token}")]
+pub async fn public_collection(
+    web::Path(token): web::Path<String>,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let storage = &data.storage;
+    let collection = match storage
+        .public_collection(&token)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(collection) => collection,
+        None => return Ok(HttpResponse::NotFound().body("Collection not found")),
+    };
+    let items = storage
+        .collection_articles(&collection.user_id, &collection.id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(ItemView::from)
+        .collect();
+    let json = json!(PublicCollectionTemplate {
+        app_name: APP_NAME,
+        name: collection.name,
+        items,
+    });
+    let rendered = &data
+        .hb
+        .render("public_collection", &json)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
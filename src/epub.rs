@@ -0,0 +1,474 @@
+use crate::auth::{AuthUser, Scope};
+use crate::extractor::fetch;
+use crate::routes::AppState;
+use crate::snapshot::{FETCH_TIMEOUT, MAX_RESOURCES, MAX_RESOURCES_TIME};
+use crate::storage::{now, Article, ArticleFilter, ArticleStatus, Storage};
+use actix_web::client::Client;
+use actix_web::*;
+use anyhow::Result;
+use ego_tree::NodeRef;
+use handlebars::html_escape;
+use log::error;
+use scraper::{Html, Node, Selector};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Most articles put in a book, the newest pending ones when none are selected.
+pub const MAX_ARTICLES: i64 = 100;
+/// Images of a book take at most that many bytes, the others are left out.
+const MAX_IMAGES_BYTES: usize = 20 * 1024 * 1024;
+
+/// Article of the book with its content as xhtml, images refer to `images`.
+pub struct Chapter {
+    pub title: String,
+    pub url: String,
+    pub created_at: Option<i64>,
+    pub reading_minutes: Option<i64>,
+    pub tags: Vec<String>,
+    pub content: String,
+}
+
+/// Image embedded in the book, `name` is its path in the book.
+pub struct Image {
+    pub name: String,
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Media types which all e-readers show, with the extension of their files.
+const IMAGE_TYPES: &[(&str, &str)] = &[
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/gif", "gif"),
+    ("image/svg+xml", "svg"),
+];
+
+/// Elements which have no content and are closed at once in xhtml.
+const VOID_ELEMENTS: &[&str] = &["img", "br", "hr"];
+
+fn date(time: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(time).format("%F")
+}
+
+/// Fetches the images of the content while they fit in the size, count and time left for them.
+struct Images<'a> {
+    client: &'a Client,
+    budget: usize,
+    fetches_left: usize,
+    deadline: Instant,
+    /// Name in the book of the fetched images by their url, none if it can't be embedded.
+    names: HashMap<String, Option<String>>,
+    images: Vec<Image>,
+}
+
+impl Images<'_> {
+    async fn embed(&mut self, url: &str) {
+        if self.names.contains_key(url) {
+            return;
+        }
+        if self.fetches_left == 0 || Instant::now() >= self.deadline {
+            self.names.insert(url.to_string(), None);
+            return;
+        }
+        self.fetches_left -= 1;
+        let image = match fetch(self.client, url, self.budget).await {
+            Ok(Some((body, Some(content_type)))) => IMAGE_TYPES
+                .iter()
+                .find(|(t, _)| content_type.starts_with(t))
+                .map(|(media_type, extension)| Image {
+                    name: format!("images/{}.{}", self.images.len() + 1, extension),
+                    media_type: media_type.to_string(),
+                    data: body.to_vec(),
+                }),
+            Ok(_) => None,
+            Err(e) => {
+                error!("{:?}", e);
+                None
+            }
+        };
+        let name = image.as_ref().map(|i| i.name.clone());
+        if let Some(image) = image {
+            self.budget = self.budget.saturating_sub(image.data.len());
+            self.images.push(image);
+        }
+        self.names.insert(url.to_string(), name);
+    }
+}
+
+/// Extracted content as xhtml, images are replaced by their names in the book.
+fn xhtml(node: NodeRef<Node>, images: &HashMap<String, Option<String>>, out: &mut String) {
+    match node.value() {
+        Node::Text(text) => out.push_str(&html_escape(&text.text)),
+        Node::Element(el) => {
+            let name = el.name();
+            if name == "img" {
+                let src = el
+                    .attr("src")
+                    .and_then(|s| images.get(s))
+                    .cloned()
+                    .flatten();
+                if let Some(src) = src {
+                    out.push_str(&format!(r#"<img src="../{}" alt="" />"#, src));
+                }
+                return;
+            }
+            if VOID_ELEMENTS.contains(&name) {
+                out.push_str(&format!("<{} />", name));
+                return;
+            }
+            out.push_str(&format!("<{}>", name));
+            for child in node.children() {
+                xhtml(child, images, out);
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        _ => {
+            for child in node.children() {
+                xhtml(child, images, out);
+            }
+        }
+    }
+}
+
+/// Book of the articles selected by the user, or of the newest pending articles if none are.
+/// Returns none if there is no article to put in it.
+pub async fn articles_epub(
+    storage: &Storage,
+    user_id: &i64,
+    ids: &[i64],
+) -> Result<Option<Vec<u8>>> {
+    let articles: Vec<Article> = if ids.is_empty() {
+        let filter = ArticleFilter {
+            status: Some(ArticleStatus::Pending),
+            limit: MAX_ARTICLES,
+            ..ArticleFilter::default()
+        };
+        storage.articles(user_id, &filter).await?
+    } else {
+        let mut articles = vec![];
+        for id in ids.iter().take(MAX_ARTICLES as usize) {
+            articles.extend(storage.get_article(user_id, id).await?);
+        }
+        articles
+    };
+    if articles.is_empty() {
+        return Ok(None);
+    }
+    let client = Client::builder().timeout(FETCH_TIMEOUT).finish();
+    let mut images = Images {
+        client: &client,
+        budget: MAX_IMAGES_BYTES,
+        fetches_left: MAX_RESOURCES,
+        deadline: Instant::now() + MAX_RESOURCES_TIME,
+        names: HashMap::new(),
+        images: vec![],
+    };
+    let img_selector = Selector::parse("img[src]").unwrap();
+    let mut chapters = vec![];
+    for article in articles {
+        let content = storage
+            .get_content(user_id, &article.id)
+            .await?
+            .unwrap_or_default();
+        let fragment = Html::parse_fragment(&content);
+        for img in fragment.select(&img_selector) {
+            if let Some(src) = img.value().attr("src") {
+                images.embed(src).await;
+            }
+        }
+        let mut content = String::new();
+        for node in fragment.root_element().children() {
+            xhtml(node, &images.names, &mut content);
+        }
+        let url = article.data.url.to_string();
+        chapters.push(Chapter {
+            title: article.data.title.unwrap_or_else(|| url.clone()),
+            url,
+            created_at: article.created_at,
+            reading_minutes: article.reading_minutes,
+            tags: article.tags,
+            content,
+        });
+    }
+    let title = format!("Save to read, {}", date(now()));
+    Ok(Some(build(&title, &chapters, &images.images)))
+}
+
+fn chapter_page(chapter: &Chapter) -> String {
+    let mut details = vec![format!(
+        r#"<a href="{0}">{0}</a>"#,
+        html_escape(&chapter.url)
+    )];
+    if let Some(created_at) = chapter.created_at {
+        details.push(format!("saved {}", date(created_at)));
+    }
+    if let Some(minutes) = chapter.reading_minutes {
+        details.push(format!("{} min read", minutes));
+    }
+    if !chapter.tags.is_empty() {
+        details.push(html_escape(&chapter.tags.join(", ")));
+    }
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>{0}</title></head>
+<body>
+<h1>{0}</h1>
+<p><small>{1}</small></p>
+{2}
+</body>
+</html>
+"#,
+        html_escape(&chapter.title),
+        details.join(" · "),
+        chapter.content
+    )
+}
+
+/// EPUB 3 book with a table of contents, the ncx one is there for older readers.
+pub fn build(title: &str, chapters: &[Chapter], images: &[Image]) -> Vec<u8> {
+    let title = html_escape(title);
+    let identifier = format!("urn:save2read:{}", now());
+    let modified = time::OffsetDateTime::now_utc().format("%Y-%m-%dT%H:%M:%SZ");
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    let mut nav = String::new();
+    let mut ncx = String::new();
+    for (i, chapter) in chapters.iter().enumerate() {
+        let n = i + 1;
+        let chapter_title = html_escape(&chapter.title);
+        manifest.push_str(&format!(
+            "<item id=\"article{0}\" href=\"articles/{0}.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+            n
+        ));
+        spine.push_str(&format!("<itemref idref=\"article{}\"/>\n", n));
+        nav.push_str(&format!(
+            "<li><a href=\"articles/{}.xhtml\">{}</a></li>\n",
+            n, chapter_title
+        ));
+        ncx.push_str(&format!(
+            "<navPoint id=\"article{0}\" playOrder=\"{0}\"><navLabel><text>{1}</text></navLabel>\
+            <content src=\"articles/{0}.xhtml\"/></navPoint>\n",
+            n, chapter_title
+        ));
+    }
+    for (i, image) in images.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"image{}\" href=\"{}\" media-type=\"{}\"/>\n",
+            i + 1,
+            image.name,
+            image.media_type
+        ));
+    }
+    let opf = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:identifier id="id">{}</dc:identifier>
+<dc:title>{}</dc:title>
+<dc:language>en</dc:language>
+<meta property="dcterms:modified">{}</meta>
+</metadata>
+<manifest>
+<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+{}</manifest>
+<spine toc="ncx">
+{}</spine>
+</package>
+"#,
+        identifier, title, modified, manifest, spine
+    );
+    let nav = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{0}</title></head>
+<body>
+<nav epub:type="toc"><h1>{0}</h1>
+<ol>
+{1}</ol>
+</nav>
+</body>
+</html>
+"#,
+        title, nav
+    );
+    let ncx = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+<head><meta name="dtb:uid" content="{}"/></head>
+<docTitle><text>{}</text></docTitle>
+<navMap>
+{}</navMap>
+</ncx>
+"#,
+        identifier, title, ncx
+    );
+    let container = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+    let mut zip = Zip::default();
+    // Readers find the type of the file by this first entry.
+    zip.add("mimetype", b"application/epub+zip");
+    zip.add("META-INF/container.xml", container.as_bytes());
+    zip.add("OEBPS/content.opf", opf.as_bytes());
+    zip.add("OEBPS/nav.xhtml", nav.as_bytes());
+    zip.add("OEBPS/toc.ncx", ncx.as_bytes());
+    for (i, chapter) in chapters.iter().enumerate() {
+        zip.add(
+            &format!("OEBPS/articles/{}.xhtml", i + 1),
+            chapter_page(chapter).as_bytes(),
+        );
+    }
+    for image in images {
+        zip.add(&format!("OEBPS/{}", image.name), &image.data);
+    }
+    zip.finish()
+}
+
+/// Zip archive of stored entries, compression is not worth it for a book of text.
+#[derive(Default)]
+struct Zip {
+    data: Vec<u8>,
+    /// Central directory records of the entries added so far.
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+impl Zip {
+    fn add(&mut self, name: &str, content: &[u8]) {
+        let offset = self.data.len() as u32;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(content);
+        let crc = hasher.finalize();
+        let size = content.len() as u32;
+        let name = name.as_bytes();
+        // Version 2.0, no flags, stored, no time.
+        let common = |out: &mut Vec<u8>| {
+            out.extend_from_slice(&20u16.to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&crc.to_le_bytes());
+            out.extend_from_slice(&size.to_le_bytes());
+            out.extend_from_slice(&size.to_le_bytes());
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+        };
+        self.data.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        common(&mut self.data);
+        self.data.extend_from_slice(name);
+        self.data.extend_from_slice(content);
+
+        self.directory
+            .extend_from_slice(&0x02014b50u32.to_le_bytes());
+        self.directory.extend_from_slice(&20u16.to_le_bytes());
+        common(&mut self.directory);
+        // No comment, disk 0, no attributes.
+        self.directory.extend_from_slice(&[0; 6]);
+        self.directory.extend_from_slice(&0u32.to_le_bytes());
+        self.directory.extend_from_slice(&offset.to_le_bytes());
+        self.directory.extend_from_slice(name);
+        self.entries += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let offset = self.data.len() as u32;
+        let size = self.directory.len() as u32;
+        self.data.extend_from_slice(&self.directory);
+        self.data.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        self.data.extend_from_slice(&[0; 4]);
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&size.to_le_bytes());
+        self.data.extend_from_slice(&offset.to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes());
+        self.data
+    }
+}
+
+/// Book of the selected articles, repeated `ids` in the query, or of the pending ones.
+#[get("/export/epub")]
+pub async fn epub_download(
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let mut ids = vec![];
+    for (key, value) in url::form_urlencoded::parse(req.query_string().as_bytes()) {
+        if key == "ids" {
+            ids.push(value.parse::<i64>().map_err(error::ErrorBadRequest)?);
+        }
+    }
+    match articles_epub(&data.storage, &user.user_id, &ids)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(book) => Ok(HttpResponse::Ok()
+            .content_type("application/epub+zip")
+            .header(
+                http::header::CONTENT_DISPOSITION,
+                "attachment; filename=\"save2read.epub\"",
+            )
+            .body(book)),
+        None => Ok(HttpResponse::NotFound().body("No articles to put in the book")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_zip() {
+        let mut zip = Zip::default();
+        zip.add("mimetype", b"application/epub+zip");
+        zip.add("a/b.txt", b"text");
+        let data = zip.finish();
+        // The first entry is stored right after its 30 bytes header and its name.
+        assert_eq!(b"mimetypeapplication/epub+zip", &data[30..58]);
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(2, archive.len());
+        let mut read = |name: &str| {
+            let mut text = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        assert_eq!("application/epub+zip", read("mimetype"));
+        assert_eq!("text", read("a/b.txt"));
+    }
+
+    #[test]
+    fn test_xhtml() {
+        let fragment = Html::parse_fragment(
+            r#"<h1>A &amp; B</h1><p><img src="http://img/1.png"></p><p><img src="http://img/2.webp"></p>"#,
+        );
+        let mut images = HashMap::new();
+        images.insert(
+            "http://img/1.png".to_string(),
+            Some("images/1.png".to_string()),
+        );
+        images.insert("http://img/2.webp".to_string(), None);
+        let mut out = String::new();
+        for node in fragment.root_element().children() {
+            xhtml(node, &images, &mut out);
+        }
+        assert_eq!(
+            r#"<h1>A &amp; B</h1><p><img src="../images/1.png" alt="" /></p><p></p>"#,
+            out
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::api::ArticleJson;
+use crate::storage::{Article, ArticleFilter, ArticleStatus, Storage};
+use anyhow::{Context, Result};
+use futures::stream::{self, LocalBoxStream, StreamExt};
+use handlebars::html_escape;
+use std::sync::Arc;
+
+/// Articles are read from the storage by pages of this size while the export is streamed.
+const PAGE_SIZE: i64 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    /// Columns are `url,title,status,created_at,tags,starred`, tags are separated by `|`.
+    Csv,
+    Netscape,
+    Opml,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Option<ExportFormat> {
+        match format {
+            "json" => Some(ExportFormat::Json),
+            "csv" => Some(ExportFormat::Csv),
+            "html" | "netscape" => Some(ExportFormat::Netscape),
+            "opml" => Some(ExportFormat::Opml),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Netscape => "text/html; charset=utf-8",
+            ExportFormat::Opml => "text/x-opml; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Netscape => "html",
+            ExportFormat::Opml => "opml",
+        }
+    }
+
+    fn header(&self) -> String {
+        match self {
+            ExportFormat::Json => "[".to_string(),
+            ExportFormat::Csv => "url,title,status,created_at,tags,starred\n".to_string(),
+            ExportFormat::Netscape => "<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks</H1>
+<DL><p>
+"
+            .to_string(),
+            ExportFormat::Opml => "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<opml version=\"2.0\">
+<head><title>Save to read</title></head>
+<body>
+"
+            .to_string(),
+        }
+    }
+
+    fn footer(&self) -> String {
+        match self {
+            ExportFormat::Json => "]\n".to_string(),
+            ExportFormat::Csv => String::new(),
+            ExportFormat::Netscape => "</DL><p>\n".to_string(),
+            ExportFormat::Opml => "</body>\n</opml>\n".to_string(),
+        }
+    }
+}
+
+fn title(article: &Article) -> String {
+    article
+        .data
+        .title
+        .clone()
+        .unwrap_or_else(|| article.data.url.to_string())
+}
+
+/// Renders a page of articles, `first` is set for the first page of the export.
+pub fn render_page(format: ExportFormat, articles: Vec<Article>, first: bool) -> Result<String> {
+    let mut out = String::new();
+    match format {
+        ExportFormat::Json => {
+            for (i, article) in articles.into_iter().enumerate() {
+                if !(first && i == 0) {
+                    out.push(',');
+                }
+                out.push('\n');
+                out.push_str(&serde_json::to_string(&ArticleJson::from(article))?);
+            }
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(vec![]);
+            for article in articles {
+                writer.write_record([
+                    article.data.url.as_str(),
+                    article.data.title.as_deref().unwrap_or_default(),
+                    article.status.as_str(),
+                    &article
+                        .created_at
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                    &article.tags.join("|"),
+                    if article.starred { "1" } else { "0" },
+                ])?;
+            }
+            let bytes = writer.into_inner().context("Can't write csv")?;
+            out.push_str(&String::from_utf8(bytes)?);
+        }
+        // Unread links are marked with TOREAD as Pinboard does.
+        ExportFormat::Netscape => {
+            for article in articles {
+                out.push_str(&format!(
+                    "<DT><A HREF=\"{}\"",
+                    html_escape(article.data.url.as_str())
+                ));
+                if let Some(created_at) = article.created_at {
+                    out.push_str(&format!(" ADD_DATE=\"{}\"", created_at));
+                }
+                out.push_str(&format!(
+                    " TAGS=\"{}\"",
+                    html_escape(&article.tags.join(","))
+                ));
+                if article.status == ArticleStatus::Pending {
+                    out.push_str(" TOREAD=\"1\"");
+                }
+                out.push_str(&format!(">{}</A>\n", html_escape(&title(&article))));
+            }
+        }
+        ExportFormat::Opml => {
+            for article in articles {
+                out.push_str(&format!(
+                    "<outline type=\"link\" text=\"{}\" url=\"{}\" status=\"{}\"",
+                    html_escape(&title(&article)),
+                    html_escape(article.data.url.as_str()),
+                    article.status.as_str()
+                ));
+                if let Some(created_at) = article.created_at {
+                    out.push_str(&format!(
+                        " created=\"{}\"",
+                        time::OffsetDateTime::from_unix_timestamp(created_at)
+                            .format("%a, %d %b %Y %H:%M:%S +0000")
+                    ));
+                }
+                if !article.tags.is_empty() {
+                    out.push_str(&format!(
+                        " category=\"{}\"",
+                        html_escape(&article.tags.join(","))
+                    ));
+                }
+                if article.starred {
+                    out.push_str(" starred=\"true\"");
+                }
+                out.push_str("/>\n");
+            }
+        }
+    }
+    Ok(out)
+}
+
+enum Page {
+    Header,
+    Articles(Option<i64>),
+    Done,
+}
+
+/// Streams all articles of the user, newest first, reading them from the storage page by page.
+pub fn export(
+    storage: Arc<Storage>,
+    user_id: i64,
+    format: ExportFormat,
+) -> LocalBoxStream<'static, Result<String>> {
+    stream::unfold(Page::Header, move |page| {
+        let storage = storage.clone();
+        async move {
+            match page {
+                Page::Header => Some((Ok(format.header()), Page::Articles(None))),
+                Page::Articles(before) => {
+                    let filter = ArticleFilter {
+                        before,
+                        limit: PAGE_SIZE,
+                        ..ArticleFilter::default()
+                    };
+                    match storage.articles(&user_id, &filter).await {
+                        Ok(articles) if articles.is_empty() => {
+                            Some((Ok(format.footer()), Page::Done))
+                        }
+                        Ok(articles) => {
+                            let last = articles.last().map(|a| a.id);
+                            let chunk = render_page(format, articles, before.is_none());
+                            Some((chunk, Page::Articles(last)))
+                        }
+                        Err(e) => Some((Err(e), Page::Done)),
+                    }
+                }
+                Page::Done => None,
+            }
+        }
+    })
+    .boxed_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::parse;
+
+    fn articles() -> Vec<Article> {
+        let mut first =
+            Article::for_test(2, "https://example.com/a?x=1&y=2", ArticleStatus::Pending);
+        first.created_at = Some(1600000000);
+        first.starred = true;
+        first.tags = vec!["rust".to_string(), "web".to_string()];
+        first.data.title = Some("Title, with \"quotes\" & <tags>".to_string());
+        let mut second = Article::for_test(1, "https://example.com/b", ArticleStatus::Archived);
+        second.created_at = Some(1500000000);
+        vec![first, second]
+    }
+
+    fn round_trip(format: ExportFormat) {
+        let data = format!(
+            "{}{}{}",
+            format.header(),
+            render_page(format, articles(), true).unwrap(),
+            format.footer()
+        );
+        let (links, invalid) = parse(&data).unwrap();
+        assert_eq!(invalid, 0);
+        assert_eq!(links.len(), 2);
+        for (link, article) in links.iter().zip(articles()) {
+            assert_eq!(link.url, article.data.url);
+            assert_eq!(link.title, article.data.title);
+            assert_eq!(link.status, article.status);
+            assert_eq!(link.created_at, article.created_at);
+            assert_eq!(link.tags, article.tags);
+            if format == ExportFormat::Csv {
+                assert_eq!(link.starred, article.starred);
+            }
+        }
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        round_trip(ExportFormat::Csv);
+    }
+
+    #[test]
+    fn test_netscape_round_trip() {
+        round_trip(ExportFormat::Netscape);
+    }
+
+    #[test]
+    fn test_json() {
+        let data = format!(
+            "{}{}{}",
+            ExportFormat::Json.header(),
+            render_page(ExportFormat::Json, articles(), true).unwrap(),
+            ExportFormat::Json.footer()
+        );
+        let json: Vec<ArticleJson> = serde_json::from_str(&data).unwrap();
+        assert_eq!(json.len(), 2);
+        assert_eq!(json[0].tags, vec!["rust", "web"]);
+        assert_eq!(json[1].status, "archived");
+        assert!(json[0].starred);
+    }
+}
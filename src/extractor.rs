@@ -1,9 +1,11 @@
 use std::time::Duration;
 
+use actix_web::client::{Client, ClientResponse};
 use actix_web::http;
-use actix_web::{client::Client, web::Bytes};
+use actix_web::{error::PayloadError, web::Bytes};
 use anyhow::{anyhow, Result};
 use encoding_rs::*;
+use futures::Stream;
 use lazy_static::*;
 use regex::*;
 use scraper::{Html, Selector};
@@ -51,35 +53,249 @@ fn charset(doc: &Html) -> Result<Option<&'static Encoding>> {
         .next())
 }
 
-pub async fn extract(url: &url::Url) -> Result<Option<String>> {
+/// Data extracted from the page of the saved link.
+#[derive(Clone, Debug, Default)]
+pub struct Extracted {
+    pub title: Option<String>,
+    /// Readable part of the page as simplified html, see `content`.
+    pub content: Option<String>,
+}
+
+/// Parses the page decoded with the charset of its meta tags, utf-8 if there is none.
+pub fn parse_page(data: &[u8]) -> Html {
+    let html_str = String::from_utf8_lossy(data);
+    let html = Html::parse_document(&html_str);
+    if let Ok(Some(encoding)) = charset(&html) {
+        let (decoded, _, _) = encoding.decode(data);
+        let data = match decoded {
+            Borrowed(b) => b.to_string(),
+            Owned(o) => o,
+        };
+        Html::parse_document(&data)
+    } else {
+        html
+    }
+}
+
+pub async fn extract(url: &url::Url) -> Result<Extracted> {
     let client = Client::builder().timeout(Duration::from_secs(60)).finish();
     if let Some(data) = ignore_redirects(&client, url.as_str(), 10).await? {
-        let resp: Vec<u8> = data.to_vec();
-        let html_str = String::from_utf8_lossy(&resp);
-        let html = Html::parse_document(&html_str);
-        if let Ok(Some(encoding)) = charset(&html) {
-            let (decoded, _, _) = encoding.decode(&resp);
-            let data = match decoded {
-                Borrowed(b) => b.to_string(),
-                Owned(o) => o,
-            };
-            Ok(title(&Html::parse_document(&data))?)
+        let doc = parse_page(&data);
+        Ok(Extracted {
+            title: title(&doc)?,
+            content: content(&doc, url)?,
+        })
+    } else {
+        Ok(Extracted::default())
+    }
+}
+
+const BLOCKS: &[&str] = &[
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "p",
+    "pre",
+    "blockquote",
+    "li",
+    "img",
+];
+const SKIPPED: &[&str] = &[
+    "nav", "header", "footer", "aside", "form", "script", "style", "noscript",
+];
+
+/// Readable part of the page: text blocks and images of the `article`, `main` or `body`
+/// element in document order, without any attributes except absolute image sources.
+fn content(doc: &Html, base: &url::Url) -> Result<Option<String>> {
+    let container_selector = Selector::parse("article, main, body")
+        .map_err(|err| anyhow!("Can't parse selector for content container {:?}", err))?;
+    let blocks_selector = Selector::parse(&BLOCKS.join(", "))
+        .map_err(|err| anyhow!("Can't parse selector for content blocks {:?}", err))?;
+    let container = match ["article", "main", "body"].iter().find_map(|name| {
+        doc.select(&container_selector)
+            .find(|el| el.value().name() == *name)
+    }) {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    let mut result = String::new();
+    for el in container.select(&blocks_selector) {
+        let nested = el
+            .ancestors()
+            .take_while(|a| a.id() != container.id())
+            .filter_map(|a| a.value().as_element().map(|e| e.name()))
+            .any(|name| BLOCKS.contains(&name) || SKIPPED.contains(&name));
+        if nested {
+            continue;
+        }
+        let name = el.value().name();
+        if name == "img" {
+            if let Some(src) = el.value().attr("src").and_then(|s| base.join(s).ok()) {
+                if src.scheme() == "http" || src.scheme() == "https" {
+                    result.push_str(&format!(
+                        r#"<p><img src="{}"></p>"#,
+                        handlebars::html_escape(src.as_str())
+                    ));
+                }
+            }
         } else {
-            Ok((title(&html))?)
+            let text = el.text().collect::<String>();
+            let text = if name == "pre" {
+                text
+            } else {
+                text.split_whitespace().collect::<Vec<&str>>().join(" ")
+            };
+            if !text.trim().is_empty() {
+                let text = handlebars::html_escape(&text);
+                result.push_str(&match name {
+                    "li" => format!("<ul><li>{}</li></ul>", text),
+                    _ => format!("<{0}>{1}</{0}>", name, text),
+                });
+            }
+        }
+    }
+    Ok(Some(result).filter(|r| !r.is_empty()))
+}
+
+/// Html given by a client reduced like the content of a fetched page, so it's safe to show.
+pub fn readable(html: &str, base: &url::Url) -> Result<Option<String>> {
+    content(&Html::parse_document(html), base)
+}
+
+/// Plain text beginning of the extracted content, cut at a word boundary.
+pub fn summary(content: &str, max_chars: usize) -> String {
+    let fragment = Html::parse_fragment(content);
+    let paragraph_selector = Selector::parse("p").unwrap();
+    let text = fragment
+        .select(&paragraph_selector)
+        .flat_map(|p| p.text())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let words = text.split_whitespace();
+    let mut result = String::new();
+    for word in words {
+        if result.chars().count() + word.chars().count() + 1 > max_chars {
+            result.push('…');
+            break;
+        }
+        if !result.is_empty() {
+            result.push(' ');
+        }
+        result.push_str(word);
+    }
+    result
+}
+
+/// Average silent reading speed used for the estimates.
+const WORDS_PER_MINUTE: i64 = 230;
+
+/// Number of words in the text of the extracted content.
+pub fn word_count(content: &str) -> i64 {
+    let fragment = Html::parse_fragment(content);
+    fragment
+        .root_element()
+        .text()
+        .map(|t| t.split_whitespace().count() as i64)
+        .sum()
+}
+
+/// Estimated minutes to read the words, at least a minute for any text.
+pub fn reading_minutes(words: i64) -> i64 {
+    (words + WORDS_PER_MINUTE - 1) / WORDS_PER_MINUTE
+}
+
+/// Result of a request to a saved link, see `check_link`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkCheck {
+    /// Status of the last response, none if the server could not be reached.
+    pub status: Option<u16>,
+    /// Url of the last response after the redirects.
+    pub final_url: String,
+}
+
+/// Requests the link with HEAD, then with GET for servers which don't answer HEAD properly.
+pub async fn check_link(url: &url::Url) -> LinkCheck {
+    let client = Client::builder().timeout(Duration::from_secs(30)).finish();
+    let mut check = LinkCheck {
+        status: None,
+        final_url: url.to_string(),
+    };
+    for method in &[http::Method::HEAD, http::Method::GET] {
+        if let Ok((resp, final_url)) =
+            follow_redirects(&client, method.clone(), url.as_str(), 10).await
+        {
+            check = LinkCheck {
+                status: Some(resp.status().as_u16()),
+                final_url,
+            };
+            if resp.status().is_success() {
+                break;
+            }
         }
+    }
+    check
+}
+
+async fn ignore_redirects(client: &Client, url: &str, max_redirect: i8) -> Result<Option<Bytes>> {
+    let (mut resp, _) = follow_redirects(client, http::Method::GET, url, max_redirect).await?;
+    if resp.status().is_success() {
+        Ok(Some(resp.body().limit(usize::MAX).await?))
     } else {
         Ok(None)
     }
 }
 
-async fn ignore_redirects(client: &Client, url: &str, max_redirect: i8) -> Result<Option<Bytes>> {
-    let mut resp = client.get(url).send().await.map_err(|err| {
-        anyhow!(
-            "Can't send request for data extraction to url {} with error {:?}",
-            url,
-            err
-        )
-    })?;
+/// Body of the url and its content type, none if the response is not a success.
+/// Bodies longer than `limit` bytes are an error.
+pub async fn fetch(
+    client: &Client,
+    url: &str,
+    limit: usize,
+) -> Result<Option<(Bytes, Option<String>)>> {
+    let (mut resp, _) = follow_redirects(client, http::Method::GET, url, 10).await?;
+    if !resp.status().is_success() {
+        return Ok(None);
+    }
+    let content_type = resp
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .map(|t| t.to_string());
+    let body = resp
+        .body()
+        .limit(limit)
+        .await
+        .map_err(|err| anyhow!("Can't read the body of {} with error {:?}", url, err))?;
+    Ok(Some((body, content_type)))
+}
+
+/// Sends the request following up to `max_redirect` redirects,
+/// returns the last response with its url.
+async fn follow_redirects(
+    client: &Client,
+    method: http::Method,
+    url: &str,
+    max_redirect: i8,
+) -> Result<(
+    ClientResponse<impl Stream<Item = Result<Bytes, PayloadError>> + Unpin>,
+    String,
+)> {
+    let mut current = url.to_string();
+    let mut resp = client
+        .request(method.clone(), &current)
+        .send()
+        .await
+        .map_err(|err| {
+            anyhow!(
+                "Can't send request for data extraction to url {} with error {:?}",
+                url,
+                err
+            )
+        })?;
     let mut redirects = max_redirect;
     while resp.status().is_redirection() && redirects > 0 {
         let location = resp.headers().get_all(http::header::LOCATION).last();
@@ -91,23 +307,28 @@ async fn ignore_redirects(client: &Client, url: &str, max_redirect: i8) -> Resul
                     err
                 )
             })?;
-            resp = client.get(str_loc).send().await.map_err(|err| {
-                anyhow!(
-                    "Can't send request for data extraction to url {} with error {:?}",
-                    url,
-                    err
-                )
-            })?;
+            // Locations may be relative to the url of the redirect.
+            current = url::Url::parse(&current)
+                .and_then(|base| base.join(str_loc))
+                .map(|next| next.to_string())
+                .unwrap_or_else(|_| str_loc.to_string());
+            resp = client
+                .request(method.clone(), &current)
+                .send()
+                .await
+                .map_err(|err| {
+                    anyhow!(
+                        "Can't send request for data extraction to url {} with error {:?}",
+                        url,
+                        err
+                    )
+                })?;
         } else {
             break;
         }
         redirects = redirects - 1;
     }
-    if resp.status().is_success() {
-        Ok(Some(resp.body().limit(usize::MAX).await?))
-    } else {
-        Ok(None)
-    }
+    Ok((resp, current))
 }
 
 #[actix_rt::test]
@@ -136,3 +357,60 @@ async fn test_charset_content() {
     );
     assert_eq!(encoding_rs::KOI8_R, charset(&html).unwrap().unwrap());
 }
+
+#[actix_rt::test]
+async fn test_content() {
+    let html = Html::parse_document(
+        r#"
+        <html>
+        <body>
+        <nav><p>Menu</p></nav>
+        <article>
+            <h1>Header</h1>
+            <p>First   <b>paragraph</b> &amp; more</p>
+            <ul><li><p>Item</p></li></ul>
+            <img src="/image.png">
+            <footer><p>Footer</p></footer>
+        </article>
+        </body>
+        </html>
+        "#,
+    );
+    let base = url::Url::parse("http://example.com/post").unwrap();
+    assert_eq!(
+        r#"<h1>Header</h1><p>First paragraph &amp; more</p><ul><li>Item</li></ul><p><img src="http://example.com/image.png"></p>"#,
+        content(&html, &base).unwrap().unwrap()
+    );
+}
+
+#[test]
+fn test_readable() {
+    let base = url::Url::parse("http://example.com/post").unwrap();
+    assert_eq!(
+        Some(r#"<p>Text &lt;b&gt;</p><p><img src="http://example.com/a.png"></p>"#.to_string()),
+        readable(
+            r#"<script>alert(1)</script><p onclick="alert(2)">Text <a href="javascript:alert(3)">&lt;b&gt;</a></p>
+            <img src="javascript:alert(4)"><img src="/a.png" onerror="alert(5)"><iframe src="http://evil"></iframe>"#,
+            &base
+        )
+        .unwrap()
+    );
+    assert_eq!(None, readable("<script>alert(1)</script>", &base).unwrap());
+}
+
+#[test]
+fn test_summary() {
+    let content = "<h1>Header</h1><p>First paragraph &amp; more</p><p>Second  one</p>";
+    assert_eq!("First paragraph & more Second one", summary(content, 100));
+    assert_eq!("First paragraph…", summary(content, 16));
+}
+
+#[test]
+fn test_word_count() {
+    let content = "<h1>Header</h1><p>First <b>paragraph</b> &amp; more</p><ul><li>Item</li></ul>";
+    assert_eq!(6, word_count(content));
+    assert_eq!(0, reading_minutes(0));
+    assert_eq!(1, reading_minutes(6));
+    assert_eq!(1, reading_minutes(230));
+    assert_eq!(2, reading_minutes(231));
+}
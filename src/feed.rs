@@ -0,0 +1,136 @@
+use crate::extractor::summary;
+use crate::routes::AppState;
+use crate::storage::{Article, ArticleFilter, ArticleStatus};
+use actix_web::http::header::{
+    ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::*;
+use handlebars::html_escape;
+
+const FEED_SIZE: i64 = 50;
+const SUMMARY_LENGTH: usize = 300;
+
+fn date(ts: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(ts).format("%Y-%m-%dT%H:%M:%SZ")
+}
+
+/// Time of the last change of the article shown in the feed.
+fn updated(article: &Article) -> i64 {
+    article.updated_at.or(article.created_at).unwrap_or(0)
+}
+
+/// Atom feed of articles with their summaries, `self_url` is also the id of the feed.
+pub fn atom(title: &str, self_url: &str, articles: &[(Article, Option<String>)]) -> String {
+    let feed_updated = articles.iter().map(|(a, _)| updated(a)).max().unwrap_or(0);
+    let mut xml = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{}</title>
+<id>{}</id>
+<link rel="self" href="{}"/>
+<updated>{}</updated>
+<author><name>Save to read</name></author>
+"#,
+        html_escape(title),
+        html_escape(self_url),
+        html_escape(self_url),
+        date(feed_updated)
+    );
+    for (article, summary) in articles {
+        let url = article.data.url.as_str();
+        xml.push_str(&format!(
+            "<entry>\n<title>{}</title>\n<link href=\"{}\"/>\n<id>{}#{}</id>\n",
+            html_escape(article.data.title.as_deref().unwrap_or(url)),
+            html_escape(url),
+            html_escape(self_url),
+            article.id
+        ));
+        if let Some(created_at) = article.created_at {
+            xml.push_str(&format!("<published>{}</published>\n", date(created_at)));
+        }
+        xml.push_str(&format!("<updated>{}</updated>\n", date(updated(article))));
+        if let Some(summary) = summary.as_ref().filter(|s| !s.is_empty()) {
+            xml.push_str(&format!("<summary>{}</summary>\n", html_escape(summary)));
+        }
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// True when the client already has the feed with the tag or the modification time.
+/// Modification time is checked only without `If-None-Match`, deletions don't change it.
+fn not_modified(req: &HttpRequest, etag: &EntityTag, last_modified: HttpDate) -> bool {
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(etag)),
+        None => match req.get_header::<IfModifiedSince>() {
+            Some(IfModifiedSince(since)) => last_modified <= since,
+            None => false,
+        },
+    }
+}
+
+/// Feeds of pending and archived articles, authenticated by the feed token of the user
+/// from the settings, as feed readers can't log in.
+#[get("/feed/{token}/{status}.atom")]
+pub async fn feed(
+    web::Path((token, status)): web::Path<(String, String)>,
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let storage = &data.storage;
+    let status = match ArticleStatus::parse(&status) {
+        Some(status) => status,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let user_id = match storage
+        .feed_token_user(&token)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(user_id) => user_id,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let filter = ArticleFilter {
+        status: Some(status),
+        limit: FEED_SIZE,
+        ..ArticleFilter::default()
+    };
+    let mut articles = vec![];
+    for article in storage
+        .articles(&user_id, &filter)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        let content = storage
+            .get_content(&user_id, &article.id)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        articles.push((article, content.map(|c| summary(&c, SUMMARY_LENGTH))));
+    }
+
+    let info = req.connection_info();
+    let self_url = format!("{}://{}{}", info.scheme(), info.host(), req.path());
+    let title = format!("Save to read: {}", status.as_str());
+    let body = atom(&title, &self_url, &articles);
+    let hash: String = openssl::sha::sha256(body.as_bytes())
+        .iter()
+        .take(16)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let etag = EntityTag::strong(hash);
+    let last_modified = articles.iter().map(|(a, _)| updated(a)).max().unwrap_or(0);
+    let last_modified = HttpDate::from(time::OffsetDateTime::from_unix_timestamp(last_modified));
+    if not_modified(&req, &etag, last_modified) {
+        return Ok(HttpResponse::NotModified()
+            .set(ETag(etag))
+            .set(LastModified(last_modified))
+            .finish());
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .set(ETag(etag))
+        .set(LastModified(last_modified))
+        .body(body))
+}
@@ -0,0 +1,317 @@
+use crate::routes::http_url;
+use crate::storage::{now, ArticleData, ArticleStatus, Storage};
+use anyhow::{bail, Context, Result};
+use scraper::{Html, Selector};
+use url::Url;
+
+/// Link read from an export file of another service.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedLink {
+    pub url: Url,
+    pub title: Option<String>,
+    pub status: ArticleStatus,
+    /// Unix time in seconds when the link was saved in the original service.
+    pub created_at: Option<i64>,
+    pub tags: Vec<String>,
+    pub starred: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Links which are already saved by the user or repeated in the file.
+    pub duplicates: usize,
+    /// Entries without a http(s) url, like bookmarklets or browser internal pages.
+    pub invalid: usize,
+}
+
+/// Parses any of the supported exports, the format is detected by the content:
+/// - Pocket HTML export and Netscape bookmark files (Pinboard, browsers, etc.);
+/// - Pocket CSV export with `title,url,time_added,tags,status` columns;
+/// - Instapaper CSV export with `URL,Title,Selection,Folder,Timestamp` columns,
+///   links in its Starred folder are starred;
+/// - CSV and bookmark files of our own export.
+///
+/// Returns parsed links and the number of entries with invalid urls.
+pub fn parse(data: &str) -> Result<(Vec<ImportedLink>, usize)> {
+    let data = data.trim_start_matches('\u{feff}').trim_start();
+    if data.starts_with('<') {
+        Ok(parse_html(data))
+    } else {
+        parse_csv(data)
+    }
+}
+
+/// Services put the url as a title when they don't know it.
+fn title(title: &str, url: &Url) -> Option<String> {
+    let title = title.trim();
+    if title.is_empty() || title == url.as_str() || Url::parse(title).ok().as_ref() == Some(url) {
+        None
+    } else {
+        Some(title.to_string())
+    }
+}
+
+fn timestamp(value: &str) -> Option<i64> {
+    value.trim().parse::<i64>().ok().filter(|t| *t > 0)
+}
+
+fn tags(value: &str, separators: &[char]) -> Vec<String> {
+    value
+        .split(separators)
+        .map(|t| t.trim_matches(|c: char| c.is_whitespace() || "[]\"".contains(c)))
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect()
+}
+
+/// Pocket puts links under "Unread" and "Read Archive" headers, Pinboard marks unread
+/// links with TOREAD attribute, other bookmark files have no read state, so their links
+/// are pending.
+fn parse_html(data: &str) -> (Vec<ImportedLink>, usize) {
+    let document = Html::parse_document(data);
+    let selector = Selector::parse("h1, a").unwrap();
+    let has_toread = document
+        .select(&Selector::parse("a[toread]").unwrap())
+        .next()
+        .is_some();
+    let mut status = ArticleStatus::Pending;
+    let mut links = vec![];
+    let mut invalid = 0;
+    for element in document.select(&selector) {
+        let text = element.text().collect::<String>();
+        if element.value().name() == "h1" {
+            status = if text.trim().eq_ignore_ascii_case("read archive") {
+                ArticleStatus::Archived
+            } else {
+                ArticleStatus::Pending
+            };
+            continue;
+        }
+        let attr = |name| element.value().attr(name).unwrap_or_default();
+        match http_url(attr("href")) {
+            Some(url) => links.push(ImportedLink {
+                title: title(&text, &url),
+                status: match attr("toread") {
+                    _ if !has_toread => status,
+                    "1" => ArticleStatus::Pending,
+                    _ => ArticleStatus::Archived,
+                },
+                created_at: timestamp(attr("time_added")).or_else(|| timestamp(attr("add_date"))),
+                tags: tags(attr("tags"), &[',']),
+                starred: false,
+                url,
+            }),
+            None => invalid += 1,
+        }
+    }
+    (links, invalid)
+}
+
+fn parse_csv(data: &str) -> Result<(Vec<ImportedLink>, usize)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(data.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .context("Can't read csv header")?
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let url_column = match column("url") {
+        Some(c) => c,
+        None => bail!("Unknown file format, expected a bookmarks html or a csv with url column"),
+    };
+    let title_column = column("title");
+    let time_column = column("time_added")
+        .or_else(|| column("timestamp"))
+        .or_else(|| column("created_at"));
+    let tags_column = column("tags");
+    // Pocket has unread/archive status, our own export has pending/archived,
+    // Instapaper has Unread/Archive/Starred or custom folders.
+    let status_column = column("status");
+    let folder_column = column("folder");
+    let starred_column = column("starred");
+
+    let mut links = vec![];
+    let mut invalid = 0;
+    for record in reader.records() {
+        let record = record.context("Can't read csv record")?;
+        let field = |column: Option<usize>| column.and_then(|c| record.get(c)).unwrap_or_default();
+        let url = match http_url(field(Some(url_column))) {
+            Some(url) => url,
+            None => {
+                invalid += 1;
+                continue;
+            }
+        };
+        let mut link_tags = tags(field(tags_column), &['|', ',']);
+        let status = field(status_column).trim().to_lowercase();
+        let folder = field(folder_column).trim();
+        let starred = folder.eq_ignore_ascii_case("starred")
+            || matches!(field(starred_column).trim(), "1" | "true");
+        let status = if status == "archive"
+            || status == "archived"
+            || folder.eq_ignore_ascii_case("archive")
+        {
+            ArticleStatus::Archived
+        } else {
+            if !folder.is_empty()
+                && !folder.eq_ignore_ascii_case("unread")
+                && !folder.eq_ignore_ascii_case("starred")
+            {
+                link_tags.push(folder.to_string());
+            }
+            ArticleStatus::Pending
+        };
+        links.push(ImportedLink {
+            title: title(field(title_column), &url),
+            status,
+            created_at: timestamp(field(time_column)),
+            tags: link_tags,
+            starred,
+            url,
+        });
+    }
+    Ok((links, invalid))
+}
+
+/// Saves parsed links for the user, skipping urls which are already saved.
+pub async fn import(storage: &Storage, user_id: i64, data: &str) -> Result<ImportReport> {
+    let (mut links, invalid) = parse(data)?;
+    // Lists are ordered by id, so older links are saved first to keep their order.
+    // Exports list the newest links first, that order is kept for links saved at once.
+    links.reverse();
+    links.sort_by_key(|l| l.created_at.unwrap_or(i64::MAX));
+    let mut report = ImportReport {
+        invalid,
+        ..ImportReport::default()
+    };
+    for link in links {
+        if storage.find_by_url(&user_id, &link.url).await?.is_some() {
+            report.duplicates += 1;
+            continue;
+        }
+        let id = storage
+            .add_at(
+                ArticleData {
+                    user_id,
+                    url: link.url,
+                    title: link.title,
+                },
+                link.created_at.unwrap_or_else(now),
+            )
+            .await?;
+        if link.status == ArticleStatus::Archived {
+            storage.archive(&user_id, &id).await?;
+        }
+        if !link.tags.is_empty() {
+            storage.set_tags(&user_id, &id, &link.tags).await?;
+        }
+        if link.starred {
+            storage.set_starred(&user_id, &id, true).await?;
+        }
+        report.imported += 1;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pocket_html() {
+        let data = r#"<!DOCTYPE html>
+<html><head><title>Pocket Export</title></head><body>
+<h1>Unread</h1>
+<ul>
+<li><a href="https://example.com/a" time_added="1600000000" tags="rust,web">Article A</a></li>
+<li><a href="https://example.com/b" time_added="1600000100" tags="">https://example.com/b</a></li>
+</ul>
+<h1>Read Archive</h1>
+<ul>
+<li><a href="https://example.com/c" time_added="1500000000" tags="old">Article C</a></li>
+</ul>
+</body></html>"#;
+        let (links, invalid) = parse(data).unwrap();
+        assert_eq!(invalid, 0);
+        assert_eq!(links.len(), 3);
+        assert_eq!(links[0].title.as_deref(), Some("Article A"));
+        assert_eq!(links[0].status, ArticleStatus::Pending);
+        assert_eq!(links[0].created_at, Some(1600000000));
+        assert_eq!(links[0].tags, vec!["rust", "web"]);
+        assert_eq!(links[1].title, None);
+        assert!(links[1].tags.is_empty());
+        assert_eq!(links[2].status, ArticleStatus::Archived);
+        assert_eq!(links[2].created_at, Some(1500000000));
+    }
+
+    #[test]
+    fn test_netscape_bookmarks() {
+        let data = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks</H1>
+<DL><p>
+    <DT><H3 ADD_DATE="1600000000">Folder</H3>
+    <DL><p>
+        <DT><A HREF="https://example.com/a" ADD_DATE="1610000000" TAGS="one,two">Article A</A>
+        <DT><A HREF="javascript:alert(1)">Bookmarklet</A>
+    </DL><p>
+    <DT><A HREF="http://example.com/b">Article B</A>
+</DL><p>"#;
+        let (links, invalid) = parse(data).unwrap();
+        assert_eq!(invalid, 1);
+        assert_eq!(links.len(), 2);
+        assert!(links.iter().all(|l| l.status == ArticleStatus::Pending));
+        assert_eq!(links[0].created_at, Some(1610000000));
+        assert_eq!(links[0].tags, vec!["one", "two"]);
+        assert_eq!(links[1].url.as_str(), "http://example.com/b");
+        assert_eq!(links[1].created_at, None);
+    }
+
+    #[test]
+    fn test_pocket_csv() {
+        let data = "title,url,time_added,tags,status
+Article A,https://example.com/a,1600000000,rust|web,unread
+\"Title, with comma\",https://example.com/b,1600000100,,archive
+";
+        let (links, invalid) = parse(data).unwrap();
+        assert_eq!(invalid, 0);
+        assert_eq!(links[0].status, ArticleStatus::Pending);
+        assert_eq!(links[0].tags, vec!["rust", "web"]);
+        assert_eq!(links[1].title.as_deref(), Some("Title, with comma"));
+        assert_eq!(links[1].status, ArticleStatus::Archived);
+        assert_eq!(links[1].created_at, Some(1600000100));
+    }
+
+    #[test]
+    fn test_instapaper_csv() {
+        let data = "URL,Title,Selection,Folder,Timestamp
+https://example.com/a,Article A,,Unread,1600000000
+https://example.com/b,Article B,some text,Archive,1600000100
+https://example.com/c,Article C,,Recipes,1600000200
+https://example.com/d,Article D,,Starred,1600000250
+not a url,Broken,,Unread,1600000300
+";
+        let (links, invalid) = parse(data).unwrap();
+        assert_eq!(invalid, 1);
+        assert_eq!(links.len(), 4);
+        assert_eq!(links[0].status, ArticleStatus::Pending);
+        assert!(links[0].tags.is_empty());
+        assert!(!links[0].starred);
+        assert_eq!(links[1].status, ArticleStatus::Archived);
+        assert_eq!(links[2].status, ArticleStatus::Pending);
+        assert_eq!(links[2].tags, vec!["Recipes"]);
+        assert_eq!(links[2].created_at, Some(1600000200));
+        assert!(links[3].starred);
+        assert!(links[3].tags.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        assert!(parse("name,link\nfoo,https://example.com").is_err());
+    }
+}
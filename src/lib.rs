@@ -1,16 +1,29 @@
+pub mod api;
 pub mod auth;
+pub mod collections;
+pub mod epub;
+pub mod export;
 pub mod extractor;
+pub mod feed;
+pub mod import;
+pub mod openapi;
+pub mod pocket;
+pub mod reader;
 pub mod routes;
+pub mod share;
+pub mod snapshot;
 pub mod storage;
+pub mod teams;
 pub mod telegram_api;
+pub mod wallabag;
 
 use actix_session::*;
 use actix_web::client::*;
 use actix_web::*;
 use anyhow::Result;
 use auth::*;
-use extractor::extract;
-use log::error;
+use extractor::{extract, Extracted};
+use log::{error, info, warn};
 use routes::*;
 use storage::*;
 use telegram_api::*;
@@ -18,6 +31,7 @@ use time::prelude::*;
 use url::Url;
 
 pub fn configure_app(cfg: &mut web::ServiceConfig) {
+    wallabag::configure(cfg);
     cfg.service(
         web::scope("/")
             .wrap(
@@ -27,34 +41,226 @@ pub fn configure_app(cfg: &mut web::ServiceConfig) {
             )
             .service(pending_list)
             .service(archived_list)
+            .service(starred_list)
+            .service(toggle_star)
+            .service(reader::reader_page)
+            .service(reader::save_progress)
+            .service(reader::create_annotation)
+            .service(reader::update_annotation)
+            .service(reader::delete_annotation)
+            .service(reader::highlights_markdown)
+            .service(collections::collections_page)
+            .service(collections::create_collection)
+            .service(collections::collection_page)
+            .service(collections::rename_collection)
+            .service(collections::delete_collection)
+            .service(collections::add_to_collection)
+            .service(collections::remove_from_collection)
+            .service(collections::reorder_collection)
+            .service(collections::publish_collection)
+            .service(collections::unpublish_collection)
+            .service(collections::public_collection)
+            .service(teams::teams_page)
+            .service(teams::create_team)
+            .service(teams::team_page)
+            .service(teams::save_team_link)
+            .service(teams::set_team_member)
+            .service(teams::remove_team_member)
+            .service(share::shares_page)
+            .service(share::create_share)
+            .service(share::revoke_share)
+            .service(share::public_share)
+            .service(snapshot::create_snapshot)
+            .service(snapshot::snapshot_page)
+            .service(epub::epub_download)
             .service(archive)
             .service(unarchive)
             .service(delete_archived)
             .service(delete_pending)
-            .service(auth),
+            .service(undo)
+            .service(bulk)
+            .service(trash)
+            .service(restore)
+            .service(empty_trash)
+            .service(add)
+            .service(save)
+            .service(share)
+            .service(manifest)
+            .service(service_worker)
+            .service(icon)
+            .service(settings)
+            .service(create_token)
+            .service(revoke_token)
+            .service(reset_feed_token)
+            .service(save_reading_settings)
+            .service(feed::feed)
+            .service(import_page)
+            .service(import_upload)
+            .service(export_download)
+            .service(authorize_page)
+            .service(authorize)
+            .service(auth)
+            .service(openapi::spec)
+            .service(api::scope())
+            .service(pocket::scope()),
     );
 }
 
+/// Saves the link to the pending list, extracting the title from the page when it's unknown.
+/// A snapshot of the page is taken too if the user turned them on.
+pub async fn save_link(
+    storage: &Storage,
+    user_id: i64,
+    url: Url,
+    title: Option<String>,
+) -> Result<i64> {
+    let extracted = match title {
+        Some(t) => Extracted {
+            title: Some(t),
+            content: None,
+        },
+        None => extract(&url).await.unwrap_or_else(|e| {
+            error!("{}", e);
+            Extracted::default()
+        }),
+    };
+    let id = storage
+        .add(ArticleData {
+            user_id,
+            url,
+            title: extracted.title,
+        })
+        .await?;
+    if extracted.content.is_some() {
+        storage
+            .set_content(&user_id, &id, extracted.content)
+            .await?;
+    }
+    if storage.snapshots_enabled(&user_id).await? {
+        snapshot::take_snapshot_later(storage.clone(), user_id, id);
+    }
+    Ok(id)
+}
+
+/// Permanently deletes articles which are in the trash for longer than the retention.
+pub async fn purge_loop(storage: &Storage, retention_days: i64) {
+    loop {
+        match storage
+            .purge_trash(now() - retention_days * 24 * 60 * 60)
+            .await
+        {
+            Ok(0) => (),
+            Ok(purged) => info!("Purged {} articles from the trash", purged),
+            Err(e) => error!("{:?}", e),
+        }
+        actix_rt::time::delay_for(std::time::Duration::from_secs(60 * 60)).await;
+    }
+}
+
+/// Links are checked again after that many seconds.
+const LINK_RECHECK_AFTER: i64 = 7 * 24 * 60 * 60;
+/// Links checked on each run of `health_loop`, one after another to go easy on the sites.
+const LINK_CHECK_BATCH: i64 = 50;
+
+/// Re-requests saved links to find those which are dead or moved.
+pub async fn health_loop(storage: &Storage) {
+    loop {
+        match check_links(storage, LINK_CHECK_BATCH).await {
+            Ok(0) => (),
+            Ok(checked) => info!("Checked {} links", checked),
+            Err(e) => error!("{:?}", e),
+        }
+        actix_rt::time::delay_for(std::time::Duration::from_secs(60 * 60)).await;
+    }
+}
+
+/// Checks the links least recently checked, returns how many were checked.
+/// When none of several links answers, the network of the server is more likely down
+/// than all the sites, so nothing is saved and they are checked on the next run.
+pub async fn check_links(storage: &Storage, limit: i64) -> Result<usize> {
+    let links = storage
+        .links_to_check(now() - LINK_RECHECK_AFTER, limit)
+        .await?;
+    let mut checks = vec![];
+    for (id, url) in links.iter() {
+        checks.push((id, url, extractor::check_link(url).await));
+    }
+    if checks.len() > 1 && checks.iter().all(|(_, _, check)| check.status.is_none()) {
+        warn!(
+            "None of {} checked links answered, skipping them",
+            checks.len()
+        );
+        return Ok(0);
+    }
+    for (id, url, check) in checks {
+        match check.status {
+            Some(status) => {
+                let final_url = Some(check.final_url).filter(|u| u != url.as_str());
+                storage
+                    .set_link_health(id, i64::from(status), final_url.as_deref())
+                    .await?
+            }
+            None => storage.set_link_unreachable(id).await?,
+        }
+    }
+    Ok(links.len())
+}
+
 pub async fn update_loop(storage: &Storage, token_storage: &TokenStorage, port: &str) {
     let client = Client::default();
     let api_token = std::env::var("BOT_TOKEN").expect("Provide telegram api token pls");
     let host = std::env::var("SERVER_HOST").expect("Provide server host for generating urls");
     let base_url = format!("http://{}:{}", host, port);
     let telegram_api = telegram_api::TelegramClient::new(api_token, &client);
-    let commands = vec![BotCommand {
-        command: "auth",
-        description: "get auth link for new devices",
-    }];
+    let commands = vec![
+        BotCommand {
+            command: "auth",
+            description: "get auth link for new devices",
+        },
+        BotCommand {
+            command: "token",
+            description: "create personal api token: /token <name>",
+        },
+        BotCommand {
+            command: "note",
+            description: "add a note to the article: /note <id> <text>",
+        },
+        BotCommand {
+            command: "team",
+            description: "save links sent to this group with a mention to a team: /team <id>",
+        },
+        BotCommand {
+            command: "epub",
+            description: "pending articles as a book for e-readers, or some of them: /epub [ids]",
+        },
+        BotCommand {
+            command: "random",
+            description: "random pending article, optionally short: /random 10m",
+        },
+    ];
     telegram_api.set_command(&commands).await.unwrap();
+    let bot_username = telegram_api
+        .get_me()
+        .await
+        .unwrap()
+        .result
+        .username
+        .unwrap_or_default();
     let mut update_id = -1;
     loop {
         match telegram_api.get_updates(update_id + 1).await {
             Ok(updates) => {
                 for update in updates.result {
                     update_id = update.update_id;
-                    if let Err(e) =
-                        process_update(&update, storage, token_storage, &telegram_api, &base_url)
-                            .await
+                    if let Err(e) = process_update(
+                        &update,
+                        storage,
+                        token_storage,
+                        &telegram_api,
+                        &base_url,
+                        &bot_username,
+                    )
+                    .await
                     {
                         error!("{}", e);
                     }
@@ -65,52 +271,326 @@ pub async fn update_loop(storage: &Storage, token_storage: &TokenStorage, port:
     }
 }
 
+/// Handles a message to the bot. In groups links are saved to the list of the sender,
+/// or to the list shared by the group when the bot is mentioned, and login links and
+/// tokens are sent in private chats only.
 pub async fn process_update<'a>(
     update: &Update,
     storage: &Storage,
     token_storage: &TokenStorage,
     telegram_api: &TelegramClient<'a>,
     base_url: &str,
+    bot_username: &str,
 ) -> Result<()> {
-    match update.message.text {
-        Some(ref t) => {
-            if t == "/auth" {
-                let token = generate_token();
-
-                token_storage
-                    .push(update.message.chat.id, token.clone())
-                    .await?;
-                telegram_api
-                    .async_send_message(SendMessage {
-                        chat_id: format!("{}", update.message.chat.id),
-                        text: format!(r#"{}/auth/{}"#, base_url, token),
-                        reply_to_message_id: None,
-                        parse_mode: Some(ParseMode::Markdown),
-                    })
-                    .await?;
-            } else if let Ok(url) = Url::parse(&t) {
-                let title = extract(&url).await.unwrap_or_else(|e| {
-                    error!("{}", e);
+    if let Some(ref callback) = update.callback_query {
+        return process_callback(callback, storage, telegram_api).await;
+    }
+    let message = match update.message {
+        Some(ref message) => message,
+        None => return Ok(()),
+    };
+    let (t, mentioned) = match message
+        .text
+        .as_deref()
+        .and_then(|t| addressed_text(t, bot_username))
+    {
+        Some(text) => text,
+        None => return Ok(()),
+    };
+    let user_id = message.sender_id();
+    let private = message.chat.is_private();
+    if (t == "/auth" || t.starts_with("/token") || t.starts_with("/epub")) && !private {
+        return reply(
+            telegram_api,
+            message,
+            "Send it to me in a private chat, this chat is seen by others".to_string(),
+        )
+        .await;
+    }
+    if t == "/auth" {
+        let token = generate_token();
+
+        token_storage.push(user_id, token.clone()).await?;
+        telegram_api
+            .async_send_message(SendMessage {
+                chat_id: format!("{}", message.chat.id),
+                text: format!(r#"{}/auth/{}"#, base_url, token),
+                reply_to_message_id: None,
+                parse_mode: Some(ParseMode::Markdown),
+                reply_markup: None,
+            })
+            .await?;
+    } else if let Some(name) = t.strip_prefix("/token") {
+        let name = match name.trim() {
+            "" => "telegram",
+            n => n,
+        };
+        let token = issue_api_token(
+            storage,
+            &user_id,
+            name,
+            &[auth::Scope::Read, auth::Scope::Write],
+        )
+        .await?;
+        reply(
+            telegram_api,
+            message,
+            format!("Personal api token {}: {}", name, token),
+        )
+        .await?;
+    } else if let Some(args) = t.strip_prefix("/note") {
+        let text = match parse_note(args) {
+            Some((id, note)) => match storage
+                .add_annotation(&user_id, &id, AnnotationKind::Note, note, None)
+                .await?
+            {
+                Some(_) => format!("Note added to the article {}", id),
+                None => format!("Article {} not found", id),
+            },
+            None => "Usage: /note <id> <text>".to_string(),
+        };
+        reply(telegram_api, message, text).await?;
+    } else if let Some(args) = t.strip_prefix("/random") {
+        let text = match parse_minutes(args) {
+            Some(max_minutes) => {
+                let filter = ArticleFilter {
+                    status: Some(ArticleStatus::Pending),
+                    max_minutes,
+                    ..ArticleFilter::default()
+                };
+                match storage.random_article(&user_id, &filter).await? {
+                    Some(a) => {
+                        let url = a.data.url.to_string();
+                        let minutes = a
+                            .reading_minutes
+                            .map(|m| format!(" ({} min)", m))
+                            .unwrap_or_default();
+                        format!(
+                            "{}{}\n{}",
+                            a.data.title.unwrap_or_else(|| url.clone()),
+                            minutes,
+                            url
+                        )
+                    }
+                    None => "No pending articles to read".to_string(),
+                }
+            }
+            None => "Usage: /random [minutes, e.g. 10m]".to_string(),
+        };
+        reply(telegram_api, message, text).await?;
+    } else if let Some(args) = t.strip_prefix("/epub") {
+        let ids = match args
+            .split_whitespace()
+            .map(|id| id.parse::<i64>())
+            .collect::<std::result::Result<Vec<i64>, _>>()
+        {
+            Ok(ids) => ids,
+            Err(_) => return reply(telegram_api, message, "Usage: /epub [ids]".to_string()).await,
+        };
+        send_epub_later(
+            storage.clone(),
+            telegram_api.token().to_string(),
+            message.clone(),
+            user_id,
+            ids,
+        );
+    } else if let Some(args) = t.strip_prefix("/team") {
+        let text = match args.trim().parse::<i64>() {
+            Ok(_) if private => "Send it in a group chat to save its links to a team".to_string(),
+            Ok(team_id) => match storage.get_team(&user_id, &team_id).await? {
+                Some((team, Role::Owner)) => {
+                    storage.set_team_chat(&team.id, &message.chat.id).await?;
+                    format!(
+                        "Links sent here with a mention of me are saved to the team {}",
+                        team.name
+                    )
+                }
+                Some(_) => "Only owners of the team can do it".to_string(),
+                None => format!("Team {} not found", team_id),
+            },
+            Err(_) => "Usage: /team <id>".to_string(),
+        };
+        reply(telegram_api, message, text).await?;
+    } else if let Some(url) = http_url(&t) {
+        let account_id = if mentioned && !private {
+            match group_account(storage, message).await? {
+                Some(account_id) => account_id,
+                None => {
+                    return reply(
+                        telegram_api,
+                        message,
+                        "Only editors of the team can save links here".to_string(),
+                    )
+                    .await
+                }
+            }
+        } else {
+            user_id
+        };
+        let id = save_link(storage, account_id, url.clone(), None).await?;
+        let title = storage
+            .get_article(&account_id, &id)
+            .await?
+            .and_then(|a| a.data.title)
+            .unwrap_or_else(|| url.to_string());
+        telegram_api
+            .async_send_message(SendMessage {
+                chat_id: format!("{}", message.chat.id),
+                text: format!("Saved: {}", title),
+                reply_to_message_id: if private {
                     None
-                });
-                storage
-                    .add(ArticleData {
-                        user_id: update.message.chat.id,
-                        url,
-                        title,
-                    })
-                    .await?;
-                telegram_api
-                    .async_send_message(SendMessage {
-                        chat_id: format!("{}", update.message.chat.id),
-                        text: format!("{}", update.message.chat.id),
-                        reply_to_message_id: None,
-                        parse_mode: None,
-                    })
-                    .await?;
+                } else {
+                    Some(&message.message_id)
+                },
+                parse_mode: None,
+                reply_markup: Some(InlineKeyboardMarkup {
+                    inline_keyboard: vec![vec![InlineKeyboardButton {
+                        text: "☆ Star".to_string(),
+                        callback_data: format!("/star {}", id),
+                    }]],
+                }),
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Makes the book in the background, fetching its images takes a while
+/// and the bot keeps answering others meanwhile.
+fn send_epub_later(storage: Storage, token: String, message: Message, user_id: i64, ids: Vec<i64>) {
+    actix_rt::spawn(async move {
+        let client = Client::default();
+        let telegram_api = TelegramClient::new(token, &client);
+        if let Err(e) = send_epub(&storage, &telegram_api, &message, &user_id, &ids).await {
+            error!("{:?}", e);
+        }
+    });
+}
+
+async fn send_epub<'a>(
+    storage: &Storage,
+    telegram_api: &TelegramClient<'a>,
+    message: &Message,
+    user_id: &i64,
+    ids: &[i64],
+) -> Result<()> {
+    match epub::articles_epub(storage, user_id, ids).await? {
+        Some(book) => {
+            telegram_api
+                .send_document(SendDocument {
+                    chat_id: format!("{}", message.chat.id),
+                    reply_to_message_id: None,
+                    caption: None,
+                    file_name: format!(
+                        "save2read-{}.epub",
+                        time::OffsetDateTime::now_utc().format("%F")
+                    ),
+                    content_type: "application/epub+zip",
+                    content: book,
+                })
+                .await
+        }
+        None => reply(telegram_api, message, "No articles to read".to_string()).await,
+    }
+}
+
+/// Sends the text to the chat of the message, as a reply to it in groups.
+async fn reply<'a>(
+    telegram_api: &TelegramClient<'a>,
+    message: &Message,
+    text: String,
+) -> Result<()> {
+    telegram_api
+        .async_send_message(SendMessage {
+            chat_id: format!("{}", message.chat.id),
+            text,
+            reply_to_message_id: if message.chat.is_private() {
+                None
+            } else {
+                Some(&message.message_id)
+            },
+            parse_mode: None,
+            reply_markup: None,
+        })
+        .await
+}
+
+/// Account of the list shared by the group chat. It's created on the first link sent
+/// with a mention of the bot and everyone in the group can save to it. A team linked
+/// to the group with `/team` takes links of its editors only. `None` if the sender can't.
+async fn group_account(storage: &Storage, message: &Message) -> Result<Option<i64>> {
+    let sender = message.sender_id();
+    let team = match storage.chat_team(&message.chat.id).await? {
+        Some(team) => team,
+        None => {
+            let name = message.chat.title.as_deref().unwrap_or("Group chat");
+            storage
+                .create_chat_team(&sender, &message.chat.id, name)
+                .await?
+        }
+    };
+    let role = storage.role(&sender, &team.account_id).await?;
+    if role.is_none() && team.account_id == message.chat.id {
+        storage
+            .set_team_member(&team.id, &sender, Role::Editor)
+            .await?;
+        return Ok(Some(team.account_id));
+    }
+    Ok(role.filter(|r| r.can_write()).map(|_| team.account_id))
+}
+
+/// Id of the article and the text of `/note <id> <text>`.
+fn parse_note(args: &str) -> Option<(i64, &str)> {
+    let (id, text) = args.trim().split_once(char::is_whitespace)?;
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    id.parse().ok().map(|id| (id, text))
+}
+
+/// Reading time limit of `/random [10m]`, `Some(None)` when no limit is given.
+fn parse_minutes(args: &str) -> Option<Option<i64>> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Some(None);
+    }
+    let minutes = args.strip_suffix("min").or_else(|| args.strip_suffix('m'));
+    minutes.unwrap_or(args).trim().parse().ok().map(Some)
+}
+
+/// Handles buttons of inline keyboards: `/star <id>` under a saved link toggles its star.
+async fn process_callback<'a>(
+    callback: &CallbackQuery,
+    storage: &Storage,
+    telegram_api: &TelegramClient<'a>,
+) -> Result<()> {
+    let id = callback
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix("/star "))
+        .and_then(|id| id.trim().parse::<i64>().ok());
+    let text = match id {
+        Some(id) => {
+            // Buttons are under links saved to the list of the sender or of a team,
+            // so the user pressing one must be able to change the list.
+            let owner = writable_owner(storage, &callback.from.id, &id).await?;
+            let starred = match owner {
+                Some(owner) => storage.toggle_starred(&owner, &id).await?,
+                None => None,
+            };
+            match starred {
+                Some(true) => "Starred",
+                Some(false) => "Unstarred",
+                None => "Article not found",
             }
         }
-        None => (),
+        None => "Unknown action",
     };
-    Ok(())
+    telegram_api
+        .answer_callback_query(AnswerCallbackQuery {
+            callback_query_id: callback.id.clone(),
+            text: Some(text.to_string()),
+        })
+        .await
 }
@@ -1,31 +1,101 @@
 use actix_web::{web, App, HttpServer};
+use futures::StreamExt;
 use handlebars::Handlebars;
 use openssl_probe::init_ssl_cert_env_vars;
 use routes::*;
-use save2read::auth::TokenStorage;
+use save2read::auth::{OauthCodes, TokenStorage};
 use save2read::*;
 use sqlx::sqlite::SqlitePoolOptions;
+use std::io::Write;
 use std::sync::Arc;
 use storage::Storage;
 
 const TOKEN_TTL: u64 = 120;
+const OAUTH_CODE_TTL: u64 = 600;
+const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+const USAGE: &str = "usage: main [import <user_id> <file> | export <user_id> <format>]";
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
     init_ssl_cert_env_vars();
 
-    let mut handlebars = Handlebars::new();
-    handlebars
-        .register_templates_directory(".html", "./templates")
-        .unwrap();
-    let handlebars_ref = Arc::new(handlebars);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => serve().await,
+        Some("import") => import_command(&args[1..]).await,
+        Some("export") => export_command(&args[1..]).await,
+        Some(command) => Err(invalid_input(format!(
+            "Unknown command {}, {}",
+            command, USAGE
+        ))),
+    }
+}
+
+async fn open_storage() -> Storage {
     let db_path = std::env::var("DB_PATH").expect("Provide database path");
     let db_pool = SqlitePoolOptions::new()
         .connect(&format!("sqlite:{}", &db_path))
         .await
         .unwrap();
-    let storage = Arc::new(Storage::init(db_pool).await.unwrap());
+    Storage::init(db_pool).await.unwrap()
+}
+
+/// `main import <user_id> <file>` imports an export of another service for the user.
+async fn import_command(args: &[String]) -> std::io::Result<()> {
+    let (user_id, path) = match args {
+        [user_id, path] => (parse_user_id(user_id)?, path),
+        _ => return Err(invalid_input(USAGE.to_string())),
+    };
+    let data = std::fs::read_to_string(path)?;
+    let storage = open_storage().await;
+    let report = import::import(&storage, user_id, &data)
+        .await
+        .map_err(|e| invalid_input(format!("{:?}", e)))?;
+    println!(
+        "Imported {}, already saved {}, invalid {}",
+        report.imported, report.duplicates, report.invalid
+    );
+    Ok(())
+}
+
+/// `main export <user_id> <json|csv|html|opml>` writes all articles of the user to stdout.
+async fn export_command(args: &[String]) -> std::io::Result<()> {
+    let (user_id, format) = match args {
+        [user_id, format] => (
+            parse_user_id(user_id)?,
+            export::ExportFormat::parse(format)
+                .ok_or_else(|| invalid_input(format!("Unknown format {}", format)))?,
+        ),
+        _ => return Err(invalid_input(USAGE.to_string())),
+    };
+    let storage = Arc::new(open_storage().await);
+    let mut chunks = export::export(storage, user_id, format);
+    let mut stdout = std::io::stdout();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| invalid_input(format!("{:?}", e)))?;
+        stdout.write_all(chunk.as_bytes())?;
+    }
+    stdout.flush()
+}
+
+fn parse_user_id(user_id: &str) -> std::io::Result<i64> {
+    user_id
+        .parse()
+        .map_err(|_| invalid_input(format!("Invalid user id {}", user_id)))
+}
+
+fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+async fn serve() -> std::io::Result<()> {
+    let mut handlebars = Handlebars::new();
+    handlebars
+        .register_templates_directory(".html", "./templates")
+        .unwrap();
+    let handlebars_ref = Arc::new(handlebars);
+    let storage = Arc::new(open_storage().await);
     let token_storage = Arc::new(TokenStorage::new(TOKEN_TTL));
 
     let st = storage.clone();
@@ -36,11 +106,29 @@ async fn main() -> std::io::Result<()> {
         update_loop(&st, &tt, &p).await;
     });
 
+    let trash_retention_days = std::env::var("TRASH_RETENTION_DAYS")
+        .map(|d| {
+            d.parse()
+                .expect("Provide trash retention as a number of days")
+        })
+        .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS);
+    let st2 = storage.clone();
+    actix_rt::spawn(async move {
+        purge_loop(&st2, trash_retention_days).await;
+    });
+
+    let st3 = storage.clone();
+    actix_rt::spawn(async move {
+        health_loop(&st3).await;
+    });
+
     let st1 = storage.clone();
     let app_state = web::Data::new(AppState {
         storage: st1,
         hb: handlebars_ref.clone(),
         token_storage: token_storage,
+        oauth_codes: Arc::new(OauthCodes::new(OAUTH_CODE_TTL)),
+        trash_retention_days,
     });
 
     HttpServer::new(move || {
@@ -0,0 +1,591 @@
+use actix_web::*;
+use serde_json::{json, Map, Value};
+
+/// Json schema of a payload type of the api.
+pub trait ApiSchema {
+    fn name() -> &'static str;
+    fn schema() -> Value;
+
+    fn reference() -> Value {
+        json!({ "$ref": format!("#/components/schemas/{}", Self::name()) })
+    }
+}
+
+impl ApiSchema for crate::api::ArticleJson {
+    fn name() -> &'static str {
+        "Article"
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["id", "url", "status", "tags"],
+            "properties": {
+                "id": { "type": "integer", "format": "int64" },
+                "url": { "type": "string", "format": "uri" },
+                "title": { "type": "string", "nullable": true },
+                "status": { "type": "string", "enum": ["pending", "archived"] },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "starred": { "type": "boolean" },
+                "word_count": {
+                    "type": "integer",
+                    "format": "int64",
+                    "nullable": true,
+                    "description": "Words of the extracted content, absent until it is fetched"
+                },
+                "reading_minutes": {
+                    "type": "integer",
+                    "format": "int64",
+                    "nullable": true,
+                    "description": "Estimated reading time in minutes"
+                },
+                "created_at": {
+                    "type": "integer",
+                    "format": "int64",
+                    "nullable": true,
+                    "description": "Unix time in seconds"
+                }
+            }
+        })
+    }
+}
+
+impl ApiSchema for crate::api::ArticleList {
+    fn name() -> &'static str {
+        "ArticleList"
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["articles"],
+            "properties": {
+                "articles": { "type": "array", "items": crate::api::ArticleJson::reference() },
+                "next_cursor": {
+                    "type": "integer",
+                    "format": "int64",
+                    "nullable": true,
+                    "description": "Pass as cursor to get the next page, absent on the last page"
+                }
+            }
+        })
+    }
+}
+
+impl ApiSchema for crate::api::NewArticle {
+    fn name() -> &'static str {
+        "NewArticle"
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["url"],
+            "properties": {
+                "url": { "type": "string", "format": "uri" },
+                "title": {
+                    "type": "string",
+                    "description": "Extracted from the page when absent"
+                },
+                "tags": { "type": "array", "items": { "type": "string" } }
+            }
+        })
+    }
+}
+
+impl ApiSchema for crate::api::ArticleUpdate {
+    fn name() -> &'static str {
+        "ArticleUpdate"
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "title": { "type": "string" },
+                "status": { "type": "string", "enum": ["pending", "archived"] },
+                "tags": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Replaces all tags of the article"
+                },
+                "starred": { "type": "boolean" }
+            }
+        })
+    }
+}
+
+impl ApiSchema for crate::api::ApiError {
+    fn name() -> &'static str {
+        "Error"
+    }
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["error"],
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "required": ["code", "message"],
+                    "properties": {
+                        "code": { "type": "string" },
+                        "message": { "type": "string" }
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Documented api operation, path is relative to the server root.
+pub struct Operation {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub operation_id: &'static str,
+    pub summary: &'static str,
+    pub parameters: Vec<Value>,
+    pub request: Option<Value>,
+    pub status: u16,
+    pub response: Option<Value>,
+}
+
+fn path_id() -> Value {
+    json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "integer", "format": "int64" }
+    })
+}
+
+fn query_param(name: &str, schema: Value, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "schema": schema,
+        "description": description
+    })
+}
+
+pub fn operations() -> Vec<Operation> {
+    use crate::api::*;
+    vec![
+        Operation {
+            method: "get",
+            path: "/api/v1/articles",
+            operation_id: "listArticles",
+            summary: "List articles, newest first",
+            parameters: vec![
+                query_param(
+                    "status",
+                    json!({ "type": "string", "enum": ["pending", "archived"] }),
+                    "Only articles with the status",
+                ),
+                query_param(
+                    "tag",
+                    json!({ "type": "string" }),
+                    "Only articles with the tag",
+                ),
+                query_param(
+                    "starred",
+                    json!({ "type": "boolean" }),
+                    "Only starred or only not starred articles",
+                ),
+                query_param(
+                    "max_minutes",
+                    json!({ "type": "integer", "minimum": 0 }),
+                    "Only articles which can be read in that many minutes",
+                ),
+                query_param(
+                    "cursor",
+                    json!({ "type": "integer", "format": "int64" }),
+                    "next_cursor of the previous page",
+                ),
+                query_param(
+                    "limit",
+                    json!({ "type": "integer", "minimum": 1, "maximum": 100, "default": 20 }),
+                    "Page size",
+                ),
+            ],
+            request: None,
+            status: 200,
+            response: Some(ArticleList::reference()),
+        },
+        Operation {
+            method: "post",
+            path: "/api/v1/articles",
+            operation_id: "createArticle",
+            summary: "Save new article by url",
+            parameters: vec![],
+            request: Some(NewArticle::reference()),
+            status: 201,
+            response: Some(ArticleJson::reference()),
+        },
+        Operation {
+            method: "get",
+            path: "/api/v1/articles/{id}",
+            operation_id: "getArticle",
+            summary: "Get the article",
+            parameters: vec![path_id()],
+            request: None,
+            status: 200,
+            response: Some(ArticleJson::reference()),
+        },
+        Operation {
+            method: "patch",
+            path: "/api/v1/articles/{id}",
+            operation_id: "updateArticle",
+            summary: "Update title, status, tags or the star of the article",
+            parameters: vec![path_id()],
+            request: Some(ArticleUpdate::reference()),
+            status: 200,
+            response: Some(ArticleJson::reference()),
+        },
+        Operation {
+            method: "delete",
+            path: "/api/v1/articles/{id}",
+            operation_id: "deleteArticle",
+            summary: "Delete the article",
+            parameters: vec![path_id()],
+            request: None,
+            status: 204,
+            response: None,
+        },
+    ]
+}
+
+fn schemas() -> Value {
+    use crate::api::*;
+    let mut schemas = Map::new();
+    for (name, schema) in [
+        (ArticleJson::name(), ArticleJson::schema()),
+        (ArticleList::name(), ArticleList::schema()),
+        (NewArticle::name(), NewArticle::schema()),
+        (ArticleUpdate::name(), ArticleUpdate::schema()),
+        (ApiError::name(), ApiError::schema()),
+    ] {
+        schemas.insert(name.to_string(), schema);
+    }
+    Value::Object(schemas)
+}
+
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for op in operations() {
+        let mut responses = Map::new();
+        let mut success = json!({ "description": op.summary });
+        if let Some(response) = op.response {
+            success["content"] = json!({ "application/json": { "schema": response } });
+        }
+        responses.insert(op.status.to_string(), success);
+        responses.insert(
+            "default".to_string(),
+            json!({
+                "description": "Error",
+                "content": {
+                    "application/json": { "schema": crate::api::ApiError::reference() }
+                }
+            }),
+        );
+        let mut operation = json!({
+            "operationId": op.operation_id,
+            "summary": op.summary,
+            "parameters": op.parameters,
+            "responses": responses,
+        });
+        if let Some(request) = op.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": request } }
+            });
+        }
+        let path = paths
+            .entry(op.path.to_string())
+            .or_insert_with(|| json!({}));
+        path[op.method] = operation;
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Save to read",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" }
+            }
+        },
+        "security": [{ "bearer": [] }]
+    })
+}
+
+#[get("/api/openapi.json")]
+pub async fn spec() -> HttpResponse {
+    HttpResponse::Ok().json(document())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{document, ApiSchema};
+    use crate::api::{ApiError, ArticleJson, ArticleList, ArticleUpdate, NewArticle};
+    use crate::storage::{Article, ArticleStatus};
+    use regex::Regex;
+    use serde::de::{self, DeserializeOwned, Visitor};
+    use serde_json::{json, Value};
+
+    /// Differences between the value and the schema, the properties of objects must match
+    /// both ways so that fields added to or removed from a type show up.
+    fn mismatches(doc: &Value, schema: &Value, value: &Value, path: &str) -> Vec<String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return mismatches(doc, &doc["components"]["schemas"][name], value, path);
+        }
+        if value.is_null() {
+            return match schema["nullable"].as_bool() {
+                Some(true) => vec![],
+                _ => vec![format!("{} is null but not nullable", path)],
+            };
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            if !values.contains(value) {
+                return vec![format!("{} is {} out of the enum", path, value)];
+            }
+        }
+        let mut result = vec![];
+        match (schema["type"].as_str(), value) {
+            (Some("integer"), Value::Number(n)) if n.is_i64() => (),
+            (Some("string"), Value::String(_)) | (Some("boolean"), Value::Bool(_)) => (),
+            (Some("array"), Value::Array(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    result.extend(mismatches(
+                        doc,
+                        &schema["items"],
+                        item,
+                        &format!("{}[{}]", path, i),
+                    ));
+                }
+            }
+            (Some("object"), Value::Object(fields)) => {
+                let empty = serde_json::Map::new();
+                let properties = schema["properties"].as_object().unwrap_or(&empty);
+                for required in schema["required"].as_array().into_iter().flatten() {
+                    let required = required.as_str().unwrap();
+                    if !fields.contains_key(required) {
+                        result.push(format!("{}.{} is required but missing", path, required));
+                    }
+                }
+                for name in properties.keys() {
+                    if !fields.contains_key(name) {
+                        result.push(format!("{}.{} is documented but missing", path, name));
+                    }
+                }
+                for (name, field) in fields {
+                    let path = format!("{}.{}", path, name);
+                    match properties.get(name) {
+                        Some(property) => result.extend(mismatches(doc, property, field, &path)),
+                        None => result.push(format!("{} is not documented", path)),
+                    }
+                }
+            }
+            (kind, _) => result.push(format!("{} is {} but should be {:?}", path, value, kind)),
+        }
+        result
+    }
+
+    fn assert_matches<T: ApiSchema>(value: Value) {
+        let doc = document();
+        let problems = mismatches(&doc, &T::reference(), &value, T::name());
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    fn article(id: i64, title: Option<&str>, word_count: Option<i64>) -> ArticleJson {
+        let mut article = Article::for_test(id, "https://example.com/a", ArticleStatus::Pending);
+        article.created_at = Some(1600000000);
+        article.starred = true;
+        article.word_count = word_count;
+        article.reading_minutes = word_count.map(crate::extractor::reading_minutes);
+        article.tags = vec!["rust".to_string()];
+        article.data.title = title.map(|t| t.to_string());
+        ArticleJson::from(article)
+    }
+
+    /// Responses serialized from the api types match their documented schemas.
+    #[test]
+    fn test_responses_match_schemas() {
+        assert_matches::<ArticleJson>(
+            serde_json::to_value(article(1, Some("A"), Some(10))).unwrap(),
+        );
+        assert_matches::<ArticleJson>(serde_json::to_value(article(2, None, None)).unwrap());
+        let list = ArticleList {
+            articles: vec![article(2, None, None), article(1, Some("A"), Some(10))],
+            next_cursor: Some(1),
+        };
+        assert_matches::<ArticleList>(serde_json::to_value(list).unwrap());
+        let last = ArticleList {
+            articles: vec![],
+            next_cursor: None,
+        };
+        assert_matches::<ArticleList>(serde_json::to_value(last).unwrap());
+        assert_matches::<ApiError>(ApiError::not_found().body());
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let mut value = serde_json::to_value(article(1, Some("A"), None)).unwrap();
+        value["extra"] = Value::Bool(true);
+        value["status"] = Value::String("unknown".to_string());
+        value.as_object_mut().unwrap().remove("starred");
+        let doc = document();
+        let mut problems = mismatches(&doc, &ArticleJson::reference(), &value, "Article");
+        problems.sort();
+        assert_eq!(
+            vec![
+                "Article.extra is not documented",
+                "Article.starred is documented but missing",
+                "Article.status is \"unknown\" out of the enum",
+            ],
+            problems
+        );
+    }
+
+    /// Deserializer which only takes the names of the fields a struct asks for.
+    struct FieldNames<'a>(&'a mut Vec<&'static str>);
+
+    impl<'de> de::Deserializer<'de> for FieldNames<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0.extend(fields);
+            Err(de::Error::custom("only the fields are taken"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option
+            unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    /// Value with every property of the schema set.
+    fn example(doc: &Value, schema: &Value) -> Value {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return example(doc, &doc["components"]["schemas"][name]);
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            return values[0].clone();
+        }
+        match schema["type"].as_str() {
+            Some("object") => Value::Object(
+                schema["properties"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, property)| (name.clone(), example(doc, property)))
+                    .collect(),
+            ),
+            Some("array") => json!([example(doc, &schema["items"])]),
+            Some("integer") => json!(1),
+            Some("boolean") => json!(true),
+            _ if schema["format"] == "uri" => json!("https://example.com/a"),
+            _ => json!("text"),
+        }
+    }
+
+    /// The request type takes exactly the documented properties, deserializes from a full
+    /// example and needs only the required ones.
+    fn assert_request_matches<T: ApiSchema + DeserializeOwned>() {
+        let doc = document();
+        let schema = &doc["components"]["schemas"][T::name()];
+        let mut fields = vec![];
+        let _ = T::deserialize(FieldNames(&mut fields));
+        fields.sort_unstable();
+        let mut properties: Vec<&str> = schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+        properties.sort_unstable();
+        assert_eq!(fields, properties, "fields of {}", T::name());
+        let full = example(&doc, schema);
+        if let Err(e) = serde_json::from_value::<T>(full.clone()) {
+            panic!("{} doesn't take {}: {}", T::name(), full, e);
+        }
+        for name in properties {
+            let mut value = full.clone();
+            value.as_object_mut().unwrap().remove(name);
+            let required = schema["required"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .any(|r| r == name);
+            assert_eq!(
+                required,
+                serde_json::from_value::<T>(value).is_err(),
+                "{}.{} is required: {}",
+                T::name(),
+                name,
+                required
+            );
+        }
+    }
+
+    /// Request types take what their documented schemas describe.
+    #[test]
+    fn test_requests_match_schemas() {
+        assert_request_matches::<NewArticle>();
+        assert_request_matches::<ArticleUpdate>();
+    }
+
+    /// Every route declared in the api module must be in the document.
+    #[test]
+    fn test_all_routes_documented() {
+        let source = include_str!("api.rs");
+        let scope = Regex::new(r#"web::scope\("([^"]+)"\)"#)
+            .unwrap()
+            .captures(source)
+            .unwrap()[1]
+            .to_string();
+        let route = Regex::new(r#"#\[(get|post|put|patch|delete)\("([^"]*)"\)\]"#).unwrap();
+        let doc = document();
+        let mut routes = 0;
+        for cap in route.captures_iter(source) {
+            routes += 1;
+            let path = format!("{}{}", scope, &cap[2]);
+            assert!(
+                doc["paths"][&path][&cap[1]].is_object(),
+                "{} {} is not documented in openapi",
+                &cap[1],
+                path
+            );
+        }
+        assert!(routes > 0);
+    }
+
+    #[test]
+    fn test_references_resolved() {
+        let doc = document();
+        let text = doc.to_string();
+        let reference = Regex::new(r##""#/components/schemas/([A-Za-z]+)""##).unwrap();
+        for cap in reference.captures_iter(&text) {
+            assert!(
+                doc["components"]["schemas"][&cap[1]].is_object(),
+                "schema {} is not defined",
+                &cap[1]
+            );
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//! Compatibility layer for clients of the Pocket v3 api.
+//!
+//! Access tokens are personal api tokens issued by the oauth flow, pending
+//! articles are Pocket's unread items and archived ones are Pocket's archive.
+use crate::api;
+use crate::auth::{hash_token, issue_api_token, AuthUser, Scope};
+use crate::routes::{http_url, AppState};
+use crate::save_link;
+use crate::storage::{Article, ArticleFilter, ArticleStatus};
+use actix_web::http::StatusCode;
+use actix_web::*;
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::*;
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+const MAX_COUNT: i64 = 5000;
+
+#[derive(Debug)]
+pub struct PocketError {
+    status: StatusCode,
+    code: u16,
+    message: String,
+}
+
+impl PocketError {
+    fn new(status: StatusCode, code: u16, message: impl Into<String>) -> PocketError {
+        PocketError {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> PocketError {
+        PocketError::new(StatusCode::BAD_REQUEST, 138, message)
+    }
+
+    fn unauthorized() -> PocketError {
+        PocketError::new(StatusCode::UNAUTHORIZED, 107, "Invalid access token")
+    }
+}
+
+impl fmt::Display for PocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+/// Pocket reports errors with the `X-Error-Code` and `X-Error` headers.
+impl ResponseError for PocketError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .header("X-Error-Code", self.code.to_string())
+            .header("X-Error", self.message.clone())
+            .finish()
+    }
+}
+
+impl From<anyhow::Error> for PocketError {
+    fn from(e: anyhow::Error) -> PocketError {
+        error!("{:?}", e);
+        PocketError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            199,
+            "Internal server error",
+        )
+    }
+}
+
+fn parse_body<T: DeserializeOwned>(req: &HttpRequest, body: &[u8]) -> Result<T, PocketError> {
+    api::parse_body(req, body).map_err(PocketError::bad_request)
+}
+
+fn accepts_json(req: &HttpRequest) -> bool {
+    req.headers()
+        .get("X-Accept")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.contains("json"))
+        .unwrap_or(false)
+}
+
+/// Responds in json or form encoded way depending on the `X-Accept` header, like Pocket does.
+fn oauth_response(req: &HttpRequest, fields: &[(&str, &str)]) -> HttpResponse {
+    if accepts_json(req) {
+        let body: Map<String, Value> = fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), json!(v)))
+            .collect();
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::Ok()
+            .content_type("application/x-www-form-urlencoded")
+            .body(
+                url::form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(fields)
+                    .finish(),
+            )
+    }
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',').map(|t| t.to_string()).collect()
+}
+
+async fn authenticate(
+    data: &AppState<'_>,
+    access_token: &str,
+    scope: Scope,
+) -> Result<AuthUser, PocketError> {
+    let user = data
+        .storage
+        .use_api_token(&hash_token(access_token))
+        .await?
+        .map(|t| AuthUser::from_token(&t))
+        .ok_or_else(PocketError::unauthorized)?;
+    user.require(scope).map_err(|_| {
+        PocketError::new(StatusCode::FORBIDDEN, 158, "Access token has no permission")
+    })?;
+    Ok(user)
+}
+
+fn item(article: &Article) -> Value {
+    let id = article.id.to_string();
+    let title = article.data.title.clone().unwrap_or_default();
+    let time_added = article.created_at.unwrap_or_default().to_string();
+    let tags: Map<String, Value> = article
+        .tags
+        .iter()
+        .map(|t| (t.clone(), json!({ "item_id": id, "tag": t })))
+        .collect();
+    json!({
+        "item_id": id,
+        "resolved_id": id,
+        "given_url": article.data.url.as_str(),
+        "resolved_url": article.data.url.as_str(),
+        "given_title": title,
+        "resolved_title": title,
+        "favorite": if article.starred { "1" } else { "0" },
+        "word_count": article.word_count.unwrap_or(0).to_string(),
+        "status": match article.status {
+            ArticleStatus::Pending => "0",
+            ArticleStatus::Archived => "1",
+        },
+        "time_added": time_added,
+        "time_updated": time_added,
+        "tags": tags,
+    })
+}
+
+pub fn scope() -> actix_web::Scope {
+    web::scope("/v3")
+        .service(oauth_request)
+        .service(oauth_authorize)
+        .service(add)
+        .service(get)
+        .service(send)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OauthRequest {
+    pub consumer_key: String,
+    #[serde(default)]
+    pub redirect_uri: Option<String>,
+}
+
+/// Issues request token. It becomes usable only after the user confirms it on the authorize page.
+#[post("/oauth/request")]
+pub async fn oauth_request(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, PocketError> {
+    let request: OauthRequest = parse_body(&req, &body)?;
+    let code = data
+        .oauth_codes
+        .issue(&request.consumer_key, request.redirect_uri)
+        .await;
+    Ok(oauth_response(&req, &[("code", &code)]))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OauthAuthorize {
+    pub consumer_key: String,
+    pub code: String,
+}
+
+#[post("/oauth/authorize")]
+pub async fn oauth_authorize(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, PocketError> {
+    let request: OauthAuthorize = parse_body(&req, &body)?;
+    match data
+        .oauth_codes
+        .exchange(&request.code, &request.consumer_key)
+        .await
+    {
+        Some(user_id) => {
+            let token = issue_api_token(
+                &data.storage,
+                &user_id,
+                &format!("pocket: {}", request.consumer_key),
+                &[Scope::Read, Scope::Write],
+            )
+            .await?;
+            Ok(oauth_response(
+                &req,
+                &[("access_token", &token), ("username", &user_id.to_string())],
+            ))
+        }
+        None => Err(PocketError::new(
+            StatusCode::FORBIDDEN,
+            159,
+            "User rejected code or it is expired",
+        )),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AddRequest {
+    pub access_token: String,
+    pub url: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Option<String>,
+}
+
+#[post("/add")]
+pub async fn add(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, PocketError> {
+    let request: AddRequest = parse_body(&req, &body)?;
+    let user = authenticate(&data, &request.access_token, Scope::Write).await?;
+    let url = http_url(&request.url)
+        .ok_or_else(|| PocketError::bad_request("Only http and https urls are saved"))?;
+    let id = save_link(&data.storage, user.user_id, url, request.title).await?;
+    if let Some(tags) = request.tags {
+        data.storage
+            .set_tags(&user.user_id, &id, &split_tags(&tags))
+            .await?;
+    }
+    match data.storage.get_article(&user.user_id, &id).await? {
+        Some(article) => {
+            Ok(HttpResponse::Ok().json(json!({ "item": item(&article), "status": 1 })))
+        }
+        None => Err(PocketError::bad_request("Item was not saved")),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetRequest {
+    pub access_token: String,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default, deserialize_with = "api::lenient_number")]
+    pub favorite: Option<i64>,
+    #[serde(default, deserialize_with = "api::lenient_number")]
+    pub count: Option<i64>,
+    #[serde(default, deserialize_with = "api::lenient_number")]
+    pub offset: Option<i64>,
+}
+
+/// Only newest first sorting is supported and `since` is ignored, so clients always get a full list.
+#[post("/get")]
+pub async fn get(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, PocketError> {
+    let request: GetRequest = parse_body(&req, &body)?;
+    let user = authenticate(&data, &request.access_token, Scope::Read).await?;
+    let status = match request.state.as_deref() {
+        None | Some("all") => None,
+        Some("unread") => Some(ArticleStatus::Pending),
+        Some("archive") => Some(ArticleStatus::Archived),
+        Some(s) => return Err(PocketError::bad_request(format!("Unknown state {}", s))),
+    };
+    let tags = match request.tag {
+        Some(t) if t == "_untagged_" => {
+            return Err(PocketError::bad_request("Untagged filter is not supported"))
+        }
+        t => t.into_iter().collect(),
+    };
+    let filter = ArticleFilter {
+        status,
+        tags,
+        starred: match request.favorite {
+            Some(0) => Some(false),
+            Some(1) => Some(true),
+            _ => None,
+        },
+        limit: request.count.unwrap_or(MAX_COUNT).clamp(1, MAX_COUNT),
+        offset: request.offset.unwrap_or(0).max(0),
+        ..ArticleFilter::default()
+    };
+    let list: Map<String, Value> = data
+        .storage
+        .articles(&user.user_id, &filter)
+        .await?
+        .iter()
+        .map(|a| (a.id.to_string(), item(a)))
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "status": 1,
+        "complete": 1,
+        "list": list,
+        "since": crate::storage::now(),
+    })))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SendRequest {
+    pub access_token: String,
+    #[serde(deserialize_with = "actions")]
+    pub actions: Vec<Action>,
+}
+
+/// Form encoded requests carry actions as a json string.
+fn actions<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Action>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Actions {
+        List(Vec<Action>),
+        Json(String),
+    }
+    match Actions::deserialize(deserializer)? {
+        Actions::List(actions) => Ok(actions),
+        Actions::Json(json) => serde_json::from_str(&json).map_err(de::Error::custom),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Action {
+    pub action: String,
+    #[serde(default, deserialize_with = "api::lenient_number")]
+    pub item_id: Option<i64>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Option<String>,
+}
+
+async fn apply(data: &AppState<'_>, user_id: &i64, action: &Action) -> anyhow::Result<bool> {
+    let storage = &data.storage;
+    if action.action == "add" {
+        return match action.url.as_deref().and_then(http_url) {
+            Some(url) => {
+                let id = save_link(storage, *user_id, url, action.title.clone()).await?;
+                if let Some(tags) = &action.tags {
+                    storage.set_tags(user_id, &id, &split_tags(tags)).await?;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        };
+    }
+    let article = match action.item_id {
+        Some(id) => storage.get_article(user_id, &id).await?,
+        None => None,
+    };
+    let article = match article {
+        Some(a) => a,
+        None => return Ok(false),
+    };
+    let id = article.id;
+    match (action.action.as_str(), article.status) {
+        ("archive", ArticleStatus::Pending) => Ok(storage.archive(user_id, &id).await?.is_some()),
+        ("readd", ArticleStatus::Archived) => Ok(storage.unarchive(user_id, &id).await?.is_some()),
+        ("archive", ArticleStatus::Archived) | ("readd", ArticleStatus::Pending) => Ok(true),
+        ("delete", ArticleStatus::Pending) => {
+            storage.delete_pending(user_id, &id).await.map(|_| true)
+        }
+        ("delete", ArticleStatus::Archived) => {
+            storage.delete_archived(user_id, &id).await.map(|_| true)
+        }
+        ("tags_add", _) => {
+            let mut tags = article.tags.clone();
+            tags.extend(split_tags(action.tags.as_deref().unwrap_or_default()));
+            storage.set_tags(user_id, &id, &tags).await.map(|_| true)
+        }
+        ("tags_replace", _) => storage
+            .set_tags(
+                user_id,
+                &id,
+                &split_tags(action.tags.as_deref().unwrap_or_default()),
+            )
+            .await
+            .map(|_| true),
+        ("favorite", _) => storage.set_starred(user_id, &id, true).await,
+        ("unfavorite", _) => storage.set_starred(user_id, &id, false).await,
+        ("tags_clear", _) => storage.set_tags(user_id, &id, &[]).await.map(|_| true),
+        _ => Ok(false),
+    }
+}
+
+#[post("/send")]
+pub async fn send(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, PocketError> {
+    let request: SendRequest = parse_body(&req, &body)?;
+    let user = authenticate(&data, &request.access_token, Scope::Write).await?;
+    let mut results = vec![];
+    for action in request.actions.iter() {
+        results.push(apply(&data, &user.user_id, action).await?);
+    }
+    Ok(HttpResponse::Ok().json(json!({ "status": 1, "action_results": results })))
+}
@@ -0,0 +1,327 @@
+use crate::auth::{AuthUser, Scope};
+use crate::routes::{format_time, see_other, AppState, APP_NAME};
+use crate::storage::{Annotation, AnnotationKind, Article};
+use actix_web::*;
+use serde::*;
+use serde_json::json;
+
+/// Longest anchor kept for a highlight, anchors are set by the script of the reader view.
+const MAX_ANCHOR_LENGTH: usize = 100;
+
+/// Highlights as quotes and notes as paragraphs, in the order they were made.
+pub fn markdown(article: &Article, annotations: &[Annotation]) -> String {
+    let url = article.data.url.as_str();
+    let mut out = format!(
+        "# {}\n\n<{}>\n",
+        article.data.title.as_deref().unwrap_or(url),
+        url
+    );
+    for annotation in annotations {
+        out.push('\n');
+        for line in annotation.text.trim().lines() {
+            match annotation.kind {
+                AnnotationKind::Highlight if line.trim().is_empty() => out.push_str(">\n"),
+                AnnotationKind::Highlight => out.push_str(&format!("> {}\n", line.trim_end())),
+                AnnotationKind::Note => out.push_str(&format!("{}\n", line.trim_end())),
+            }
+        }
+    }
+    out
+}
+
+#[derive(Serialize, Debug)]
+struct AnnotationView {
+    id: i64,
+    highlight: bool,
+    text: String,
+    created_at: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ReaderTemplate<'a> {
+    app_name: &'a str,
+    id: i64,
+    url: String,
+    title: String,
+    /// Simplified html made by the extractor.
+    content: Option<String>,
+    annotations: Vec<AnnotationView>,
+    /// Id and name of the collections of the user, to add the article to one of them.
+    collections: Vec<(i64, String)>,
+    /// Url the link redirects to now, if it moved.
+    moved_to: Option<String>,
+    /// Snapshot of the page in the Internet Archive, if the link is dead.
+    archive_url: Option<String>,
+    /// Time the snapshot of the page was taken, if there is one.
+    snapshot_at: Option<String>,
+    progress: i64,
+    /// Scroll position to restore, saved by the page itself.
+    position: i64,
+    user_id: i64,
+    page: &'a str,
+}
+
+/// Extracted content of the article with its highlights and notes.
+#[get("/read/{link_id}")]
+pub async fn reader_page(
+    web::Path(link_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let storage = &data.storage;
+    let article = match storage
+        .get_article(&user.user_id, &link_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(article) => article,
+        None => return Ok(HttpResponse::NotFound().body("Article not found")),
+    };
+    let content = storage
+        .get_content(&user.user_id, &link_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let annotations = storage
+        .annotations(&user.user_id, &link_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|a| AnnotationView {
+            id: a.id,
+            highlight: a.kind == AnnotationKind::Highlight,
+            text: a.text,
+            created_at: format_time(a.created_at),
+        })
+        .collect();
+    let collections = storage
+        .collections(&user.user_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|c| (c.id, c.name))
+        .collect();
+    let snapshot_at = storage
+        .snapshot_info(&user.user_id, &link_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .map(|(_, created_at)| format_time(created_at));
+    let url = article.data.url.to_string();
+    let archive_url = Some(article.archive_url()).filter(|_| article.is_dead());
+    let json = json!(ReaderTemplate {
+        app_name: APP_NAME,
+        id: article.id,
+        title: article.data.title.unwrap_or_else(|| url.clone()),
+        url,
+        content,
+        annotations,
+        collections,
+        moved_to: article.final_url,
+        archive_url,
+        snapshot_at,
+        progress: article.progress,
+        position: article.position.unwrap_or(0),
+        user_id: user.user_id,
+        page: "reader"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ProgressForm {
+    progress: i64,
+    position: i64,
+}
+
+/// Saves the position sent by the reader view while it's scrolled. Pending articles
+/// read to the end are archived if the user turned it on in the settings.
+#[post("/read/{link_id}/progress")]
+pub async fn save_progress(
+    web::Path(link_id): web::Path<i64>,
+    form: web::Form<ProgressForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let storage = &data.storage;
+    let saved = storage
+        .set_progress(&user.user_id, &link_id, form.progress, form.position)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    if !saved {
+        return Ok(HttpResponse::NotFound().body("Article not found"));
+    }
+    if form.progress >= 100
+        && storage
+            .auto_archive(&user.user_id)
+            .await
+            .map_err(error::ErrorInternalServerError)?
+    {
+        storage
+            .archive(&user.user_id, &link_id)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn back_to_reader(article_id: i64) -> HttpResponse {
+    see_other(&format!("/read/{}#annotations", article_id))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AnnotationForm {
+    /// Set by the submit button, `highlight` or `note`.
+    kind: String,
+    text: String,
+    #[serde(default)]
+    anchor: Option<String>,
+}
+
+#[post("/read/{link_id}/annotations")]
+pub async fn create_annotation(
+    web::Path(link_id): web::Path<i64>,
+    form: web::Form<AnnotationForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let form = form.into_inner();
+    let kind = match AnnotationKind::parse(&form.kind) {
+        Some(kind) => kind,
+        None => return Ok(HttpResponse::BadRequest().body("Unknown annotation kind")),
+    };
+    if form.text.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().body("Annotation text is empty"));
+    }
+    let anchor = form
+        .anchor
+        .map(|a| a.trim().chars().take(MAX_ANCHOR_LENGTH).collect::<String>())
+        .filter(|a| !a.is_empty());
+    match data
+        .storage
+        .add_annotation(&user.user_id, &link_id, kind, form.text.trim(), anchor)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(_) => Ok(back_to_reader(link_id)),
+        None => Ok(HttpResponse::NotFound().body("Article not found")),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AnnotationTextForm {
+    text: String,
+}
+
+#[post("/annotations/{annotation_id}")]
+pub async fn update_annotation(
+    web::Path(annotation_id): web::Path<i64>,
+    form: web::Form<AnnotationTextForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    if form.text.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().body("Annotation text is empty"));
+    }
+    let storage = &data.storage;
+    let annotation = match storage
+        .get_annotation(&user.user_id, &annotation_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(annotation) => annotation,
+        None => return Ok(HttpResponse::NotFound().body("Annotation not found")),
+    };
+    storage
+        .update_annotation(&user.user_id, &annotation_id, form.text.trim())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(back_to_reader(annotation.article_id))
+}
+
+#[post("/annotations/{annotation_id}/delete")]
+pub async fn delete_annotation(
+    web::Path(annotation_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let storage = &data.storage;
+    let annotation = match storage
+        .get_annotation(&user.user_id, &annotation_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(annotation) => annotation,
+        None => return Ok(HttpResponse::NotFound().body("Annotation not found")),
+    };
+    storage
+        .delete_annotation(&user.user_id, &annotation_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(back_to_reader(annotation.article_id))
+}
+
+#[get("/read/{link_id}/highlights.md")]
+pub async fn highlights_markdown(
+    web::Path(link_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let storage = &data.storage;
+    let article = match storage
+        .get_article(&user.user_id, &link_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(article) => article,
+        None => return Ok(HttpResponse::NotFound().body("Article not found")),
+    };
+    let annotations = storage
+        .annotations(&user.user_id, &link_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/markdown; charset=utf-8")
+        .header(
+            http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"article-{}-highlights.md\"", link_id),
+        )
+        .body(markdown(&article, &annotations)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ArticleStatus;
+
+    #[test]
+    fn test_markdown() {
+        let mut article = Article::for_test(1, "https://example.com/a", ArticleStatus::Pending);
+        article.data.title = Some("Title".to_string());
+        let annotation = |kind, text: &str| Annotation {
+            id: 1,
+            article_id: 1,
+            kind,
+            text: text.to_string(),
+            anchor: None,
+            created_at: 0,
+            updated_at: None,
+        };
+        let annotations = vec![
+            annotation(AnnotationKind::Highlight, "First line\n\nsecond line"),
+            annotation(AnnotationKind::Note, "My thought"),
+        ];
+        assert_eq!(
+            markdown(&article, &annotations),
+            "# Title\n\n<https://example.com/a>\n\n> First line\n>\n> second line\n\nMy thought\n"
+        );
+    }
+}
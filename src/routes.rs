@@ -1,189 +1,832 @@
-use crate::auth::TokenStorage;
+use crate::auth::{
+    generate_token, issue_api_token, AuthUser, OauthCodes, Scope, TokenStorage, UserSession,
+};
+use crate::export::{export, ExportFormat};
+use crate::import::{import, ImportReport};
+use crate::save_link;
 
-use super::storage::Storage;
+use super::storage::{
+    normalize_tags, Article, ArticleFilter, ArticleStatus, BulkAction, Storage, UndoAction,
+};
+use actix_multipart::Multipart;
 use actix_session::Session;
 use actix_web::*;
+use futures::StreamExt;
 use handlebars::Handlebars;
+use lazy_static::lazy_static;
+use log::error;
+use regex::Regex;
 use serde::*;
 use serde_json::*;
 use std::sync::Arc;
 
-const APP_NAME: &str = "Save to read";
+pub(crate) const APP_NAME: &str = "Save to read";
+/// Longest name of a collection or a team, longer names are cut.
+pub(crate) const MAX_NAME_LENGTH: usize = 100;
+const LIST_PAGE_SIZE: i64 = 30;
+const MAX_IMPORT_SIZE: usize = 20 * 1024 * 1024;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct UserSession {
-    user_id: i64,
+lazy_static! {
+    static ref URL_REGEXP: Regex = Regex::new(r"https?://[^\s]+").unwrap();
 }
 
 pub struct AppState<'a> {
     pub storage: Arc<Storage>,
     pub token_storage: Arc<TokenStorage>,
+    /// Request tokens of the Pocket oauth flow, confirmed by the user.
+    pub oauth_codes: Arc<OauthCodes>,
+    /// Days trashed articles are kept before `purge_loop` deletes them.
+    pub trash_retention_days: i64,
     pub hb: Arc<Handlebars<'a>>,
 }
 
+/// Id, url, title, starred, progress, reading minutes, word count
+/// and the archived copy of the page when the link is dead.
+type ListCard = (
+    String,
+    String,
+    String,
+    bool,
+    i64,
+    Option<i64>,
+    Option<i64>,
+    Option<String>,
+);
+
 #[derive(Serialize, Deserialize, Debug)]
 struct ListTemplate<'a> {
     app_name: &'a str,
-    links: Vec<(String, String, String)>,
+    links: Vec<ListCard>,
+    /// Cursor of the next page, loaded by htmx when the end of the list is revealed.
+    next: Option<i64>,
+    /// Reading time filter, kept in the url of the next page.
+    max_minutes: Option<i64>,
     user_id: i64,
     page: &'a str,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct PageQuery {
+    before: Option<i64>,
+    max_minutes: Option<i64>,
+}
+
+/// Requests made by htmx get only the fragment to swap, not the whole page.
+pub(crate) fn is_htmx(req: &HttpRequest) -> bool {
+    req.headers().contains_key("HX-Request")
+}
+
+fn list_card(article: Article) -> ListCard {
+    let url = article.data.url.to_string();
+    let archive_url = Some(article.archive_url()).filter(|_| article.is_dead());
+    (
+        article.id.to_string(),
+        url.clone(),
+        article.data.title.unwrap_or(url),
+        article.starred,
+        article.progress,
+        article.reading_minutes,
+        article.word_count,
+        archive_url,
+    )
+}
+
+/// Renders a page of the articles matching the filter, or only its cards for htmx.
+async fn render_list(
+    req: &HttpRequest,
+    data: &AppState<'_>,
+    user_id: i64,
+    filter: ArticleFilter,
+    page: &str,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    let articles = data
+        .storage
+        .articles(&user_id, &filter)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let next = if articles.len() as i64 == LIST_PAGE_SIZE {
+        articles.last().map(|a| a.id)
+    } else {
+        None
+    };
+    let json = json!(ListTemplate {
+        app_name: APP_NAME,
+        links: articles.into_iter().map(list_card).collect(),
+        next,
+        max_minutes: filter.max_minutes,
+        user_id,
+        page
+    });
+    let template = if is_htmx(req) {
+        format!("{}_cards", page)
+    } else {
+        "index".to_string()
+    };
+    let rendered = &data
+        .hb
+        .render(&template, &json)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
 #[get("")]
 pub async fn pending_list(
+    web::Query(q): web::Query<PageQuery>,
+    req: HttpRequest,
     data: web::Data<AppState<'_>>,
-    session: Session,
+    user: AuthUser,
 ) -> std::result::Result<HttpResponse, actix_web::error::Error> {
-    if let Some(user_id) = session.get::<UserSession>("user")? {
-        let d = &data.storage;
-        let links = d
-            .pending_list(&user_id.user_id)
-            .await
-            .map_err(actix_web::error::ErrorInternalServerError)?
-            .into_iter()
-            .map(|url| {
-                (
-                    url.0.to_string(),
-                    url.1.to_string(),
-                    url.2.unwrap_or(url.1.to_string()),
-                )
-            })
-            .collect();
-        let json = json!(ListTemplate {
-            app_name: APP_NAME,
-            links,
-            user_id: user_id.user_id,
-            page: "pending"
-        });
-        let rendered = &data
-            .hb
-            .render("index", &json)
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        Ok(HttpResponse::Ok().body(rendered))
-    } else {
-        Ok(HttpResponse::Forbidden().finish())
-    }
+    user.require(Scope::Read)?;
+    let filter = ArticleFilter {
+        status: Some(ArticleStatus::Pending),
+        max_minutes: q.max_minutes,
+        before: q.before,
+        limit: LIST_PAGE_SIZE,
+        ..ArticleFilter::default()
+    };
+    render_list(&req, &data, user.user_id, filter, "pending").await
 }
 
 #[get("/archived")]
 pub async fn archived_list(
+    web::Query(q): web::Query<PageQuery>,
+    req: HttpRequest,
     data: web::Data<AppState<'_>>,
-    session: Session,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let filter = ArticleFilter {
+        status: Some(ArticleStatus::Archived),
+        max_minutes: q.max_minutes,
+        before: q.before,
+        limit: LIST_PAGE_SIZE,
+        ..ArticleFilter::default()
+    };
+    render_list(&req, &data, user.user_id, filter, "archived").await
+}
+
+/// Starred articles of both lists, newest first.
+#[get("/starred")]
+pub async fn starred_list(
+    web::Query(q): web::Query<PageQuery>,
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let filter = ArticleFilter {
+        starred: Some(true),
+        max_minutes: q.max_minutes,
+        before: q.before,
+        limit: LIST_PAGE_SIZE,
+        ..ArticleFilter::default()
+    };
+    render_list(&req, &data, user.user_id, filter, "starred").await
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StarTemplate {
+    id: i64,
+    starred: bool,
+}
+
+/// Toggles the star of the article and renders the button in its new state.
+#[post("/star/{link_id}")]
+pub async fn toggle_star(
+    web::Path(link_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
 ) -> std::result::Result<HttpResponse, actix_web::error::Error> {
-    if let Some(user_id) = session.get::<UserSession>("user")? {
-        let d = &data.storage;
-        let links = d
-            .archived_list(&user_id.user_id)
+    user.require(Scope::Write)?;
+    let d = &data.storage;
+    let owner = writable_owner(d, &user.user_id, &link_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let starred = match owner {
+        Some(owner) => d
+            .toggle_starred(&owner, &link_id)
             .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?
-            .into_iter()
-            .map(|url| {
-                (
-                    url.0.to_string(),
-                    url.1.to_string(),
-                    url.2.unwrap_or(url.1.to_string()),
-                )
-            })
-            .collect();
-        let json = json!(ListTemplate {
-            app_name: APP_NAME,
-            links,
-            user_id: user_id.user_id,
-            page: "archived"
-        });
-        let rendered = &data
-            .hb
-            .render("index", &json)
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        Ok(HttpResponse::Ok().body(rendered))
-    } else {
-        Ok(HttpResponse::Forbidden().finish())
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+        None => None,
+    };
+    let starred = match starred {
+        Some(starred) => starred,
+        None => return Ok(HttpResponse::NotFound().body("Article not found")),
+    };
+    let rendered = &data
+        .hb
+        .render(
+            "star_button",
+            &json!(StarTemplate {
+                id: link_id,
+                starred
+            }),
+        )
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+/// Outcome of a card button, rendered as a fragment in place of the card.
+struct CardMessage {
+    status: http::StatusCode,
+    message: &'static str,
+    undo: bool,
+}
+
+impl CardMessage {
+    fn done(message: &'static str) -> CardMessage {
+        CardMessage {
+            status: http::StatusCode::OK,
+            message,
+            undo: true,
+        }
+    }
+
+    fn info(message: &'static str) -> CardMessage {
+        CardMessage {
+            status: http::StatusCode::OK,
+            message,
+            undo: false,
+        }
+    }
+
+    fn error(status: http::StatusCode, message: &'static str) -> CardMessage {
+        CardMessage {
+            status,
+            message,
+            undo: false,
+        }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct CardMessageTemplate {
+    id: i64,
+    message: &'static str,
+    undo: bool,
+    error: bool,
+}
+
+/// htmx swaps only successful responses, so errors are sent to it with 200 status.
+fn render_card_message(
+    req: &HttpRequest,
+    data: &AppState<'_>,
+    id: i64,
+    result: anyhow::Result<CardMessage>,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    let message = result.unwrap_or_else(|e| {
+        error!("{:?}", e);
+        CardMessage::error(
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+            "Something went wrong, try again",
+        )
+    });
+    let status = if is_htmx(req) {
+        http::StatusCode::OK
+    } else {
+        message.status
+    };
+    let json = json!(CardMessageTemplate {
+        id,
+        message: message.message,
+        undo: message.undo,
+        error: !message.status.is_success(),
+    });
+    let rendered = &data
+        .hb
+        .render("card_message", &json)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::build(status).body(rendered))
+}
+
+/// Account owning the link if the user can change it: the user's own links and links
+/// of teams where the user is an editor or the owner. Others are reported as missing.
+pub(crate) async fn writable_owner(
+    d: &Storage,
+    user_id: &i64,
+    link_id: &i64,
+) -> anyhow::Result<Option<i64>> {
+    let owner = match d.link_owner(link_id).await? {
+        Some(owner) => owner,
+        None => return Ok(None),
+    };
+    Ok(d.role(user_id, &owner)
+        .await?
+        .filter(|role| role.can_write())
+        .map(|_| owner))
+}
+
 #[post("/archive/{link_id}")]
 pub async fn archive(
     web::Path(link_id): web::Path<i64>,
+    req: HttpRequest,
     data: web::Data<AppState<'_>>,
-    session: Session,
+    user: AuthUser,
 ) -> std::result::Result<HttpResponse, actix_web::error::Error> {
-    if let Some(user) = session.get::<UserSession>("user")? {
-        let d = &data.storage;
-        let pending_result = d
-            .get_pending_url(&link_id)
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        match pending_result {
-            Some(link_info) if link_info.user_id == user.user_id => {
-                d.archive(&user.user_id, &link_id)
-                    .await
-                    .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-                Ok(HttpResponse::Ok().finish())
+    user.require(Scope::Write)?;
+    let d = &data.storage;
+    let result = async {
+        let owner = writable_owner(d, &user.user_id, &link_id).await?;
+        match d.get_pending_url(&link_id).await? {
+            Some(link_info) if Some(link_info.user_id) == owner => {
+                d.archive(&link_info.user_id, &link_id).await?;
+                d.add_undo(&link_info.user_id, &link_id, UndoAction::Archive)
+                    .await?;
+                Ok(CardMessage::done("Archived"))
             }
-            _ => Ok(HttpResponse::NotFound().finish()),
+            _ => Ok(CardMessage::error(
+                http::StatusCode::NOT_FOUND,
+                "Article not found",
+            )),
         }
-    } else {
-        Ok(HttpResponse::Forbidden().finish())
     }
+    .await;
+    render_card_message(&req, &data, link_id, result)
 }
 
 #[post("/unarchive/{link_id}")]
 pub async fn unarchive(
     web::Path(link_id): web::Path<i64>,
+    req: HttpRequest,
     data: web::Data<AppState<'_>>,
-    session: Session,
+    user: AuthUser,
 ) -> std::result::Result<HttpResponse, actix_web::error::Error> {
-    if let Some(user) = session.get::<UserSession>("user")? {
-        let d = &data.storage;
-        let archived_result = d
-            .get_archived_url(&link_id)
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        match archived_result {
-            Some(link_info) if link_info.user_id == user.user_id => {
-                d.unarchive(&user.user_id, &link_id)
-                    .await
-                    .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-                Ok(HttpResponse::Ok().finish())
+    user.require(Scope::Write)?;
+    let d = &data.storage;
+    let result = async {
+        let owner = writable_owner(d, &user.user_id, &link_id).await?;
+        match d.get_archived_url(&link_id).await? {
+            Some(link_info) if Some(link_info.user_id) == owner => {
+                d.unarchive(&link_info.user_id, &link_id).await?;
+                d.add_undo(&link_info.user_id, &link_id, UndoAction::Unarchive)
+                    .await?;
+                Ok(CardMessage::done("Moved to pending"))
             }
-            _ => Ok(HttpResponse::NotFound().finish()),
+            _ => Ok(CardMessage::error(
+                http::StatusCode::NOT_FOUND,
+                "Article not found",
+            )),
         }
-    } else {
-        Ok(HttpResponse::Forbidden().finish())
+    }
+    .await;
+    render_card_message(&req, &data, link_id, result)
+}
+
+/// Deleting a missing link is not an error, it's gone anyway.
+async fn delete_link(
+    d: &Storage,
+    user_id: &i64,
+    link_id: &i64,
+    status: ArticleStatus,
+) -> anyhow::Result<CardMessage> {
+    let owner = match writable_owner(d, user_id, link_id).await? {
+        Some(owner) => owner,
+        None => return Ok(CardMessage::info("Already deleted")),
+    };
+    match d.get_article(&owner, link_id).await? {
+        Some(article) if article.status == status => {
+            d.add_undo(&owner, link_id, UndoAction::Delete).await?;
+            match status {
+                ArticleStatus::Pending => d.delete_pending(&owner, link_id).await?,
+                ArticleStatus::Archived => d.delete_archived(&owner, link_id).await?,
+            }
+            Ok(CardMessage::done("Moved to trash"))
+        }
+        _ => Ok(CardMessage::info("Already deleted")),
     }
 }
 
 #[delete("/archived/delete/{link_id}")]
 pub async fn delete_archived(
     web::Path(link_id): web::Path<i64>,
+    req: HttpRequest,
     data: web::Data<AppState<'_>>,
-    session: Session,
+    user: AuthUser,
 ) -> std::result::Result<HttpResponse, actix_web::error::Error> {
-    if let Some(user) = session.get::<UserSession>("user")? {
-        let d = &data.storage;
-        d.delete_archived(&user.user_id, &link_id)
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        Ok(HttpResponse::Ok().finish())
-    } else {
-        Ok(HttpResponse::Forbidden().finish())
-    }
+    user.require(Scope::Write)?;
+    let result = delete_link(
+        &data.storage,
+        &user.user_id,
+        &link_id,
+        ArticleStatus::Archived,
+    )
+    .await;
+    render_card_message(&req, &data, link_id, result)
 }
 
 #[delete("/pending/delete/{link_id}")]
 pub async fn delete_pending(
     web::Path(link_id): web::Path<i64>,
+    req: HttpRequest,
     data: web::Data<AppState<'_>>,
-    session: Session,
+    user: AuthUser,
 ) -> std::result::Result<HttpResponse, actix_web::error::Error> {
-    if let Some(user) = session.get::<UserSession>("user")? {
-        let d = &data.storage;
-        d.delete_pending(&user.user_id, &link_id)
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        Ok(HttpResponse::Ok().finish())
-    } else {
-        Ok(HttpResponse::Forbidden().finish())
+    user.require(Scope::Write)?;
+    let result = delete_link(
+        &data.storage,
+        &user.user_id,
+        &link_id,
+        ArticleStatus::Pending,
+    )
+    .await;
+    render_card_message(&req, &data, link_id, result)
+}
+
+/// Reverts the last card action and renders the card back in the list it belongs to now.
+#[post("/undo/{link_id}")]
+pub async fn undo(
+    web::Path(link_id): web::Path<i64>,
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let d = &data.storage;
+    let restored = async {
+        let owner = match writable_owner(d, &user.user_id, &link_id).await? {
+            Some(owner) => owner,
+            None => return Ok(None),
+        };
+        match d.undo(&owner, &link_id).await? {
+            Some(status) => Ok(d.get_article(&owner, &link_id).await?.map(|a| (status, a))),
+            None => Ok(None),
+        }
+    }
+    .await;
+    match restored {
+        Ok(Some((status, article))) => {
+            let json = json!(ListTemplate {
+                app_name: APP_NAME,
+                links: vec![list_card(article)],
+                next: None,
+                max_minutes: None,
+                user_id: user.user_id,
+                page: status.as_str()
+            });
+            let rendered = &data
+                .hb
+                .render(&format!("{}_cards", status.as_str()), &json)
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            Ok(HttpResponse::Ok().body(rendered))
+        }
+        Ok(None) => render_card_message(
+            &req,
+            &data,
+            link_id,
+            Ok(CardMessage::error(
+                http::StatusCode::NOT_FOUND,
+                "It's too late to undo",
+            )),
+        ),
+        Err(e) => render_card_message(&req, &data, link_id, Err(e)),
+    }
+}
+
+/// Applies the action to the articles checked in a list and goes back to that list.
+/// The form repeats `ids` for every checked article, so it's parsed without serde.
+#[post("/bulk/{action}")]
+pub async fn bulk(
+    web::Path(action): web::Path<String>,
+    body: web::Bytes,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let mut ids = vec![];
+    let mut tags = vec![];
+    let mut back = "/";
+    for (key, value) in url::form_urlencoded::parse(&body) {
+        match key.as_ref() {
+            "ids" => ids.push(
+                value
+                    .parse::<i64>()
+                    .map_err(actix_web::error::ErrorBadRequest)?,
+            ),
+            "tags" => tags.extend(value.split(',').map(|t| t.to_string())),
+            "page" if value == "archived" => back = "/archived",
+            _ => {}
+        }
+    }
+    let action = match action.as_str() {
+        "archive" => BulkAction::Archive,
+        "unarchive" => BulkAction::Unarchive,
+        "delete" => BulkAction::Delete,
+        "tag" if !normalize_tags(&tags).is_empty() => BulkAction::Tag(tags),
+        "tag" => return Ok(HttpResponse::BadRequest().body("No tags to add")),
+        _ => return Ok(HttpResponse::NotFound().finish()),
+    };
+    match data
+        .storage
+        .bulk(&user.user_id, &ids, &action)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        Some(_) => Ok(see_other(back)),
+        None => Ok(HttpResponse::NotFound().body("Article not found")),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrashTemplate<'a> {
+    app_name: &'a str,
+    /// Id, url, title and deletion time.
+    links: Vec<(i64, String, String, String)>,
+    retention_days: i64,
+    user_id: i64,
+    page: &'a str,
+}
+
+#[get("/trash")]
+pub async fn trash(
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let links = data
+        .storage
+        .trash(&user.user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|a| {
+            let url = a.data.url.to_string();
+            (
+                a.id,
+                url.clone(),
+                a.data.title.unwrap_or(url),
+                a.deleted_at.map(format_time).unwrap_or_default(),
+            )
+        })
+        .collect();
+    let json = json!(TrashTemplate {
+        app_name: APP_NAME,
+        links,
+        retention_days: data.trash_retention_days,
+        user_id: user.user_id,
+        page: "trash"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[post("/trash/{link_id}/restore")]
+pub async fn restore(
+    web::Path(link_id): web::Path<i64>,
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let result = match data.storage.restore(&user.user_id, &link_id).await {
+        Ok(Some(ArticleStatus::Pending)) => Ok(CardMessage::info("Restored to pending")),
+        Ok(Some(ArticleStatus::Archived)) => Ok(CardMessage::info("Restored to archived")),
+        Ok(None) => Ok(CardMessage::error(
+            http::StatusCode::NOT_FOUND,
+            "Article not found in the trash",
+        )),
+        Err(e) => Err(e),
+    };
+    render_card_message(&req, &data, link_id, result)
+}
+
+#[post("/trash/empty")]
+pub async fn empty_trash(
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    data.storage
+        .empty_trash(&user.user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(see_other("/trash"))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AddForm {
+    url: String,
+    /// Title known by the browser, the page is not fetched for it then.
+    #[serde(default)]
+    title: Option<String>,
+}
+
+/// Url of a page to save, other schemes like `javascript:` would run when the link is opened.
+pub fn http_url(url: &str) -> Option<url::Url> {
+    url::Url::parse(url.trim())
+        .ok()
+        .filter(|u| u.scheme() == "http" || u.scheme() == "https")
+}
+
+/// Redirect after a form post, so reloading the page doesn't post it again.
+pub(crate) fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .header(http::header::LOCATION, location)
+        .finish()
+}
+
+fn parse_add_form(
+    form: AddForm,
+) -> std::result::Result<(url::Url, Option<String>), actix_web::error::Error> {
+    let url = http_url(&form.url)
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("Only http and https links are saved"))?;
+    let title = form
+        .title
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+    Ok((url, title))
+}
+
+#[post("/add")]
+pub async fn add(
+    form: web::Form<AddForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let (url, title) = parse_add_form(form.into_inner())?;
+    save_link(&data.storage, user.user_id, url, title)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(see_other("/"))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SavedTemplate<'a> {
+    app_name: &'a str,
+    url: String,
+    title: String,
+    user_id: i64,
+    page: &'a str,
+}
+
+/// Endpoint of the bookmarklet, saves the page the user is on and shows a confirmation.
+#[get("/save")]
+pub async fn save(
+    web::Query(form): web::Query<AddForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let (url, title) = parse_add_form(form)?;
+    let id = save_link(&data.storage, user.user_id, url.clone(), title)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let title = data
+        .storage
+        .get_article(&user.user_id, &id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .and_then(|a| a.data.title)
+        .unwrap_or_else(|| url.to_string());
+    let json = json!(SavedTemplate {
+        app_name: APP_NAME,
+        url: url.to_string(),
+        title,
+        user_id: user.user_id,
+        page: "saved"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ShareForm {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+}
+
+/// Shared url, apps often put it into the text together with the title.
+fn shared_url(form: &ShareForm) -> Option<url::Url> {
+    form.url
+        .iter()
+        .chain(form.text.iter())
+        .find_map(|s| http_url(s).or_else(|| URL_REGEXP.find(s).and_then(|m| http_url(m.as_str()))))
+}
+
+/// Target of the Web Share of the installed app, see `manifest.webmanifest`.
+#[post("/share")]
+pub async fn share(
+    form: web::Form<ShareForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let url = match shared_url(&form) {
+        Some(url) => url,
+        None => return Ok(HttpResponse::BadRequest().body("Nothing to save, no link is shared")),
+    };
+    let title = form
+        .title
+        .as_ref()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+    save_link(&data.storage, user.user_id, url, title)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(see_other("/"))
+}
+
+#[get("/manifest.webmanifest")]
+pub async fn manifest() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/manifest+json")
+        .body(include_str!("../static/manifest.webmanifest"))
+}
+
+/// Served from the root to control all pages of the app.
+#[get("/sw.js")]
+pub async fn service_worker() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/javascript")
+        .body(include_str!("../static/sw.js"))
+}
+
+#[get("/icon.svg")]
+pub async fn icon() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .body(include_str!("../static/icon.svg"))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthorizeRequest {
+    request_token: String,
+}
+
+#[derive(Serialize, Debug)]
+struct AuthorizeTemplate<'a> {
+    app_name: &'a str,
+    request_token: String,
+    consumer_key: String,
+    redirect_uri: Option<String>,
+    user_id: i64,
+    page: &'a str,
+}
+
+/// Asks the user to confirm the request token of a Pocket client, showing the client
+/// and where it sends the user back.
+#[get("/auth/authorize")]
+pub async fn authorize_page(
+    web::Query(request): web::Query<AuthorizeRequest>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require_session()?;
+    let code = match data.oauth_codes.get(&request.request_token).await {
+        Some(code) if code.user_id.is_none() => code,
+        _ => return Ok(HttpResponse::NotFound().body("Unknown or expired request token")),
+    };
+    let json = json!(AuthorizeTemplate {
+        app_name: APP_NAME,
+        request_token: request.request_token,
+        consumer_key: code.consumer_key,
+        redirect_uri: code.redirect_uri,
+        user_id: user.user_id,
+        page: "authorize"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+/// Confirms the request token of a Pocket client, so it can be exchanged for an access token.
+/// The user goes back to the redirect uri the client gave with the request, if any.
+#[post("/auth/authorize")]
+pub async fn authorize(
+    form: web::Form<AuthorizeRequest>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require_session()?;
+    let code = match data
+        .oauth_codes
+        .confirm(&form.request_token, user.user_id)
+        .await
+    {
+        Some(code) => code,
+        None => return Ok(HttpResponse::NotFound().body("Unknown or expired request token")),
+    };
+    match code
+        .redirect_uri
+        .as_deref()
+        .and_then(|uri| url::Url::parse(uri).ok())
+    {
+        Some(uri) => Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, uri.as_str())
+            .finish()),
+        None => Ok(HttpResponse::Ok().body("Application is authorized, you can return to it")),
     }
 }
 
@@ -206,3 +849,293 @@ pub async fn auth(
         .header(http::header::LOCATION, "/")
         .finish())
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TokenView {
+    id: i64,
+    name: String,
+    scopes: String,
+    created_at: String,
+    last_used_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SettingsTemplate<'a> {
+    app_name: &'a str,
+    tokens: Vec<TokenView>,
+    new_token: Option<String>,
+    feed_token: Option<String>,
+    auto_archive: bool,
+    snapshots: bool,
+    user_id: i64,
+    page: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewTokenForm {
+    name: String,
+    #[serde(default)]
+    read: Option<String>,
+    #[serde(default)]
+    write: Option<String>,
+}
+
+pub(crate) fn format_time(ts: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(ts).format("%F %R")
+}
+
+async fn render_settings(
+    data: &AppState<'_>,
+    user: &AuthUser,
+    new_token: Option<String>,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    let tokens = data
+        .storage
+        .api_tokens(&user.user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|t| TokenView {
+            id: t.id,
+            name: t.name,
+            scopes: t.scopes,
+            created_at: format_time(t.created_at),
+            last_used_at: t.last_used_at.map(format_time).unwrap_or_default(),
+        })
+        .collect();
+    let feed_token = data
+        .storage
+        .feed_token(&user.user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let auto_archive = data
+        .storage
+        .auto_archive(&user.user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let snapshots = data
+        .storage
+        .snapshots_enabled(&user.user_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let json = json!(SettingsTemplate {
+        app_name: APP_NAME,
+        tokens,
+        new_token,
+        feed_token,
+        auto_archive,
+        snapshots,
+        user_id: user.user_id,
+        page: "settings"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[get("/settings")]
+pub async fn settings(
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require_session()?;
+    render_settings(&data, &user, None).await
+}
+
+#[post("/settings/tokens")]
+pub async fn create_token(
+    form: web::Form<NewTokenForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require_session()?;
+    let mut scopes = vec![];
+    if form.read.is_some() {
+        scopes.push(Scope::Read);
+    }
+    if form.write.is_some() {
+        scopes.push(Scope::Write);
+    }
+    if form.name.trim().is_empty() || scopes.is_empty() {
+        return Ok(HttpResponse::BadRequest().body("Token needs a name and at least one scope"));
+    }
+    let token = issue_api_token(&data.storage, &user.user_id, form.name.trim(), &scopes)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    render_settings(&data, &user, Some(token)).await
+}
+
+#[post("/settings/tokens/{token_id}/revoke")]
+pub async fn revoke_token(
+    web::Path(token_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require_session()?;
+    data.storage
+        .delete_api_token(&user.user_id, &token_id)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/settings")
+        .finish())
+}
+
+/// Creates the feed token of the user or replaces it, so old feed links stop working.
+#[post("/settings/feed")]
+pub async fn reset_feed_token(
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require_session()?;
+    data.storage
+        .set_feed_token(&user.user_id, &generate_token())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/settings")
+        .finish())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReadingForm {
+    /// Checkboxes, present only when they are checked.
+    auto_archive: Option<String>,
+    snapshots: Option<String>,
+}
+
+#[post("/settings/reading")]
+pub async fn save_reading_settings(
+    form: web::Form<ReadingForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require_session()?;
+    data.storage
+        .set_auto_archive(&user.user_id, form.auto_archive.is_some())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    data.storage
+        .set_snapshots_enabled(&user.user_id, form.snapshots.is_some())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/settings")
+        .finish())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ImportTemplate<'a> {
+    app_name: &'a str,
+    report: Option<ImportResult>,
+    error: Option<String>,
+    user_id: i64,
+    page: &'a str,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ImportResult {
+    imported: usize,
+    duplicates: usize,
+    invalid: usize,
+}
+
+fn render_import(
+    data: &AppState<'_>,
+    user: &AuthUser,
+    report: Option<ImportReport>,
+    error: Option<String>,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    let json = json!(ImportTemplate {
+        app_name: APP_NAME,
+        report: report.map(|r| ImportResult {
+            imported: r.imported,
+            duplicates: r.duplicates,
+            invalid: r.invalid,
+        }),
+        error,
+        user_id: user.user_id,
+        page: "import"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[get("/import")]
+pub async fn import_page(
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    render_import(&data, &user, None, None)
+}
+
+/// Imports the export file of Pocket, Instapaper or a bookmarks file sent as `file` field.
+#[post("/import")]
+pub async fn import_upload(
+    mut payload: Multipart,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let mut file = vec![];
+    while let Some(field) = payload.next().await {
+        let mut field = field?;
+        let is_file = field
+            .content_disposition()
+            .map(|d| d.get_name() == Some("file"))
+            .unwrap_or(false);
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk?;
+            if is_file {
+                if file.len() + chunk.len() > MAX_IMPORT_SIZE {
+                    return Ok(HttpResponse::PayloadTooLarge().body("Import file is too large"));
+                }
+                file.extend_from_slice(&chunk);
+            }
+        }
+    }
+    let text = match String::from_utf8(file) {
+        Ok(text) => text,
+        Err(_) => return render_import(&data, &user, None, Some("File must be in utf-8".into())),
+    };
+    match import(&data.storage, user.user_id, &text).await {
+        Ok(report) => render_import(&data, &user, Some(report), None),
+        Err(e) => render_import(&data, &user, None, Some(e.to_string())),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportQuery {
+    format: String,
+}
+
+#[get("/export")]
+pub async fn export_download(
+    web::Query(q): web::Query<ExportQuery>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> std::result::Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let format = match ExportFormat::parse(&q.format) {
+        Some(format) => format,
+        None => return Ok(HttpResponse::BadRequest().body("Unknown export format")),
+    };
+    let body = export(data.storage.clone(), user.user_id, format).map(|chunk| {
+        chunk
+            .map(web::Bytes::from)
+            .map_err(actix_web::error::ErrorInternalServerError)
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .header(
+            http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"save2read.{}\"", format.extension()),
+        )
+        .streaming(body))
+}
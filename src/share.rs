@@ -0,0 +1,274 @@
+use crate::api;
+use crate::auth::{generate_token, AuthUser, Scope};
+use crate::extractor::summary;
+use crate::routes::{format_time, see_other, AppState, APP_NAME};
+use crate::storage::{
+    normalize_tags, now, AnnotationKind, Article, ArticleFilter, ArticleStatus, ShareLink,
+    ShareTarget,
+};
+use actix_web::*;
+use serde::*;
+use serde_json::json;
+
+/// Articles shown on the page of a shared list, newest first.
+const SHARE_PAGE_SIZE: i64 = 100;
+/// Characters of the content shown as the summary of a shared article.
+const SUMMARY_LENGTH: usize = 300;
+/// Longest expiry of a share link, links without expiry work until revoked.
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+#[derive(Serialize, Debug)]
+struct ShareView {
+    id: i64,
+    url: String,
+    /// What the link shows, as a short text for the owner.
+    target: String,
+    expires_at: Option<String>,
+    expired: bool,
+}
+
+fn describe(target: &ShareTarget) -> String {
+    match target {
+        ShareTarget::Article(id) => format!("Article {}", id),
+        ShareTarget::List {
+            status,
+            tag,
+            starred,
+        } => {
+            let mut parts = vec![status.map_or("all", |s| s.as_str()).to_string()];
+            if let Some(tag) = tag {
+                parts.push(format!("tagged {}", tag));
+            }
+            if *starred == Some(true) {
+                parts.push("starred".to_string());
+            }
+            format!("List: {}", parts.join(", "))
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct SharesTemplate<'a> {
+    app_name: &'a str,
+    shares: Vec<ShareView>,
+    user_id: i64,
+    page: &'a str,
+}
+
+/// Share links of the user with their public address, to copy or revoke them.
+#[get("/shares")]
+pub async fn shares_page(
+    req: HttpRequest,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let base = {
+        let info = req.connection_info();
+        format!("{}://{}", info.scheme(), info.host())
+    };
+    let now = now();
+    let shares = data
+        .storage
+        .share_links(&user.user_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|s: ShareLink| ShareView {
+            id: s.id,
+            url: format!("{}/s/{}", base, s.token),
+            target: describe(&s.target),
+            expires_at: s.expires_at.map(format_time),
+            expired: s.expires_at.is_some_and(|t| t <= now),
+        })
+        .collect();
+    let json = json!(SharesTemplate {
+        app_name: APP_NAME,
+        shares,
+        user_id: user.user_id,
+        page: "shares"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ShareForm {
+    /// Shares the article, or else the articles matching the other fields.
+    article_id: Option<i64>,
+    status: Option<String>,
+    tag: Option<String>,
+    starred: Option<bool>,
+    /// Empty when the link works until revoked.
+    #[serde(default, deserialize_with = "api::lenient_number")]
+    expires_in_days: Option<i64>,
+}
+
+#[post("/shares")]
+pub async fn create_share(
+    form: web::Form<ShareForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let form = form.into_inner();
+    let target = match form.article_id {
+        Some(id) => ShareTarget::Article(id),
+        None => {
+            let status = match form.status.as_deref().filter(|s| !s.is_empty()) {
+                Some(status) => match ArticleStatus::parse(status) {
+                    Some(status) => Some(status),
+                    None => return Ok(HttpResponse::BadRequest().body("Unknown status")),
+                },
+                None => None,
+            };
+            ShareTarget::List {
+                status,
+                tag: normalize_tags(&form.tag.into_iter().collect::<Vec<String>>()).pop(),
+                starred: form.starred.filter(|s| *s),
+            }
+        }
+    };
+    let expires_at = match form.expires_in_days {
+        Some(days) if (1..=MAX_EXPIRY_DAYS).contains(&days) => Some(now() + days * 24 * 60 * 60),
+        Some(_) => {
+            return Ok(HttpResponse::BadRequest().body(format!(
+                "Expiry must be between 1 and {} days",
+                MAX_EXPIRY_DAYS
+            )))
+        }
+        None => None,
+    };
+    match data
+        .storage
+        .add_share_link(&user.user_id, &generate_token(), &target, expires_at)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(_) => Ok(see_other("/shares")),
+        None => Ok(HttpResponse::NotFound().body("Article not found")),
+    }
+}
+
+/// Revokes the share link, its public page stops working for good.
+#[post("/shares/{share_id}/revoke")]
+pub async fn revoke_share(
+    web::Path(share_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    if data
+        .storage
+        .revoke_share_link(&user.user_id, &share_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Ok(see_other("/shares"))
+    } else {
+        Ok(HttpResponse::NotFound().body("Share link not found"))
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct SharedArticle {
+    url: String,
+    title: String,
+    summary: Option<String>,
+    highlights: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct PublicShareTemplate<'a> {
+    app_name: &'a str,
+    title: String,
+    articles: Vec<SharedArticle>,
+}
+
+/// Read-only page of a share link, no login needed.
+/// Notes stay private, only the highlights are shown.
+#[get("/s/{token}")]
+pub async fn public_share(
+    web::Path(token): web::Path<String>,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    let storage = &data.storage;
+    let share = match storage
+        .share_link(&token)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(share) => share,
+        None => return Ok(HttpResponse::NotFound().body("Share link not found")),
+    };
+    let user_id = share.user_id;
+    let articles: Vec<Article> = match &share.target {
+        ShareTarget::Article(id) => storage
+            .get_article(&user_id, id)
+            .await
+            .map_err(error::ErrorInternalServerError)?
+            .into_iter()
+            .collect(),
+        ShareTarget::List {
+            status,
+            tag,
+            starred,
+        } => {
+            let filter = ArticleFilter {
+                status: *status,
+                tags: tag.iter().cloned().collect(),
+                starred: *starred,
+                limit: SHARE_PAGE_SIZE,
+                ..ArticleFilter::default()
+            };
+            storage
+                .articles(&user_id, &filter)
+                .await
+                .map_err(error::ErrorInternalServerError)?
+        }
+    };
+    if let ShareTarget::Article(_) = share.target {
+        if articles.is_empty() {
+            return Ok(HttpResponse::NotFound().body("Article not found"));
+        }
+    }
+    let mut shared = vec![];
+    for article in articles {
+        let content = storage
+            .get_content(&user_id, &article.id)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        let highlights = storage
+            .annotations(&user_id, &article.id)
+            .await
+            .map_err(error::ErrorInternalServerError)?
+            .into_iter()
+            .filter(|a| a.kind == AnnotationKind::Highlight)
+            .map(|a| a.text)
+            .collect();
+        let url = article.data.url.to_string();
+        shared.push(SharedArticle {
+            title: article.data.title.unwrap_or_else(|| url.clone()),
+            url,
+            summary: content.map(|c| summary(&c, SUMMARY_LENGTH)),
+            highlights,
+        });
+    }
+    let title = match &share.target {
+        ShareTarget::Article(_) => shared[0].title.clone(),
+        target => describe(target),
+    };
+    let json = json!(PublicShareTemplate {
+        app_name: APP_NAME,
+        title,
+        articles: shared,
+    });
+    let rendered = &data
+        .hb
+        .render("public_share", &json)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
@@ -0,0 +1,289 @@
+use crate::auth::{AuthUser, Scope};
+use crate::extractor::{fetch, parse_page};
+use crate::routes::{see_other, AppState};
+use crate::storage::Storage;
+use actix_web::client::Client;
+use actix_web::web::Bytes;
+use actix_web::*;
+use anyhow::{anyhow, Result};
+use ego_tree::NodeId;
+use lazy_static::*;
+use log::error;
+use regex::*;
+use scraper::{Html, Node, Selector};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Largest snapshot stored, resources which don't fit are left out of it.
+pub const MAX_SNAPSHOT_BYTES: usize = 10 * 1024 * 1024;
+/// Most resources fetched for a snapshot or a book and the time spent on them,
+/// the others keep their urls or are left out.
+pub(crate) const MAX_RESOURCES: usize = 50;
+pub(crate) const MAX_RESOURCES_TIME: Duration = Duration::from_secs(60);
+/// Time to fetch the page or one of its resources.
+pub(crate) const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The snapshot is served from the app domain, so it must not run scripts
+/// and it must not load anything, all it needs is inlined.
+const SNAPSHOT_POLICY: &str =
+    "default-src 'none'; img-src data:; style-src 'unsafe-inline' data:; font-src data:; sandbox";
+
+lazy_static! {
+    static ref CSS_URL_REGEXP: Regex = Regex::new(r#"url\(\s*['"]?([^'")]+?)['"]?\s*\)"#).unwrap();
+}
+
+/// Fetches the resources of the page while they fit in the size, count and time left for them.
+struct Inliner<'a> {
+    client: &'a Client,
+    budget: usize,
+    fetches_left: usize,
+    deadline: Instant,
+}
+
+impl Inliner<'_> {
+    async fn fetch(&mut self, url: &Url) -> Option<(Bytes, Option<String>)> {
+        if self.fetches_left == 0 || Instant::now() >= self.deadline {
+            return None;
+        }
+        self.fetches_left -= 1;
+        match fetch(self.client, url.as_str(), self.budget).await {
+            Ok(Some((body, content_type))) => {
+                // Base64 takes a third more than the data.
+                let size = body.len() / 3 * 4 + 4;
+                if size > self.budget {
+                    return None;
+                }
+                self.budget -= size;
+                Some((body, content_type))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                error!("{:?}", e);
+                None
+            }
+        }
+    }
+
+    async fn image(&mut self, url: &Url) -> Option<String> {
+        let (body, content_type) = self.fetch(url).await?;
+        Some(data_uri(
+            &body,
+            content_type
+                .as_deref()
+                .unwrap_or("application/octet-stream"),
+        ))
+    }
+
+    async fn stylesheet(&mut self, url: &Url) -> Option<String> {
+        let (body, _) = self.fetch(url).await?;
+        let css = self.css(&String::from_utf8_lossy(&body), url).await;
+        Some(data_uri(css.as_bytes(), "text/css"))
+    }
+
+    /// Inlines images and fonts of the stylesheet, those which don't fit get absolute urls.
+    async fn css(&mut self, css: &str, base: &Url) -> String {
+        let mut result = String::new();
+        let mut last = 0;
+        for caps in CSS_URL_REGEXP.captures_iter(css) {
+            let (whole, target) = (caps.get(0).unwrap(), caps.get(1).unwrap());
+            result.push_str(&css[last..whole.start()]);
+            last = whole.end();
+            let target = target.as_str();
+            if target.starts_with("data:") || target.starts_with('#') {
+                result.push_str(whole.as_str());
+                continue;
+            }
+            let replacement = match base.join(target) {
+                Ok(url) => match self.image(&url).await {
+                    Some(uri) => uri,
+                    None => url.to_string(),
+                },
+                Err(_) => target.to_string(),
+            };
+            result.push_str(&format!("url(\"{}\")", replacement));
+        }
+        result.push_str(&css[last..]);
+        result
+    }
+}
+
+fn data_uri(data: &[u8], content_type: &str) -> String {
+    format!(
+        "data:{};base64,{}",
+        content_type,
+        openssl::base64::encode_block(data)
+    )
+}
+
+fn select(doc: &Html, selector: &str) -> Vec<NodeId> {
+    let selector = Selector::parse(selector).unwrap();
+    doc.select(&selector).map(|e| e.id()).collect()
+}
+
+fn attr(doc: &Html, id: NodeId, name: &str) -> Option<String> {
+    match doc.tree.get(id).map(|n| n.value()) {
+        Some(Node::Element(el)) => el.attr(name).map(|v| v.to_string()),
+        _ => None,
+    }
+}
+
+fn set_attr(doc: &mut Html, id: NodeId, name: &str, value: &str) {
+    if let Some(mut node) = doc.tree.get_mut(id) {
+        if let Node::Element(el) = node.value() {
+            for (key, v) in el.attrs.iter_mut() {
+                if &*key.local == name {
+                    *v = value.into();
+                }
+            }
+        }
+    }
+}
+
+fn remove_attr(doc: &mut Html, id: NodeId, name: &str) {
+    if let Some(mut node) = doc.tree.get_mut(id) {
+        if let Node::Element(el) = node.value() {
+            el.attrs.retain(|key, _| &*key.local != name);
+        }
+    }
+}
+
+/// Self-contained copy of the page: stylesheets and images are inlined as data uris,
+/// scripts are dropped and links are made absolute.
+pub async fn snapshot(url: &Url, max_bytes: usize) -> Result<String> {
+    let client = Client::builder().timeout(FETCH_TIMEOUT).finish();
+    let (page, _) = fetch(&client, url.as_str(), max_bytes)
+        .await?
+        .ok_or_else(|| anyhow!("Can't fetch {} for the snapshot", url))?;
+    let mut doc = parse_page(&page);
+    let mut inliner = Inliner {
+        client: &client,
+        budget: max_bytes.saturating_sub(page.len()),
+        fetches_left: MAX_RESOURCES,
+        deadline: Instant::now() + MAX_RESOURCES_TIME,
+    };
+    let base = select(&doc, "base[href]")
+        .first()
+        .and_then(|id| attr(&doc, *id, "href"))
+        .and_then(|href| url.join(&href).ok())
+        .unwrap_or_else(|| url.clone());
+    for id in select(&doc, "script, noscript, iframe, object, embed, base") {
+        if let Some(mut node) = doc.tree.get_mut(id) {
+            node.detach();
+        }
+    }
+    for id in select(&doc, "link[rel~=stylesheet][href]") {
+        if let Some(href) = attr(&doc, id, "href").and_then(|h| base.join(&h).ok()) {
+            let inlined = inliner.stylesheet(&href).await;
+            set_attr(
+                &mut doc,
+                id,
+                "href",
+                &inlined.unwrap_or_else(|| href.to_string()),
+            );
+        }
+    }
+    for id in select(&doc, "style") {
+        let texts: Vec<NodeId> = match doc.tree.get(id) {
+            Some(node) => node.children().map(|c| c.id()).collect(),
+            None => vec![],
+        };
+        for text_id in texts {
+            let css = match doc.tree.get(text_id).map(|n| n.value()) {
+                Some(Node::Text(text)) => text.text.to_string(),
+                _ => continue,
+            };
+            let inlined = inliner.css(&css, &base).await;
+            if let Some(mut node) = doc.tree.get_mut(text_id) {
+                if let Node::Text(text) = node.value() {
+                    text.text = inlined.as_str().into();
+                }
+            }
+        }
+    }
+    for id in select(&doc, "img[src]") {
+        if let Some(src) = attr(&doc, id, "src").and_then(|s| base.join(&s).ok()) {
+            let inlined = inliner.image(&src).await;
+            set_attr(
+                &mut doc,
+                id,
+                "src",
+                &inlined.unwrap_or_else(|| src.to_string()),
+            );
+            remove_attr(&mut doc, id, "srcset");
+        }
+    }
+    for id in select(&doc, "a[href]") {
+        if let Some(href) = attr(&doc, id, "href").and_then(|h| base.join(&h).ok()) {
+            set_attr(&mut doc, id, "href", href.as_str());
+        }
+    }
+    let html = format!("<!DOCTYPE html>\n{}", doc.root_element().html());
+    if html.len() > max_bytes {
+        return Err(anyhow!(
+            "Snapshot of {} takes {} bytes, more than {}",
+            url,
+            html.len(),
+            max_bytes
+        ));
+    }
+    Ok(html)
+}
+
+/// Takes the snapshot of the saved link replacing the previous one,
+/// returns false if the user has no such article.
+pub async fn take_snapshot(storage: &Storage, user_id: &i64, id: &i64) -> Result<bool> {
+    let article = match storage.get_article(user_id, id).await? {
+        Some(article) => article,
+        None => return Ok(false),
+    };
+    let html = snapshot(&article.data.url, MAX_SNAPSHOT_BYTES).await?;
+    storage.set_snapshot(user_id, id, &html).await
+}
+
+/// Takes the snapshot in the background, so saving the link doesn't wait for the page resources.
+pub fn take_snapshot_later(storage: Storage, user_id: i64, id: i64) {
+    actix_rt::spawn(async move {
+        if let Err(e) = take_snapshot(&storage, &user_id, &id).await {
+            error!("{:?}", e);
+        }
+    });
+}
+
+#[post("/snapshot/{link_id}")]
+pub async fn create_snapshot(
+    web::Path(link_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    match take_snapshot(&data.storage, &user.user_id, &link_id).await {
+        Ok(true) => Ok(see_other(&format!("/read/{}", link_id))),
+        Ok(false) => Ok(HttpResponse::NotFound().body("Article not found")),
+        Err(e) => {
+            error!("{:?}", e);
+            Ok(HttpResponse::BadGateway().body("Can't take a snapshot of the page"))
+        }
+    }
+}
+
+/// Stored copy of the page, readable when the original is gone.
+#[get("/snapshot/{link_id}")]
+pub async fn snapshot_page(
+    web::Path(link_id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    match data
+        .storage
+        .snapshot(&user.user_id, &link_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(html) => Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .header(http::header::CONTENT_SECURITY_POLICY, SNAPSHOT_POLICY)
+            .body(html)),
+        None => Ok(HttpResponse::NotFound().body("Snapshot not found")),
+    }
+}
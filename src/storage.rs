@@ -1,21 +1,190 @@
+use crate::extractor;
+
 use anyhow::{Context, Result};
 use sqlx::sqlite::Sqlite;
-use sqlx::Row;
-use sqlx::{query, Pool};
+use sqlx::{query, Pool, Transaction};
+use sqlx::{Done, Row};
 use url::Url;
 
 pub static PENDING_LINKS_TABLE: &str = "pending_links";
 pub static ARCHIVED_LINKS_TABLE: &str = "archived_links";
+pub static API_TOKENS_TABLE: &str = "api_tokens";
+pub static ARTICLE_TAGS_TABLE: &str = "article_tags";
+pub static FEED_TOKENS_TABLE: &str = "feed_tokens";
+pub static UNDO_ACTIONS_TABLE: &str = "undo_actions";
+pub static ANNOTATIONS_TABLE: &str = "annotations";
+pub static USER_SETTINGS_TABLE: &str = "user_settings";
+pub static COLLECTIONS_TABLE: &str = "collections";
+pub static COLLECTION_ITEMS_TABLE: &str = "collection_items";
+pub static TEAMS_TABLE: &str = "teams";
+pub static TEAM_MEMBERS_TABLE: &str = "team_members";
+pub static SHARE_LINKS_TABLE: &str = "share_links";
+pub static SNAPSHOTS_TABLE: &str = "snapshots";
+
+/// Team lists are owned by accounts below any Telegram chat id, so the links of a team
+/// are kept like the links of a user, by `TEAM_ACCOUNT_OFFSET + team id` negated.
+/// Teams of group chats use the id of the chat instead.
+pub const TEAM_ACCOUNT_OFFSET: i64 = 1 << 52;
+
+/// Health checks in a row without response after which a link is dead.
+pub const DEAD_AFTER_FAILED_CHECKS: i64 = 3;
+
+/// Seconds during which the last action on an article can be undone.
+pub const UNDO_WINDOW: i64 = 60;
 
+/// Columns added to both links tables after their creation, applied on init if missing.
+static LINK_COLUMNS_MIGRATIONS: &[(&str, &str)] = &[
+    ("created_at", "INTEGER NULL"),
+    ("content", "TEXT NULL"),
+    ("updated_at", "INTEGER NULL"),
+    ("deleted_at", "INTEGER NULL"),
+    ("starred", "INTEGER NOT NULL DEFAULT 0"),
+    ("progress", "INTEGER NOT NULL DEFAULT 0"),
+    ("position", "INTEGER NULL"),
+    ("word_count", "INTEGER NULL"),
+    ("reading_minutes", "INTEGER NULL"),
+    ("http_status", "INTEGER NULL"),
+    ("final_url", "TEXT NULL"),
+    ("checked_at", "INTEGER NULL"),
+    ("failed_checks", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// Columns shared by pending and archived tables, used to move links between them
+/// with the same id, so the id of an article is stable whatever its status is.
+static LINK_COLUMNS: &str =
+    "id, user_id, url, title, created_at, content, updated_at, deleted_at, \
+    starred, progress, position, word_count, reading_minutes, http_status, final_url, checked_at, \
+    failed_checks";
+
+#[derive(Clone)]
 pub struct Storage {
     pool: Pool<Sqlite>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArticleStatus {
+    Pending,
+    Archived,
+}
+
+impl ArticleStatus {
+    pub fn table(&self) -> &'static str {
+        match self {
+            ArticleStatus::Pending => PENDING_LINKS_TABLE,
+            ArticleStatus::Archived => ARCHIVED_LINKS_TABLE,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArticleStatus::Pending => "pending",
+            ArticleStatus::Archived => "archived",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<ArticleStatus> {
+        match status {
+            "pending" => Some(ArticleStatus::Pending),
+            "archived" => Some(ArticleStatus::Archived),
+            _ => None,
+        }
+    }
+}
+
 pub struct Article {
     pub id: i64,
+    pub status: ArticleStatus,
+    pub created_at: Option<i64>,
+    /// Last time the status or the title of the article was changed.
+    pub updated_at: Option<i64>,
+    /// Time the article was moved to the trash, trashed articles are in `trash` only.
+    pub deleted_at: Option<i64>,
+    /// Marked by the user as worth re-reading, whatever the status is.
+    pub starred: bool,
+    /// Percentage of the article read in the reader view, the furthest point reached.
+    pub progress: i64,
+    /// Last scroll position in the reader view, in pixels.
+    pub position: Option<i64>,
+    /// Words of the extracted content, unknown until the content is fetched.
+    pub word_count: Option<i64>,
+    /// Estimated reading time in minutes, computed with the word count.
+    pub reading_minutes: Option<i64>,
+    /// Status of the last response to a health check, none if the link never answered.
+    pub http_status: Option<i64>,
+    /// Url the link redirected to on the last health check, when it differs.
+    pub final_url: Option<String>,
+    /// Time of the last health check, none if the link was never checked.
+    pub checked_at: Option<i64>,
+    /// Health checks in a row which got no response at all.
+    pub failed_checks: i64,
+    pub tags: Vec<String>,
     pub data: ArticleData,
 }
 
+impl Article {
+    /// Article of the user 1 with nothing but the id, url and status set, for unit tests.
+    #[cfg(test)]
+    pub(crate) fn for_test(id: i64, url: &str, status: ArticleStatus) -> Article {
+        Article {
+            id,
+            status,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            starred: false,
+            progress: 0,
+            position: None,
+            word_count: None,
+            reading_minutes: None,
+            http_status: None,
+            final_url: None,
+            checked_at: None,
+            failed_checks: 0,
+            tags: vec![],
+            data: ArticleData {
+                user_id: 1,
+                url: Url::parse(url).unwrap(),
+                title: None,
+            },
+        }
+    }
+
+    /// Whether the link is gone: the site answered it's not found for good, or it didn't
+    /// answer for several checks in a row. Other errors are often temporary or anti-bot
+    /// pages, and a single failed check may be a network problem of the server.
+    pub fn is_dead(&self) -> bool {
+        matches!(self.http_status, Some(404) | Some(410))
+            || self.failed_checks >= DEAD_AFTER_FAILED_CHECKS
+    }
+
+    /// Snapshot of the page in the Internet Archive closest to the time it was saved.
+    pub fn archive_url(&self) -> String {
+        match self.created_at {
+            Some(at) => format!(
+                "https://web.archive.org/web/{}/{}",
+                time::OffsetDateTime::from_unix_timestamp(at).format("%Y%m%d%H%M%S"),
+                self.data.url
+            ),
+            None => format!("https://web.archive.org/web/{}", self.data.url),
+        }
+    }
+}
+
+/// Keyset filter for the articles list: items are ordered by id descending
+/// and `before` is the id of the last item of the previous page.
+#[derive(Clone, Debug, Default)]
+pub struct ArticleFilter {
+    pub status: Option<ArticleStatus>,
+    /// Articles must have all of the tags.
+    pub tags: Vec<String>,
+    pub starred: Option<bool>,
+    /// Articles which can be read in that many minutes, those with unknown time are excluded.
+    pub max_minutes: Option<i64>,
+    pub before: Option<i64>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
 #[derive(Clone)]
 pub struct ArticleData {
     pub user_id: i64,
@@ -23,6 +192,170 @@ pub struct ArticleData {
     pub title: Option<String>,
 }
 
+/// Action of the user on an article which can be undone during `UNDO_WINDOW`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UndoAction {
+    Archive,
+    Unarchive,
+    Delete,
+}
+
+impl UndoAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UndoAction::Archive => "archive",
+            UndoAction::Unarchive => "unarchive",
+            UndoAction::Delete => "delete",
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<UndoAction> {
+        match action {
+            "archive" => Some(UndoAction::Archive),
+            "unarchive" => Some(UndoAction::Unarchive),
+            "delete" => Some(UndoAction::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Action applied at once to the articles selected in a list.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BulkAction {
+    Archive,
+    Unarchive,
+    Delete,
+    /// Adds the tags, keeping the ones the articles already have.
+    Tag(Vec<String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnnotationKind {
+    /// Quote from the article.
+    Highlight,
+    /// Thought of the reader about the article.
+    Note,
+}
+
+impl AnnotationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnnotationKind::Highlight => "highlight",
+            AnnotationKind::Note => "note",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<AnnotationKind> {
+        match kind {
+            "highlight" => Some(AnnotationKind::Highlight),
+            "note" => Some(AnnotationKind::Note),
+            _ => None,
+        }
+    }
+}
+
+/// Highlight or note attached to an article.
+#[derive(Clone, Debug)]
+pub struct Annotation {
+    pub id: i64,
+    pub article_id: i64,
+    pub kind: AnnotationKind,
+    pub text: String,
+    /// Position of the highlight in the content, as set by the reader view.
+    pub anchor: Option<String>,
+    pub created_at: i64,
+    pub updated_at: Option<i64>,
+}
+
+/// Reading list curated by the user, its articles are kept in a manual order.
+#[derive(Clone, Debug)]
+pub struct Collection {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    /// Token of the read-only public page, `None` while the collection is private.
+    pub public_token: Option<String>,
+    pub created_at: i64,
+}
+
+/// Permissions of a member in a team, every role can read the team list.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    /// Manages members, besides editing.
+    Owner,
+    /// Saves, archives and deletes links.
+    Editor,
+    Viewer,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "owner" => Some(Role::Owner),
+            "editor" => Some(Role::Editor),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+
+    pub fn can_write(&self) -> bool {
+        *self != Role::Viewer
+    }
+}
+
+/// Shared reading list, its links belong to `account_id` instead of a user.
+#[derive(Clone, Debug)]
+pub struct Team {
+    pub id: i64,
+    pub name: String,
+    pub account_id: i64,
+    /// Telegram group chat saving links to the team list.
+    pub chat_id: Option<i64>,
+    pub created_at: i64,
+}
+
+/// What a share link shows: one article, or the articles matching a filter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShareTarget {
+    Article(i64),
+    List {
+        status: Option<ArticleStatus>,
+        tag: Option<String>,
+        starred: Option<bool>,
+    },
+}
+
+/// Read-only access to some articles of a user for anyone with the token.
+#[derive(Clone, Debug)]
+pub struct ShareLink {
+    pub id: i64,
+    pub user_id: i64,
+    pub token: String,
+    pub target: ShareTarget,
+    pub created_at: i64,
+    /// The link stops working after that time, it works until revoked without it.
+    pub expires_at: Option<i64>,
+}
+
+/// Personal access token record. Only the hash of the token is kept in the storage.
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub scopes: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
 impl Storage {
     pub async fn init(pool: Pool<Sqlite>) -> Result<Storage> {
         sqlx::query(
@@ -40,19 +373,280 @@ impl Storage {
                 title TEXT NULL,
                 url TEXT NOT NULL
             );
+
+            CREATE TABLE IF NOT EXISTS api_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                last_used_at INTEGER NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS article_tags (
+                article_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (article_id, tag)
+            );
+
+            CREATE TABLE IF NOT EXISTS undo_actions (
+                article_id INTEGER PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS collections (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                public_token TEXT NULL UNIQUE,
+                created_at INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS collection_items (
+                collection_id INTEGER NOT NULL,
+                article_id INTEGER NOT NULL,
+                position INTEGER NOT NULL,
+                PRIMARY KEY (collection_id, article_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS teams (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                account_id INTEGER NULL UNIQUE,
+                chat_id INTEGER NULL UNIQUE,
+                created_at INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS team_members (
+                team_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                role TEXT NOT NULL,
+                PRIMARY KEY (team_id, user_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS share_links (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                token TEXT NOT NULL UNIQUE,
+                article_id INTEGER NULL,
+                status TEXT NULL,
+                tag TEXT NULL,
+                starred INTEGER NULL,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS annotations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                article_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                text TEXT NOT NULL,
+                anchor TEXT NULL,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS snapshots (
+                article_id INTEGER PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                content BLOB NOT NULL,
+                size INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS user_settings (
+                user_id INTEGER PRIMARY KEY,
+                auto_archive INTEGER NOT NULL DEFAULT 0
+            );
+
+            CREATE TABLE IF NOT EXISTS feed_tokens (
+                user_id INTEGER PRIMARY KEY,
+                token TEXT NOT NULL UNIQUE,
+                created_at INTEGER NOT NULL
+            );
             ",
         )
         .execute(&pool)
         .await
         .with_context(|| format!("Can't init the database with needed tables"))?;
-        Ok(Storage { pool })
+        let storage = Storage { pool };
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            for (column, definition) in LINK_COLUMNS_MIGRATIONS {
+                storage.add_column(table, column, definition).await?;
+            }
+            storage.count_words(table).await?;
+        }
+        storage.separate_link_ids().await?;
+        storage
+            .add_column(
+                USER_SETTINGS_TABLE,
+                "snapshots",
+                "INTEGER NOT NULL DEFAULT 0",
+            )
+            .await?;
+        Ok(storage)
+    }
+
+    async fn add_column(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let exists: Vec<sqlx::sqlite::SqliteRow> =
+            query("SELECT name FROM pragma_table_info(?) where name = ?")
+                .bind(table)
+                .bind(column)
+                .fetch_all(&self.pool)
+                .await
+                .with_context(|| format!("Can't get columns of the table {}", table))?;
+        if exists.is_empty() {
+            query(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, definition
+            ))
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't add column {} to the table {}", column, table))?;
+        }
+        Ok(())
+    }
+
+    /// Gives a new id to archived links sharing their id with a pending link, as links
+    /// archived before ids became stable do. Rows of other tables keyed by the id stay with
+    /// the pending link unless they belong to the user of the archived one. New ids, and
+    /// those of links added later, are above any id of both tables.
+    async fn separate_link_ids(&self) -> Result<()> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .context("Can't start db transaction for separating link ids")?;
+        let clashes: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT a.id, a.user_id as archived_user, p.user_id as pending_user \
+            FROM {} a JOIN {} p ON a.id = p.id",
+            ARCHIVED_LINKS_TABLE, PENDING_LINKS_TABLE
+        ))
+        .fetch_all(&mut tx)
+        .await
+        .context("Can't get archived links clashing with pending ones")?;
+        let last: Option<i64> = query(&format!(
+            "SELECT max(id) as last FROM (SELECT id FROM {} UNION ALL SELECT id FROM {} \
+            UNION ALL SELECT seq as id FROM sqlite_sequence where name in (?, ?))",
+            PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE
+        ))
+        .bind(PENDING_LINKS_TABLE)
+        .bind(ARCHIVED_LINKS_TABLE)
+        .fetch_one(&mut tx)
+        .await
+        .context("Can't get the last link id")?
+        .try_get("last")?;
+        let mut last = last.unwrap_or(0);
+        for r in clashes.iter() {
+            let id: i64 = r.try_get("id")?;
+            let archived_user: i64 = r.try_get("archived_user")?;
+            let pending_user: i64 = r.try_get("pending_user")?;
+            last += 1;
+            query(&format!(
+                "UPDATE {} SET id = ? where id = ?",
+                ARCHIVED_LINKS_TABLE
+            ))
+            .bind(last)
+            .bind(id)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("Can't give a new id to the archived link {}", id))?;
+            if archived_user == pending_user {
+                continue;
+            }
+            for table in &[
+                ARTICLE_TAGS_TABLE,
+                UNDO_ACTIONS_TABLE,
+                ANNOTATIONS_TABLE,
+                SHARE_LINKS_TABLE,
+                SNAPSHOTS_TABLE,
+            ] {
+                query(&format!(
+                    "UPDATE {} SET article_id = ? where article_id = ? and user_id = ?",
+                    table
+                ))
+                .bind(last)
+                .bind(id)
+                .bind(archived_user)
+                .execute(&mut tx)
+                .await
+                .with_context(|| format!("Can't move {} of the archived link {}", table, id))?;
+            }
+            query(&format!(
+                "UPDATE {} SET article_id = ? where article_id = ? \
+                and collection_id in (SELECT id FROM {} where user_id = ?)",
+                COLLECTION_ITEMS_TABLE, COLLECTIONS_TABLE
+            ))
+            .bind(last)
+            .bind(id)
+            .bind(archived_user)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("Can't move collection items of the archived link {}", id))?;
+        }
+        // Links are only added as pending, its sequence keeps new ids above all others.
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            query("DELETE FROM sqlite_sequence where name = ?")
+                .bind(table)
+                .execute(&mut tx)
+                .await
+                .with_context(|| format!("Can't reset the id sequence of {}", table))?;
+            query("INSERT INTO sqlite_sequence(name, seq) values(?, ?)")
+                .bind(table)
+                .bind(last)
+                .execute(&mut tx)
+                .await
+                .with_context(|| format!("Can't set the id sequence of {}", table))?;
+        }
+        tx.commit()
+            .await
+            .context("Can't commit transaction for separating link ids")?;
+        Ok(())
+    }
+
+    /// Fills the word count of links fetched before it was computed on save.
+    async fn count_words(&self, table: &str) -> Result<()> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT id, content FROM {} where content IS NOT NULL and word_count IS NULL",
+            table
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get links without word count in {}", table))?;
+        for r in rows.iter() {
+            let id: i64 = r.try_get("id")?;
+            let words = extractor::word_count(&r.try_get::<String, &str>("content")?);
+            query(&format!(
+                "UPDATE {} SET word_count = ?, reading_minutes = ? where id = ?",
+                table
+            ))
+            .bind(words)
+            .bind(extractor::reading_minutes(words))
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't update word count of the link {}", id))?;
+        }
+        Ok(())
     }
 
     pub async fn add(&self, article: ArticleData) -> Result<i64> {
-        query("INSERT INTO pending_links(user_id, url, title) values(?, ?, ?);")
+        self.add_at(article, now()).await
+    }
+
+    /// Adds the pending link saved at the given time, e.g. when it comes from an import.
+    pub async fn add_at(&self, article: ArticleData, created_at: i64) -> Result<i64> {
+        query("INSERT INTO pending_links(user_id, url, title, created_at, updated_at) values(?, ?, ?, ?, ?);")
             .bind(article.user_id)
             .bind(article.url.to_string())
             .bind(article.title)
+            .bind(created_at)
+            .bind(now())
             .execute(&self.pool)
             .await
             .with_context(|| format!("Can't insert pending link to the storage"))
@@ -61,98 +655,46 @@ impl Storage {
 
     pub async fn archive(&self, user_id: &i64, id: &i64) -> Result<Option<i64>> {
         match self.get_pending_url(id).await? {
-            Some(article_data) => {
-                if &article_data.user_id == user_id {
-                    query("BEGIN")
-                        .execute(&self.pool)
-                        .await
-                        .with_context(|| format!("Can't start db transaction for archive item"))?;
-
-                    let archived_id = query(&format!(
-                        "INSERT INTO {}(user_id, url, title) values(?, ?, ?);",
-                        ARCHIVED_LINKS_TABLE
-                    ))
-                    .bind(article_data.user_id)
-                    .bind(article_data.url.to_string())
-                    .bind(article_data.title.clone())
-                    .execute(&self.pool)
-                    .await
-                    .with_context(|| format!("Can't insert link to move it to archived"))
-                    .map(|done| done.last_insert_rowid())?;
-
-                    query(&format!("DELETE FROM {} where id = ?", PENDING_LINKS_TABLE))
-                        .bind(id)
-                        .execute(&self.pool)
-                        .await
-                        .with_context(|| {
-                            format!("Can't delete the link from storage to move it to archived")
-                        })?;
-                    query("COMMIT").execute(&self.pool).await.with_context(|| {
-                        format!(
-                            "Can't commit transaction for archive the item {} for user {}",
-                            article_data.url.to_string(),
-                            article_data.user_id
-                        )
-                    })?;
-                    Ok(Some(archived_id))
-                } else {
-                    Ok(None)
-                }
-            }
-            None => Ok(None),
+            Some(article_data) if &article_data.user_id == user_id => self
+                .move_link(id, ArticleStatus::Pending, ArticleStatus::Archived)
+                .await
+                .map(Some),
+            _ => Ok(None),
         }
     }
 
     pub async fn unarchive(&self, user_id: &i64, id: &i64) -> Result<Option<i64>> {
         match self.get_archived_url(id).await? {
-            Some(article_data) => {
-                if &article_data.user_id == user_id {
-                    query("BEGIN").execute(&self.pool).await.with_context(|| {
-                        format!("Can't start db transaction for unarchive item")
-                    })?;
-
-                    let pending_id = query(&format!(
-                        "INSERT INTO {}(user_id, url, title) values(?, ?, ?);",
-                        PENDING_LINKS_TABLE
-                    ))
-                    .bind(article_data.user_id)
-                    .bind(article_data.url.to_string())
-                    .bind(article_data.title.clone())
-                    .execute(&self.pool)
-                    .await
-                    .with_context(|| {
-                        format!("Can't insert link to move it to pending from archive")
-                    })
-                    .map(|done| done.last_insert_rowid())?;
-
-                    query(&format!("DELETE FROM {} where id = ?", ARCHIVED_LINKS_TABLE))
-                        .bind(id)
-                        .execute(&self.pool)
-                        .await
-                        .with_context(|| {
-                            format!("Can't delete the link from storage to move it to pending from archived")
-                        })?;
-                    query("COMMIT").execute(&self.pool).await.with_context(|| {
-                        format!(
-                            "Can't commit transaction for unarchive the item {} for user {}",
-                            article_data.url.to_string(),
-                            article_data.user_id
-                        )
-                    })?;
-                    Ok(Some(pending_id))
-                } else {
-                    Ok(None)
-                }
-            }
-            None => Ok(None),
+            Some(article_data) if &article_data.user_id == user_id => self
+                .move_link(id, ArticleStatus::Archived, ArticleStatus::Pending)
+                .await
+                .map(Some),
+            _ => Ok(None),
         }
     }
 
+    /// Moves the link between tables keeping its id, ids are unique across both tables
+    /// since `separate_link_ids`.
+    async fn move_link(&self, id: &i64, from: ArticleStatus, to: ArticleStatus) -> Result<i64> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .with_context(|| format!("Can't start db transaction for moving item {}", id))?;
+        let new_id = move_link_in(&mut tx, id, from, to).await?;
+        tx.commit()
+            .await
+            .with_context(|| format!("Can't commit transaction for moving item {}", id))?;
+        Ok(new_id)
+    }
+
+    /// Moves the link to the trash, it's deleted permanently by `purge_trash` or `empty_trash`.
     pub async fn delete_archived(&self, user_id: &i64, id: &i64) -> Result<()> {
         query(&format!(
-            "DELETE FROM {} where id = ? and user_id = ?",
+            "UPDATE {} SET deleted_at = ? where id = ? and user_id = ? and deleted_at IS NULL",
             ARCHIVED_LINKS_TABLE
         ))
+        .bind(now())
         .bind(id)
         .bind(user_id)
         .execute(&self.pool)
@@ -161,11 +703,13 @@ impl Storage {
         Ok(())
     }
 
+    /// Moves the link to the trash, see `delete_archived`.
     pub async fn delete_pending(&self, user_id: &i64, id: &i64) -> Result<()> {
         query(&format!(
-            "DELETE FROM {} where id = ? and user_id = ?",
+            "UPDATE {} SET deleted_at = ? where id = ? and user_id = ? and deleted_at IS NULL",
             PENDING_LINKS_TABLE
         ))
+        .bind(now())
         .bind(id)
         .bind(user_id)
         .execute(&self.pool)
@@ -174,6 +718,300 @@ impl Storage {
         Ok(())
     }
 
+    /// Remembers the action to undo it later, replacing the previous action on the article.
+    pub async fn add_undo(&self, user_id: &i64, id: &i64, action: UndoAction) -> Result<()> {
+        query(&format!(
+            "DELETE FROM {} where created_at < ?",
+            UNDO_ACTIONS_TABLE
+        ))
+        .bind(now() - UNDO_WINDOW)
+        .execute(&self.pool)
+        .await
+        .context("Can't delete expired undo actions")?;
+        query(&format!(
+            "INSERT OR REPLACE INTO {}(article_id, user_id, action, created_at) values(?, ?, ?, ?)",
+            UNDO_ACTIONS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .bind(action.as_str())
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't save undo action for the link {}", id))?;
+        Ok(())
+    }
+
+    /// Reverts the last action on the article if it's not older than `UNDO_WINDOW`,
+    /// returns the status of the article after that.
+    pub async fn undo(&self, user_id: &i64, id: &i64) -> Result<Option<ArticleStatus>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT action FROM {} where article_id = ? and user_id = ? and created_at >= ?",
+            UNDO_ACTIONS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .bind(now() - UNDO_WINDOW)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get undo action for the link {}", id))?;
+        let action: String = match rows.first() {
+            Some(row) => row.try_get("action")?,
+            None => return Ok(None),
+        };
+        query(&format!(
+            "DELETE FROM {} where article_id = ?",
+            UNDO_ACTIONS_TABLE
+        ))
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't delete undo action for the link {}", id))?;
+        match UndoAction::parse(&action) {
+            Some(UndoAction::Archive) => Ok(self
+                .unarchive(user_id, id)
+                .await?
+                .map(|_| ArticleStatus::Pending)),
+            Some(UndoAction::Unarchive) => Ok(self
+                .archive(user_id, id)
+                .await?
+                .map(|_| ArticleStatus::Archived)),
+            Some(UndoAction::Delete) => self.restore(user_id, id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Takes the article out of the trash, returns its status if it was there.
+    pub async fn restore(&self, user_id: &i64, id: &i64) -> Result<Option<ArticleStatus>> {
+        for status in &[ArticleStatus::Pending, ArticleStatus::Archived] {
+            let done = query(&format!(
+                "UPDATE {} SET deleted_at = NULL where id = ? and user_id = ? and deleted_at IS NOT NULL",
+                status.table()
+            ))
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't restore the link {}", id))?;
+            if done.rows_affected() > 0 {
+                return Ok(Some(*status));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Trashed articles of the user, the most recently deleted first.
+    pub async fn trash(&self, user_id: &i64) -> Result<Vec<Article>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where user_id = ? order by deleted_at desc, id desc",
+            links_view_where("deleted_at IS NOT NULL")
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get trash of user {}", user_id))?;
+        rows.iter().map(article).collect()
+    }
+
+    /// Permanently deletes trashed articles of the user.
+    pub async fn empty_trash(&self, user_id: &i64) -> Result<u64> {
+        self.purge("user_id = ? and deleted_at IS NOT NULL", *user_id)
+            .await
+            .with_context(|| format!("Can't empty trash of user {}", user_id))
+    }
+
+    /// Links of all users not checked since the time, never checked ones first.
+    pub async fn links_to_check(&self, checked_before: i64, limit: i64) -> Result<Vec<(i64, Url)>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT id, url FROM {} where checked_at IS NULL or checked_at < ? \
+            order by checked_at IS NOT NULL, checked_at limit ?",
+            links_view()
+        ))
+        .bind(checked_before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Can't get links to check")?;
+        rows.iter()
+            .map(|r| {
+                let url: String = r.try_get("url")?;
+                Ok((
+                    r.try_get("id")?,
+                    Url::parse(&url).with_context(|| format!("Can't parse url {}", url))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Saves the response to the health check of the link.
+    pub async fn set_link_health(
+        &self,
+        id: &i64,
+        http_status: i64,
+        final_url: Option<&str>,
+    ) -> Result<()> {
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            query(&format!(
+                "UPDATE {} SET http_status = ?, final_url = ?, checked_at = ?, failed_checks = 0 \
+                where id = ?",
+                table
+            ))
+            .bind(http_status)
+            .bind(final_url)
+            .bind(now())
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't save health of the link {}", id))?;
+        }
+        Ok(())
+    }
+
+    /// Counts a health check of the link which got no response, the last one is kept.
+    pub async fn set_link_unreachable(&self, id: &i64) -> Result<()> {
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            query(&format!(
+                "UPDATE {} SET failed_checks = failed_checks + 1, checked_at = ? where id = ?",
+                table
+            ))
+            .bind(now())
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't save failed check of the link {}", id))?;
+        }
+        Ok(())
+    }
+
+    /// Permanently deletes articles of all users trashed before the time.
+    pub async fn purge_trash(&self, deleted_before: i64) -> Result<u64> {
+        self.purge("deleted_at < ?", deleted_before)
+            .await
+            .context("Can't purge trash")
+    }
+
+    async fn purge(&self, condition: &str, param: i64) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let mut purged = 0;
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            for child in &[
+                ARTICLE_TAGS_TABLE,
+                UNDO_ACTIONS_TABLE,
+                ANNOTATIONS_TABLE,
+                COLLECTION_ITEMS_TABLE,
+                SHARE_LINKS_TABLE,
+                SNAPSHOTS_TABLE,
+            ] {
+                query(&format!(
+                    "DELETE FROM {} where article_id in (SELECT id FROM {} where {})",
+                    child, table, condition
+                ))
+                .bind(param)
+                .execute(&mut tx)
+                .await?;
+            }
+            purged += query(&format!("DELETE FROM {} where {}", table, condition))
+                .bind(param)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(purged)
+    }
+
+    /// Applies the action to the articles in one transaction. Nothing is changed and `None`
+    /// is returned if any of the ids is not an article of the user. Returns the number
+    /// of changed articles, e.g. archiving skips articles which are archived already.
+    pub async fn bulk(
+        &self,
+        user_id: &i64,
+        ids: &[i64],
+        action: &BulkAction,
+    ) -> Result<Option<u64>> {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        if ids.is_empty() {
+            return Ok(Some(0));
+        }
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .context("Can't start db transaction for bulk action")?;
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let sql = format!(
+            "SELECT id, status FROM {} where user_id = ? and id in ({})",
+            links_view(),
+            placeholders
+        );
+        let mut q = query(&sql).bind(user_id);
+        for id in ids.iter() {
+            q = q.bind(id);
+        }
+        let rows: Vec<sqlx::sqlite::SqliteRow> = q
+            .fetch_all(&mut tx)
+            .await
+            .with_context(|| format!("Can't check articles of user {}", user_id))?;
+        let mut articles = vec![];
+        for row in rows.iter() {
+            let status: String = row.try_get("status")?;
+            let status = ArticleStatus::parse(&status)
+                .with_context(|| format!("Unknown article status {}", status))?;
+            articles.push((row.try_get::<i64, &str>("id")?, status));
+        }
+        if ids.iter().any(|id| articles.iter().all(|(a, _)| a != id)) {
+            return Ok(None);
+        }
+        let mut changed = 0;
+        for (id, status) in articles {
+            changed += match (action, status) {
+                (BulkAction::Archive, ArticleStatus::Pending) => {
+                    move_link_in(&mut tx, &id, status, ArticleStatus::Archived).await?;
+                    1
+                }
+                (BulkAction::Unarchive, ArticleStatus::Archived) => {
+                    move_link_in(&mut tx, &id, status, ArticleStatus::Pending).await?;
+                    1
+                }
+                (BulkAction::Delete, _) => query(&format!(
+                    "UPDATE {} SET deleted_at = ? where id = ? and user_id = ? and deleted_at IS NULL",
+                    status.table()
+                ))
+                .bind(now())
+                .bind(id)
+                .bind(user_id)
+                .execute(&mut tx)
+                .await
+                .with_context(|| format!("Can't delete the link {}", id))?
+                .rows_affected(),
+                (BulkAction::Tag(tags), _) => {
+                    let mut added = 0;
+                    for tag in normalize_tags(tags) {
+                        added += query(&format!(
+                            "INSERT OR IGNORE INTO {}(article_id, user_id, tag) values(?, ?, ?)",
+                            ARTICLE_TAGS_TABLE
+                        ))
+                        .bind(id)
+                        .bind(user_id)
+                        .bind(tag)
+                        .execute(&mut tx)
+                        .await
+                        .with_context(|| format!("Can't insert tag for the link {}", id))?
+                        .rows_affected();
+                    }
+                    added.min(1)
+                }
+                _ => 0,
+            };
+        }
+        tx.commit()
+            .await
+            .context("Can't commit transaction for bulk action")?;
+        Ok(Some(changed))
+    }
+
     pub async fn get_pending_url(&self, id: &i64) -> Result<Option<ArticleData>> {
         self.get_url(id, PENDING_LINKS_TABLE).await
     }
@@ -184,7 +1022,7 @@ impl Storage {
 
     async fn get_url(&self, id: &i64, table: &str) -> Result<Option<ArticleData>> {
         let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
-            "SELECT user_id, url, title from {} where id = ?",
+            "SELECT user_id, url, title from {} where id = ? and deleted_at IS NULL",
             table
         ))
         .bind(id)
@@ -209,43 +1047,1342 @@ impl Storage {
         }
     }
 
-    pub async fn pending_list(&self, user_id: &i64) -> Result<Vec<(i64, Url, Option<String>)>> {
+    pub async fn get_article(&self, user_id: &i64, id: &i64) -> Result<Option<Article>> {
         let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
-            "SELECT id, url, title from {} where user_id = ? order by id desc",
-            PENDING_LINKS_TABLE
+            "SELECT * FROM {} where user_id = ? and id = ?",
+            links_view()
         ))
         .bind(user_id)
+        .bind(id)
         .fetch_all(&self.pool)
         .await
-        .with_context(|| format!("Can't get pending list for user {}", user_id))?;
-        let mut result = vec![];
-        for r in rows.iter() {
-            result.push((
-                r.try_get::<i64, &str>("id")?,
-                Url::parse(&r.try_get::<String, &str>("url")?)?,
-                r.try_get::<Option<String>, &str>("title")?,
-            ));
+        .with_context(|| format!("Can't get article {} for user {}", id, user_id))?;
+        rows.first().map(article).transpose()
+    }
+
+    pub async fn articles(&self, user_id: &i64, filter: &ArticleFilter) -> Result<Vec<Article>> {
+        let sql = format!(
+            "SELECT * FROM {} {} order by id desc limit ? offset ?",
+            links_view(),
+            filter_condition(filter)
+        );
+        let rows: Vec<sqlx::sqlite::SqliteRow> = bind_filter(query(&sql), user_id, filter)
+            .bind(filter.limit)
+            .bind(filter.offset)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| format!("Can't get articles for user {}", user_id))?;
+        rows.iter().map(article).collect()
+    }
+
+    /// Article picked at random among those matching the filter, its limit and offset are ignored.
+    pub async fn random_article(
+        &self,
+        user_id: &i64,
+        filter: &ArticleFilter,
+    ) -> Result<Option<Article>> {
+        let sql = format!(
+            "SELECT * FROM {} {} order by random() limit 1",
+            links_view(),
+            filter_condition(filter)
+        );
+        let rows: Vec<sqlx::sqlite::SqliteRow> = bind_filter(query(&sql), user_id, filter)
+            .fetch_all(&self.pool)
+            .await
+            .with_context(|| format!("Can't get random article for user {}", user_id))?;
+        rows.first().map(article).transpose()
+    }
+
+    /// Count of articles matching the filter, regardless of its limit and offset.
+    pub async fn count_articles(&self, user_id: &i64, filter: &ArticleFilter) -> Result<i64> {
+        let sql = format!(
+            "SELECT count(*) as count FROM {} {}",
+            links_view(),
+            filter_condition(filter)
+        );
+        let row = bind_filter(query(&sql), user_id, filter)
+            .fetch_one(&self.pool)
+            .await
+            .with_context(|| format!("Can't count articles for user {}", user_id))?;
+        Ok(row.try_get("count")?)
+    }
+
+    /// Id of the article with the url, if the user has already saved it.
+    pub async fn find_by_url(&self, user_id: &i64, url: &Url) -> Result<Option<i64>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT id FROM {} where user_id = ? and url = ? order by id desc limit 1",
+            links_view()
+        ))
+        .bind(user_id)
+        .bind(url.to_string())
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't find article by url for user {}", user_id))?;
+        match rows.first() {
+            Some(r) => Ok(Some(r.try_get("id")?)),
+            None => Ok(None),
         }
-        Ok(result)
     }
 
-    pub async fn archived_list(&self, user_id: &i64) -> Result<Vec<(i64, Url, Option<String>)>> {
+    pub async fn user_tags(&self, user_id: &i64) -> Result<Vec<String>> {
         let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
-            "SELECT id, url, title from {} where user_id = ? order by id desc",
-            ARCHIVED_LINKS_TABLE
+            "SELECT DISTINCT tag FROM {} where user_id = ? order by tag",
+            ARTICLE_TAGS_TABLE
         ))
         .bind(user_id)
         .fetch_all(&self.pool)
         .await
-        .with_context(|| format!("Can't get archived list for user {}", user_id))?;
-        let mut result = vec![];
-        for r in rows.iter() {
-            result.push((
-                r.try_get::<i64, &str>("id")?,
-                Url::parse(&r.try_get::<String, &str>("url")?)?,
-                r.try_get::<Option<String>, &str>("title")?,
-            ));
+        .with_context(|| format!("Can't get tags of user {}", user_id))?;
+        rows.iter()
+            .map(|r| r.try_get("tag").map_err(anyhow::Error::from))
+            .collect()
+    }
+
+    /// Stars or unstars the article, returns false if the user has no such article.
+    pub async fn set_starred(&self, user_id: &i64, id: &i64, starred: bool) -> Result<bool> {
+        let mut updated = 0;
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            updated += query(&format!(
+                "UPDATE {} SET starred = ?, updated_at = ? where id = ? and user_id = ? and deleted_at IS NULL",
+                table
+            ))
+            .bind(starred)
+            .bind(now())
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't star the link {}", id))?
+            .rows_affected();
+        }
+        Ok(updated > 0)
+    }
+
+    /// Flips the star of the article, returns its new state or `None` if there's no such article.
+    pub async fn toggle_starred(&self, user_id: &i64, id: &i64) -> Result<Option<bool>> {
+        match self.get_article(user_id, id).await? {
+            Some(article) => {
+                self.set_starred(user_id, id, !article.starred).await?;
+                Ok(Some(!article.starred))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Saves the reading position, progress only grows to keep the furthest point read.
+    /// Returns false if the user has no such article.
+    pub async fn set_progress(
+        &self,
+        user_id: &i64,
+        id: &i64,
+        progress: i64,
+        position: i64,
+    ) -> Result<bool> {
+        let mut updated = 0;
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            updated += query(&format!(
+                "UPDATE {} SET progress = max(progress, ?), position = ? where id = ? and user_id = ? and deleted_at IS NULL",
+                table
+            ))
+            .bind(progress.clamp(0, 100))
+            .bind(position.max(0))
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't save progress of the link {}", id))?
+            .rows_affected();
+        }
+        Ok(updated > 0)
+    }
+
+    pub async fn update_title(&self, user_id: &i64, id: &i64, title: Option<String>) -> Result<()> {
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            query(&format!(
+                "UPDATE {} SET title = ?, updated_at = ? where id = ? and user_id = ?",
+                table
+            ))
+            .bind(title.clone())
+            .bind(now())
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't update title of the link {}", id))?;
+        }
+        Ok(())
+    }
+
+    /// Content is shown unescaped by the reader, so it must come out of `extractor`.
+    pub async fn set_content(
+        &self,
+        user_id: &i64,
+        id: &i64,
+        content: Option<String>,
+    ) -> Result<()> {
+        let word_count = content.as_deref().map(extractor::word_count);
+        for table in &[PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE] {
+            query(&format!(
+                "UPDATE {} SET content = ?, word_count = ?, reading_minutes = ? where id = ? and user_id = ?",
+                table
+            ))
+            .bind(content.clone())
+            .bind(word_count)
+            .bind(word_count.map(extractor::reading_minutes))
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Can't update content of the link {}", id))?;
+        }
+        Ok(())
+    }
+
+    /// Content is not a part of `Article` to keep lists light, it's loaded for one article only.
+    pub async fn get_content(&self, user_id: &i64, id: &i64) -> Result<Option<String>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT content FROM {} where user_id = ? and id = ?",
+            links_view()
+        ))
+        .bind(user_id)
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get content of the link {}", id))?;
+        match rows.first() {
+            Some(r) => Ok(r.try_get("content")?),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces all tags of the article. Tags are trimmed, lowercased and can't contain commas.
+    pub async fn set_tags(&self, user_id: &i64, id: &i64, tags: &[String]) -> Result<()> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .with_context(|| format!("Can't start db transaction for tags of {}", id))?;
+        query(&format!(
+            "DELETE FROM {} where article_id = ? and user_id = ?",
+            ARTICLE_TAGS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("Can't delete tags of the link {}", id))?;
+        for tag in normalize_tags(tags) {
+            query(&format!(
+                "INSERT OR IGNORE INTO {}(article_id, user_id, tag) values(?, ?, ?)",
+                ARTICLE_TAGS_TABLE
+            ))
+            .bind(id)
+            .bind(user_id)
+            .bind(tag)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("Can't insert tag for the link {}", id))?;
+        }
+        tx.commit()
+            .await
+            .with_context(|| format!("Can't commit tags of the link {}", id))
+    }
+
+    /// Attaches the annotation to the article, `None` if the user has no such article.
+    pub async fn add_annotation(
+        &self,
+        user_id: &i64,
+        article_id: &i64,
+        kind: AnnotationKind,
+        text: &str,
+        anchor: Option<String>,
+    ) -> Result<Option<i64>> {
+        if self.get_article(user_id, article_id).await?.is_none() {
+            return Ok(None);
+        }
+        query(&format!(
+            "INSERT INTO {}(article_id, user_id, kind, text, anchor, created_at) values(?, ?, ?, ?, ?, ?)",
+            ANNOTATIONS_TABLE
+        ))
+        .bind(article_id)
+        .bind(user_id)
+        .bind(kind.as_str())
+        .bind(text)
+        .bind(anchor)
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't add annotation to the link {}", article_id))
+        .map(|done| Some(done.last_insert_rowid()))
+    }
+
+    pub async fn get_annotation(&self, user_id: &i64, id: &i64) -> Result<Option<Annotation>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where id = ? and user_id = ?",
+            ANNOTATIONS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get annotation {}", id))?;
+        rows.first().map(annotation).transpose()
+    }
+
+    /// Annotations of the article in the order they were made.
+    pub async fn annotations(&self, user_id: &i64, article_id: &i64) -> Result<Vec<Annotation>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where article_id = ? and user_id = ? order by created_at, id",
+            ANNOTATIONS_TABLE
+        ))
+        .bind(article_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get annotations of the link {}", article_id))?;
+        rows.iter().map(annotation).collect()
+    }
+
+    /// Replaces the text of the annotation, returns false if the user has no such annotation.
+    pub async fn update_annotation(&self, user_id: &i64, id: &i64, text: &str) -> Result<bool> {
+        query(&format!(
+            "UPDATE {} SET text = ?, updated_at = ? where id = ? and user_id = ?",
+            ANNOTATIONS_TABLE
+        ))
+        .bind(text)
+        .bind(now())
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't update annotation {}", id))
+        .map(|done| done.rows_affected() > 0)
+    }
+
+    pub async fn delete_annotation(&self, user_id: &i64, id: &i64) -> Result<bool> {
+        query(&format!(
+            "DELETE FROM {} where id = ? and user_id = ?",
+            ANNOTATIONS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't delete annotation {}", id))
+        .map(|done| done.rows_affected() > 0)
+    }
+
+    pub async fn add_api_token(
+        &self,
+        user_id: &i64,
+        name: &str,
+        token_hash: &str,
+        scopes: &str,
+    ) -> Result<i64> {
+        query(&format!(
+            "INSERT INTO {}(user_id, name, token_hash, scopes, created_at) values(?, ?, ?, ?, ?);",
+            API_TOKENS_TABLE
+        ))
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .bind(scopes)
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't insert api token for user {}", user_id))
+        .map(|done| done.last_insert_rowid())
+    }
+
+    /// Finds the token by its hash and marks it as used right now.
+    pub async fn use_api_token(&self, token_hash: &str) -> Result<Option<ApiToken>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT id, user_id, name, scopes, created_at, last_used_at from {} where token_hash = ?",
+            API_TOKENS_TABLE
+        ))
+        .bind(token_hash)
+        .fetch_all(&self.pool)
+        .await
+        .context("Can't get api token by hash")?;
+
+        match rows.first() {
+            Some(r) => {
+                let token = api_token(r)?;
+                query(&format!(
+                    "UPDATE {} SET last_used_at = ? where id = ?",
+                    API_TOKENS_TABLE
+                ))
+                .bind(now())
+                .bind(token.id)
+                .execute(&self.pool)
+                .await
+                .with_context(|| format!("Can't update last usage of api token {}", token.id))?;
+                Ok(Some(token))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn api_tokens(&self, user_id: &i64) -> Result<Vec<ApiToken>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT id, user_id, name, scopes, created_at, last_used_at from {} where user_id = ? order by id desc",
+            API_TOKENS_TABLE
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get api tokens for user {}", user_id))?;
+        rows.iter().map(api_token).collect()
+    }
+
+    /// Feed tokens are kept as is to show feed links in the settings, they give read
+    /// access to the feeds only.
+    pub async fn feed_token(&self, user_id: &i64) -> Result<Option<String>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT token FROM {} where user_id = ?",
+            FEED_TOKENS_TABLE
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get feed token of user {}", user_id))?;
+        match rows.first() {
+            Some(r) => Ok(Some(r.try_get("token")?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the feed token of the user, so old feed links stop working.
+    pub async fn set_feed_token(&self, user_id: &i64, token: &str) -> Result<()> {
+        query(&format!(
+            "INSERT OR REPLACE INTO {}(user_id, token, created_at) values(?, ?, ?)",
+            FEED_TOKENS_TABLE
+        ))
+        .bind(user_id)
+        .bind(token)
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't set feed token of user {}", user_id))?;
+        Ok(())
+    }
+
+    pub async fn create_collection(&self, user_id: &i64, name: &str) -> Result<i64> {
+        query(&format!(
+            "INSERT INTO {}(user_id, name, created_at) values(?, ?, ?)",
+            COLLECTIONS_TABLE
+        ))
+        .bind(user_id)
+        .bind(name)
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't create collection for user {}", user_id))
+        .map(|done| done.last_insert_rowid())
+    }
+
+    pub async fn collections(&self, user_id: &i64) -> Result<Vec<Collection>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where user_id = ? order by id",
+            COLLECTIONS_TABLE
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get collections of user {}", user_id))?;
+        rows.iter().map(collection).collect()
+    }
+
+    pub async fn get_collection(&self, user_id: &i64, id: &i64) -> Result<Option<Collection>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where id = ? and user_id = ?",
+            COLLECTIONS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get collection {}", id))?;
+        rows.first().map(collection).transpose()
+    }
+
+    /// Published collection with the token, whoever the owner is.
+    pub async fn public_collection(&self, token: &str) -> Result<Option<Collection>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where public_token = ?",
+            COLLECTIONS_TABLE
+        ))
+        .bind(token)
+        .fetch_all(&self.pool)
+        .await
+        .context("Can't get collection by token")?;
+        rows.first().map(collection).transpose()
+    }
+
+    pub async fn rename_collection(&self, user_id: &i64, id: &i64, name: &str) -> Result<bool> {
+        query(&format!(
+            "UPDATE {} SET name = ? where id = ? and user_id = ?",
+            COLLECTIONS_TABLE
+        ))
+        .bind(name)
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't rename collection {}", id))
+        .map(|done| done.rows_affected() > 0)
+    }
+
+    /// Publishes the collection with the token, or makes it private again with `None`.
+    pub async fn set_collection_token(
+        &self,
+        user_id: &i64,
+        id: &i64,
+        token: Option<&str>,
+    ) -> Result<bool> {
+        query(&format!(
+            "UPDATE {} SET public_token = ? where id = ? and user_id = ?",
+            COLLECTIONS_TABLE
+        ))
+        .bind(token)
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't publish collection {}", id))
+        .map(|done| done.rows_affected() > 0)
+    }
+
+    /// Deletes the collection, its articles stay in the lists.
+    pub async fn delete_collection(&self, user_id: &i64, id: &i64) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let deleted = query(&format!(
+            "DELETE FROM {} where id = ? and user_id = ?",
+            COLLECTIONS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("Can't delete collection {}", id))?
+        .rows_affected()
+            > 0;
+        if deleted {
+            query(&format!(
+                "DELETE FROM {} where collection_id = ?",
+                COLLECTION_ITEMS_TABLE
+            ))
+            .bind(id)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("Can't delete articles of collection {}", id))?;
+        }
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    /// Adds the article to the end of the collection, returns false if the user has
+    /// no such collection or article. Adding an article twice keeps its place.
+    pub async fn add_to_collection(
+        &self,
+        user_id: &i64,
+        collection_id: &i64,
+        article_id: &i64,
+    ) -> Result<bool> {
+        if self.get_collection(user_id, collection_id).await?.is_none()
+            || self.get_article(user_id, article_id).await?.is_none()
+        {
+            return Ok(false);
+        }
+        query(&format!(
+            "INSERT OR IGNORE INTO {0}(collection_id, article_id, position) \
+            SELECT ?, ?, coalesce(max(position) + 1, 0) FROM {0} where collection_id = ?",
+            COLLECTION_ITEMS_TABLE
+        ))
+        .bind(collection_id)
+        .bind(article_id)
+        .bind(collection_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| {
+            format!(
+                "Can't add link {} to collection {}",
+                article_id, collection_id
+            )
+        })?;
+        Ok(true)
+    }
+
+    pub async fn remove_from_collection(
+        &self,
+        user_id: &i64,
+        collection_id: &i64,
+        article_id: &i64,
+    ) -> Result<bool> {
+        if self.get_collection(user_id, collection_id).await?.is_none() {
+            return Ok(false);
+        }
+        query(&format!(
+            "DELETE FROM {} where collection_id = ? and article_id = ?",
+            COLLECTION_ITEMS_TABLE
+        ))
+        .bind(collection_id)
+        .bind(article_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| {
+            format!(
+                "Can't remove link {} from collection {}",
+                article_id, collection_id
+            )
+        })
+        .map(|done| done.rows_affected() > 0)
+    }
+
+    /// Articles of the collection in its order, trashed articles are skipped.
+    pub async fn collection_articles(
+        &self,
+        user_id: &i64,
+        collection_id: &i64,
+    ) -> Result<Vec<Article>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT l.* FROM {} JOIN {} c on c.article_id = l.id \
+            where c.collection_id = ? and l.user_id = ? order by c.position",
+            links_view(),
+            COLLECTION_ITEMS_TABLE
+        ))
+        .bind(collection_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get articles of collection {}", collection_id))?;
+        rows.iter().map(article).collect()
+    }
+
+    /// Orders the collection as the ids, which must be all of its articles but trashed ones.
+    /// Returns false and keeps the order if they are not.
+    pub async fn reorder_collection(
+        &self,
+        user_id: &i64,
+        collection_id: &i64,
+        ids: &[i64],
+    ) -> Result<bool> {
+        if self.get_collection(user_id, collection_id).await?.is_none() {
+            return Ok(false);
+        }
+        let mut tx = self.pool.begin().await?;
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT c.article_id FROM {} c JOIN {} on l.id = c.article_id where c.collection_id = ?",
+            COLLECTION_ITEMS_TABLE,
+            links_view()
+        ))
+        .bind(collection_id)
+        .fetch_all(&mut tx)
+        .await
+        .with_context(|| format!("Can't get articles of collection {}", collection_id))?;
+        let mut current = rows
+            .iter()
+            .map(|r| r.try_get::<i64, &str>("article_id"))
+            .collect::<std::result::Result<Vec<i64>, _>>()?;
+        let mut sorted = ids.to_vec();
+        current.sort_unstable();
+        sorted.sort_unstable();
+        if current != sorted {
+            return Ok(false);
+        }
+        for (position, id) in ids.iter().enumerate() {
+            query(&format!(
+                "UPDATE {} SET position = ? where collection_id = ? and article_id = ?",
+                COLLECTION_ITEMS_TABLE
+            ))
+            .bind(position as i64)
+            .bind(collection_id)
+            .bind(id)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("Can't reorder collection {}", collection_id))?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Creates the team with the user as its owner.
+    pub async fn create_team(&self, user_id: &i64, name: &str) -> Result<Team> {
+        self.insert_team(user_id, name, None).await
+    }
+
+    /// Team of the group chat, created with the user as its owner if the chat has none.
+    /// Its links are kept by the chat id, like the links the group saved before teams.
+    pub async fn create_chat_team(&self, user_id: &i64, chat_id: &i64, name: &str) -> Result<Team> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where account_id = ?",
+            TEAMS_TABLE
+        ))
+        .bind(chat_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get team of chat {}", chat_id))?;
+        match rows.first().map(team).transpose()? {
+            // The chat was linked to another team with `/team` since then.
+            Some(team) => {
+                self.set_team_chat(&team.id, chat_id).await?;
+                Ok(Team {
+                    chat_id: Some(*chat_id),
+                    ..team
+                })
+            }
+            None => self.insert_team(user_id, name, Some(*chat_id)).await,
+        }
+    }
+
+    async fn insert_team(&self, user_id: &i64, name: &str, chat_id: Option<i64>) -> Result<Team> {
+        let mut tx = self.pool.begin().await?;
+        let created_at = now();
+        let id = query(&format!(
+            "INSERT INTO {}(name, chat_id, created_at) values(?, ?, ?)",
+            TEAMS_TABLE
+        ))
+        .bind(name)
+        .bind(chat_id)
+        .bind(created_at)
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("Can't create team for user {}", user_id))?
+        .last_insert_rowid();
+        let account_id = chat_id.unwrap_or(-(TEAM_ACCOUNT_OFFSET + id));
+        query(&format!(
+            "UPDATE {} SET account_id = ? where id = ?",
+            TEAMS_TABLE
+        ))
+        .bind(account_id)
+        .bind(id)
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("Can't set account of team {}", id))?;
+        query(&format!(
+            "INSERT INTO {}(team_id, user_id, role) values(?, ?, ?)",
+            TEAM_MEMBERS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .bind(Role::Owner.as_str())
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("Can't add owner of team {}", id))?;
+        tx.commit().await?;
+        Ok(Team {
+            id,
+            name: name.to_string(),
+            account_id,
+            chat_id,
+            created_at,
+        })
+    }
+
+    /// Teams the user is a member of, with the role of the user.
+    pub async fn teams(&self, user_id: &i64) -> Result<Vec<(Team, Role)>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT t.*, m.role FROM {} t JOIN {} m on m.team_id = t.id where m.user_id = ? order by t.id",
+            TEAMS_TABLE, TEAM_MEMBERS_TABLE
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get teams of user {}", user_id))?;
+        rows.iter().map(team_with_role).collect()
+    }
+
+    /// The team with the role of the user, if the user is a member.
+    pub async fn get_team(&self, user_id: &i64, team_id: &i64) -> Result<Option<(Team, Role)>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT t.*, m.role FROM {} t JOIN {} m on m.team_id = t.id where m.user_id = ? and t.id = ?",
+            TEAMS_TABLE, TEAM_MEMBERS_TABLE
+        ))
+        .bind(user_id)
+        .bind(team_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get team {}", team_id))?;
+        rows.first().map(team_with_role).transpose()
+    }
+
+    /// Ids and roles of the members of the team, owners first.
+    pub async fn team_members(&self, team_id: &i64) -> Result<Vec<(i64, Role)>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT user_id, role FROM {} where team_id = ? order by role = 'owner' desc, user_id",
+            TEAM_MEMBERS_TABLE
+        ))
+        .bind(team_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get members of team {}", team_id))?;
+        rows.iter()
+            .map(|r| {
+                let role: String = r.try_get("role")?;
+                Ok((
+                    r.try_get("user_id")?,
+                    Role::parse(&role).with_context(|| format!("Unknown role {}", role))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Adds the member or changes the role of an existing one.
+    pub async fn set_team_member(&self, team_id: &i64, user_id: &i64, role: Role) -> Result<()> {
+        query(&format!(
+            "INSERT INTO {}(team_id, user_id, role) values(?, ?, ?) \
+            ON CONFLICT(team_id, user_id) DO UPDATE SET role = excluded.role",
+            TEAM_MEMBERS_TABLE
+        ))
+        .bind(team_id)
+        .bind(user_id)
+        .bind(role.as_str())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't set member {} of team {}", user_id, team_id))?;
+        Ok(())
+    }
+
+    pub async fn remove_team_member(&self, team_id: &i64, user_id: &i64) -> Result<bool> {
+        query(&format!(
+            "DELETE FROM {} where team_id = ? and user_id = ?",
+            TEAM_MEMBERS_TABLE
+        ))
+        .bind(team_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't remove member {} of team {}", user_id, team_id))
+        .map(|done| done.rows_affected() > 0)
+    }
+
+    /// Role of the user for the links of the account: owner of their own links,
+    /// the member role for links of a team, `None` for links of anyone else.
+    pub async fn role(&self, user_id: &i64, account_id: &i64) -> Result<Option<Role>> {
+        if user_id == account_id {
+            return Ok(Some(Role::Owner));
+        }
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT m.role FROM {} t JOIN {} m on m.team_id = t.id where t.account_id = ? and m.user_id = ?",
+            TEAMS_TABLE, TEAM_MEMBERS_TABLE
+        ))
+        .bind(account_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get role of user {} for {}", user_id, account_id))?;
+        match rows.first() {
+            Some(r) => Ok(Role::parse(&r.try_get::<String, &str>("role")?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Links the group chat to the team, a chat saves to one team at most.
+    pub async fn set_team_chat(&self, team_id: &i64, chat_id: &i64) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        query(&format!(
+            "UPDATE {} SET chat_id = NULL where chat_id = ?",
+            TEAMS_TABLE
+        ))
+        .bind(chat_id)
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("Can't unlink chat {}", chat_id))?;
+        query(&format!(
+            "UPDATE {} SET chat_id = ? where id = ?",
+            TEAMS_TABLE
+        ))
+        .bind(chat_id)
+        .bind(team_id)
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("Can't link chat {} to team {}", chat_id, team_id))?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Team the group chat saves links to.
+    pub async fn chat_team(&self, chat_id: &i64) -> Result<Option<Team>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> =
+            query(&format!("SELECT * FROM {} where chat_id = ?", TEAMS_TABLE))
+                .bind(chat_id)
+                .fetch_all(&self.pool)
+                .await
+                .with_context(|| format!("Can't get team of chat {}", chat_id))?;
+        rows.first().map(team).transpose()
+    }
+
+    /// User or team account owning the link, trashed links included.
+    pub async fn link_owner(&self, id: &i64) -> Result<Option<i64>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT user_id FROM {} where id = ? UNION ALL SELECT user_id FROM {} where id = ?",
+            PENDING_LINKS_TABLE, ARCHIVED_LINKS_TABLE
+        ))
+        .bind(id)
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get owner of the link {}", id))?;
+        match rows.first() {
+            Some(r) => Ok(Some(r.try_get("user_id")?)),
+            None => Ok(None),
         }
-        Ok(result)
     }
+
+    /// Saves the share link, returns `None` if the user has no such article.
+    pub async fn add_share_link(
+        &self,
+        user_id: &i64,
+        token: &str,
+        target: &ShareTarget,
+        expires_at: Option<i64>,
+    ) -> Result<Option<i64>> {
+        let (article_id, status, tag, starred) = match target {
+            ShareTarget::Article(id) => {
+                if self.get_article(user_id, id).await?.is_none() {
+                    return Ok(None);
+                }
+                (Some(*id), None, None, None)
+            }
+            ShareTarget::List {
+                status,
+                tag,
+                starred,
+            } => (None, status.map(|s| s.as_str()), tag.clone(), *starred),
+        };
+        query(&format!(
+            "INSERT INTO {}(user_id, token, article_id, status, tag, starred, created_at, expires_at) \
+            values(?, ?, ?, ?, ?, ?, ?, ?)",
+            SHARE_LINKS_TABLE
+        ))
+        .bind(user_id)
+        .bind(token)
+        .bind(article_id)
+        .bind(status)
+        .bind(tag)
+        .bind(starred)
+        .bind(now())
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't add share link for user {}", user_id))
+        .map(|done| Some(done.last_insert_rowid()))
+    }
+
+    /// Share links of the user, expired ones included, newest first.
+    pub async fn share_links(&self, user_id: &i64) -> Result<Vec<ShareLink>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where user_id = ? order by id desc",
+            SHARE_LINKS_TABLE
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get share links of user {}", user_id))?;
+        rows.iter().map(share_link).collect()
+    }
+
+    /// Share link with the token, if it's not revoked or expired.
+    pub async fn share_link(&self, token: &str) -> Result<Option<ShareLink>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT * FROM {} where token = ? and (expires_at IS NULL or expires_at > ?)",
+            SHARE_LINKS_TABLE
+        ))
+        .bind(token)
+        .bind(now())
+        .fetch_all(&self.pool)
+        .await
+        .context("Can't get share link by token")?;
+        rows.first().map(share_link).transpose()
+    }
+
+    pub async fn revoke_share_link(&self, user_id: &i64, id: &i64) -> Result<bool> {
+        query(&format!(
+            "DELETE FROM {} where id = ? and user_id = ?",
+            SHARE_LINKS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't revoke share link {}", id))
+        .map(|done| done.rows_affected() > 0)
+    }
+
+    /// Whether pending articles are archived when they are read to the end.
+    pub async fn auto_archive(&self, user_id: &i64) -> Result<bool> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT auto_archive FROM {} where user_id = ?",
+            USER_SETTINGS_TABLE
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get settings of user {}", user_id))?;
+        match rows.first() {
+            Some(r) => Ok(r.try_get("auto_archive")?),
+            None => Ok(false),
+        }
+    }
+
+    pub async fn set_auto_archive(&self, user_id: &i64, auto_archive: bool) -> Result<()> {
+        query(&format!(
+            "INSERT INTO {}(user_id, auto_archive) values(?, ?) ON CONFLICT(user_id) DO UPDATE SET auto_archive = excluded.auto_archive",
+            USER_SETTINGS_TABLE
+        ))
+        .bind(user_id)
+        .bind(auto_archive)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't save settings of user {}", user_id))?;
+        Ok(())
+    }
+
+    /// Whether a snapshot of the page is taken when the user saves a link.
+    pub async fn snapshots_enabled(&self, user_id: &i64) -> Result<bool> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT snapshots FROM {} where user_id = ?",
+            USER_SETTINGS_TABLE
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get settings of user {}", user_id))?;
+        match rows.first() {
+            Some(r) => Ok(r.try_get("snapshots")?),
+            None => Ok(false),
+        }
+    }
+
+    pub async fn set_snapshots_enabled(&self, user_id: &i64, enabled: bool) -> Result<()> {
+        query(&format!(
+            "INSERT INTO {}(user_id, snapshots) values(?, ?) ON CONFLICT(user_id) DO UPDATE SET snapshots = excluded.snapshots",
+            USER_SETTINGS_TABLE
+        ))
+        .bind(user_id)
+        .bind(enabled)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't save settings of user {}", user_id))?;
+        Ok(())
+    }
+
+    /// Saves the snapshot of the article replacing the previous one,
+    /// returns false if the user has no such article.
+    pub async fn set_snapshot(&self, user_id: &i64, article_id: &i64, html: &str) -> Result<bool> {
+        if self.get_article(user_id, article_id).await?.is_none() {
+            return Ok(false);
+        }
+        query(&format!(
+            "INSERT OR REPLACE INTO {}(article_id, user_id, content, size, created_at) values(?, ?, ?, ?, ?)",
+            SNAPSHOTS_TABLE
+        ))
+        .bind(article_id)
+        .bind(user_id)
+        .bind(html.as_bytes())
+        .bind(html.len() as i64)
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't save snapshot of the link {}", article_id))?;
+        Ok(true)
+    }
+
+    pub async fn snapshot(&self, user_id: &i64, article_id: &i64) -> Result<Option<Vec<u8>>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT content FROM {} where article_id = ? and user_id = ?",
+            SNAPSHOTS_TABLE
+        ))
+        .bind(article_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get snapshot of the link {}", article_id))?;
+        rows.first()
+            .map(|r| r.try_get("content").map_err(anyhow::Error::from))
+            .transpose()
+    }
+
+    /// Size in bytes and time of the snapshot of the article, if there is one.
+    pub async fn snapshot_info(
+        &self,
+        user_id: &i64,
+        article_id: &i64,
+    ) -> Result<Option<(i64, i64)>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT size, created_at FROM {} where article_id = ? and user_id = ?",
+            SNAPSHOTS_TABLE
+        ))
+        .bind(article_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Can't get snapshot of the link {}", article_id))?;
+        match rows.first() {
+            Some(r) => Ok(Some((r.try_get("size")?, r.try_get("created_at")?))),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn feed_token_user(&self, token: &str) -> Result<Option<i64>> {
+        let rows: Vec<sqlx::sqlite::SqliteRow> = query(&format!(
+            "SELECT user_id FROM {} where token = ?",
+            FEED_TOKENS_TABLE
+        ))
+        .bind(token)
+        .fetch_all(&self.pool)
+        .await
+        .context("Can't find feed token")?;
+        match rows.first() {
+            Some(r) => Ok(Some(r.try_get("user_id")?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn delete_api_token(&self, user_id: &i64, id: &i64) -> Result<()> {
+        query(&format!(
+            "DELETE FROM {} where id = ? and user_id = ?",
+            API_TOKENS_TABLE
+        ))
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Can't delete api token {}", id))?;
+        Ok(())
+    }
+}
+
+/// Moves the link between tables within the transaction, see `Storage::move_link`.
+async fn move_link_in(
+    tx: &mut Transaction<'_, Sqlite>,
+    id: &i64,
+    from: ArticleStatus,
+    to: ArticleStatus,
+) -> Result<i64> {
+    query(&format!(
+        "INSERT INTO {}({}) SELECT {} FROM {} where id = ?",
+        to.table(),
+        LINK_COLUMNS,
+        LINK_COLUMNS,
+        from.table()
+    ))
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .with_context(|| format!("Can't insert link {} to {}", id, to.table()))?;
+    query(&format!(
+        "UPDATE {} SET updated_at = ? where id = ?",
+        to.table()
+    ))
+    .bind(now())
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .with_context(|| format!("Can't update the link {} in {}", id, to.table()))?;
+    query(&format!("DELETE FROM {} where id = ?", from.table()))
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Can't delete the link {} from {}", id, from.table()))?;
+    Ok(*id)
+}
+
+/// Union of both links tables without trashed links.
+fn links_view() -> String {
+    links_view_where("deleted_at IS NULL")
+}
+
+/// Union of both links tables with the status column and comma separated tags,
+/// filtered by the condition on the rows of the tables.
+fn links_view_where(condition: &str) -> String {
+    let select = |table: &str, status: ArticleStatus| {
+        format!(
+            "SELECT {}, '{}' as status, (SELECT group_concat(tag, ',') FROM {} t where t.article_id = {}.id) as tags FROM {} where {}",
+            LINK_COLUMNS,
+            status.as_str(),
+            ARTICLE_TAGS_TABLE,
+            table,
+            table,
+            condition
+        )
+    };
+    format!(
+        "({} UNION ALL {}) l",
+        select(PENDING_LINKS_TABLE, ArticleStatus::Pending),
+        select(ARCHIVED_LINKS_TABLE, ArticleStatus::Archived)
+    )
+}
+
+fn filter_condition(filter: &ArticleFilter) -> String {
+    let mut condition = "where user_id = ?".to_string();
+    if filter.status.is_some() {
+        condition.push_str(" and status = ?");
+    }
+    if filter.starred.is_some() {
+        condition.push_str(" and starred = ?");
+    }
+    if filter.max_minutes.is_some() {
+        condition.push_str(" and reading_minutes <= ?");
+    }
+    for _ in filter.tags.iter() {
+        condition.push_str(&format!(
+            " and exists (SELECT 1 FROM {} t where t.article_id = l.id and t.tag = ?)",
+            ARTICLE_TAGS_TABLE
+        ));
+    }
+    if filter.before.is_some() {
+        condition.push_str(" and id < ?");
+    }
+    condition
+}
+
+/// Binds parameters of `filter_condition` in the same order.
+fn bind_filter<'q>(
+    mut q: sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    user_id: &i64,
+    filter: &ArticleFilter,
+) -> sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+    q = q.bind(*user_id);
+    if let Some(status) = filter.status {
+        q = q.bind(status.as_str());
+    }
+    if let Some(starred) = filter.starred {
+        q = q.bind(starred);
+    }
+    if let Some(max_minutes) = filter.max_minutes {
+        q = q.bind(max_minutes);
+    }
+    for tag in filter.tags.iter() {
+        q = q.bind(tag.trim().to_lowercase());
+    }
+    if let Some(before) = filter.before {
+        q = q.bind(before);
+    }
+    q
+}
+
+fn article(r: &sqlx::sqlite::SqliteRow) -> Result<Article> {
+    let status: String = r.try_get("status")?;
+    Ok(Article {
+        id: r.try_get("id")?,
+        status: ArticleStatus::parse(&status)
+            .with_context(|| format!("Unknown article status {}", status))?,
+        created_at: r.try_get("created_at")?,
+        updated_at: r.try_get("updated_at")?,
+        deleted_at: r.try_get("deleted_at")?,
+        starred: r.try_get("starred")?,
+        progress: r.try_get("progress")?,
+        position: r.try_get("position")?,
+        word_count: r.try_get("word_count")?,
+        reading_minutes: r.try_get("reading_minutes")?,
+        http_status: r.try_get("http_status")?,
+        final_url: r.try_get("final_url")?,
+        checked_at: r.try_get("checked_at")?,
+        failed_checks: r.try_get("failed_checks")?,
+        tags: r
+            .try_get::<Option<String>, &str>("tags")?
+            .map(|t| {
+                let mut tags: Vec<String> = t.split(',').map(|t| t.to_string()).collect();
+                tags.sort();
+                tags
+            })
+            .unwrap_or_default(),
+        data: ArticleData {
+            user_id: r.try_get("user_id")?,
+            url: Url::parse(&r.try_get::<String, &str>("url")?)
+                .context("Can't parse url received from db")?,
+            title: r.try_get("title")?,
+        },
+    })
+}
+
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = tags
+        .iter()
+        .map(|t| t.trim().to_lowercase().replace(',', " "))
+        .filter(|t| !t.is_empty())
+        .collect();
+    result.sort();
+    result.dedup();
+    result
+}
+
+fn api_token(r: &sqlx::sqlite::SqliteRow) -> Result<ApiToken> {
+    Ok(ApiToken {
+        id: r.try_get("id")?,
+        user_id: r.try_get("user_id")?,
+        name: r.try_get("name")?,
+        scopes: r.try_get("scopes")?,
+        created_at: r.try_get("created_at")?,
+        last_used_at: r.try_get("last_used_at")?,
+    })
+}
+
+fn annotation(r: &sqlx::sqlite::SqliteRow) -> Result<Annotation> {
+    let kind: String = r.try_get("kind")?;
+    Ok(Annotation {
+        id: r.try_get("id")?,
+        article_id: r.try_get("article_id")?,
+        kind: AnnotationKind::parse(&kind)
+            .with_context(|| format!("Unknown annotation kind {}", kind))?,
+        text: r.try_get("text")?,
+        anchor: r.try_get("anchor")?,
+        created_at: r.try_get("created_at")?,
+        updated_at: r.try_get("updated_at")?,
+    })
+}
+
+fn collection(r: &sqlx::sqlite::SqliteRow) -> Result<Collection> {
+    Ok(Collection {
+        id: r.try_get("id")?,
+        user_id: r.try_get("user_id")?,
+        name: r.try_get("name")?,
+        public_token: r.try_get("public_token")?,
+        created_at: r.try_get("created_at")?,
+    })
+}
+
+fn team(r: &sqlx::sqlite::SqliteRow) -> Result<Team> {
+    Ok(Team {
+        id: r.try_get("id")?,
+        name: r.try_get("name")?,
+        account_id: r.try_get("account_id")?,
+        chat_id: r.try_get("chat_id")?,
+        created_at: r.try_get("created_at")?,
+    })
+}
+
+fn team_with_role(r: &sqlx::sqlite::SqliteRow) -> Result<(Team, Role)> {
+    let role: String = r.try_get("role")?;
+    Ok((
+        team(r)?,
+        Role::parse(&role).with_context(|| format!("Unknown role {}", role))?,
+    ))
+}
+
+fn share_link(r: &sqlx::sqlite::SqliteRow) -> Result<ShareLink> {
+    let target = match r.try_get::<Option<i64>, &str>("article_id")? {
+        Some(id) => ShareTarget::Article(id),
+        None => ShareTarget::List {
+            status: r
+                .try_get::<Option<String>, &str>("status")?
+                .and_then(|s| ArticleStatus::parse(&s)),
+            tag: r.try_get("tag")?,
+            starred: r.try_get("starred")?,
+        },
+    };
+    Ok(ShareLink {
+        id: r.try_get("id")?,
+        user_id: r.try_get("user_id")?,
+        token: r.try_get("token")?,
+        target,
+        created_at: r.try_get("created_at")?,
+        expires_at: r.try_get("expires_at")?,
+    })
+}
+
+/// Current unix time in seconds, the format of all timestamps in the storage.
+pub fn now() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
 }
@@ -0,0 +1,287 @@
+use crate::auth::{AuthUser, Scope};
+use crate::routes::{http_url, see_other, AppState, APP_NAME, MAX_NAME_LENGTH};
+use crate::save_link;
+use crate::storage::{ArticleFilter, ArticleStatus, Role, Team};
+use actix_web::*;
+use serde::*;
+use serde_json::json;
+
+/// Links shown on the page of a team, newest first.
+const TEAM_PAGE_SIZE: i64 = 100;
+
+#[derive(Serialize, Debug)]
+struct TeamView {
+    id: i64,
+    name: String,
+    role: &'static str,
+    /// Whether the user can save, archive and delete links of the team.
+    can_write: bool,
+    /// Whether the user manages the members.
+    is_owner: bool,
+}
+
+impl From<(Team, Role)> for TeamView {
+    fn from((team, role): (Team, Role)) -> TeamView {
+        TeamView {
+            id: team.id,
+            name: team.name,
+            role: role.as_str(),
+            can_write: role.can_write(),
+            is_owner: role == Role::Owner,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct TeamsTemplate<'a> {
+    app_name: &'a str,
+    teams: Vec<TeamView>,
+    user_id: i64,
+    page: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+struct TeamTemplate<'a> {
+    app_name: &'a str,
+    team: TeamView,
+    archived: bool,
+    /// Id, url and title of the links.
+    links: Vec<(i64, String, String)>,
+    /// Id and role of the members.
+    members: Vec<(i64, &'static str)>,
+    user_id: i64,
+    page: &'a str,
+}
+
+fn team_not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("Team not found")
+}
+
+#[get("/teams")]
+pub async fn teams_page(
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let teams = data
+        .storage
+        .teams(&user.user_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(TeamView::from)
+        .collect();
+    let json = json!(TeamsTemplate {
+        app_name: APP_NAME,
+        teams,
+        user_id: user.user_id,
+        page: "teams"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TeamForm {
+    name: String,
+}
+
+#[post("/teams")]
+pub async fn create_team(
+    form: web::Form<TeamForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let name: String = form.name.trim().chars().take(MAX_NAME_LENGTH).collect();
+    if name.is_empty() {
+        return Ok(HttpResponse::BadRequest().body("Team name is empty"));
+    }
+    let team = data
+        .storage
+        .create_team(&user.user_id, &name)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(see_other(&format!("/teams/{}", team.id)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TeamQuery {
+    status: Option<String>,
+}
+
+/// Shared list of the team, visible to all its members.
+#[get("/teams/{team_id}")]
+pub async fn team_page(
+    web::Path(team_id): web::Path<i64>,
+    web::Query(q): web::Query<TeamQuery>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Read)?;
+    let storage = &data.storage;
+    let (team, role) = match storage
+        .get_team(&user.user_id, &team_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(team) => team,
+        None => return Ok(team_not_found()),
+    };
+    let status = match q.status.as_deref() {
+        Some("archived") => ArticleStatus::Archived,
+        _ => ArticleStatus::Pending,
+    };
+    let filter = ArticleFilter {
+        status: Some(status),
+        limit: TEAM_PAGE_SIZE,
+        ..ArticleFilter::default()
+    };
+    let links = storage
+        .articles(&team.account_id, &filter)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|a| {
+            let url = a.data.url.to_string();
+            (a.id, url.clone(), a.data.title.unwrap_or(url))
+        })
+        .collect();
+    let members = storage
+        .team_members(&team_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|(id, role)| (id, role.as_str()))
+        .collect();
+    let json = json!(TeamTemplate {
+        app_name: APP_NAME,
+        team: (team, role).into(),
+        archived: status == ArticleStatus::Archived,
+        links,
+        members,
+        user_id: user.user_id,
+        page: "team"
+    });
+    let rendered = &data
+        .hb
+        .render("index", &json)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().body(rendered))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TeamLinkForm {
+    url: String,
+}
+
+/// Saves the link to the team list, for editors and owners.
+#[post("/teams/{team_id}/links")]
+pub async fn save_team_link(
+    web::Path(team_id): web::Path<i64>,
+    form: web::Form<TeamLinkForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let storage = &data.storage;
+    let team = match storage
+        .get_team(&user.user_id, &team_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some((team, role)) if role.can_write() => team,
+        Some(_) => return Ok(HttpResponse::Forbidden().body("Viewers can't save links")),
+        None => return Ok(team_not_found()),
+    };
+    let url = match http_url(&form.url) {
+        Some(url) => url,
+        None => return Ok(HttpResponse::BadRequest().body("Only http and https links are saved")),
+    };
+    save_link(storage, team.account_id, url, None)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(see_other(&format!("/teams/{}", team_id)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MemberForm {
+    /// Telegram id of the user, shown to them on the teams page.
+    user_id: i64,
+    role: String,
+}
+
+/// Adds a member or changes the role of one, for owners only.
+#[post("/teams/{team_id}/members")]
+pub async fn set_team_member(
+    web::Path(team_id): web::Path<i64>,
+    form: web::Form<MemberForm>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let role = match Role::parse(&form.role) {
+        Some(role) => role,
+        None => return Ok(HttpResponse::BadRequest().body("Unknown role")),
+    };
+    let storage = &data.storage;
+    match storage
+        .get_team(&user.user_id, &team_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some((_, Role::Owner)) => (),
+        Some(_) => return Ok(HttpResponse::Forbidden().body("Only owners manage members")),
+        None => return Ok(team_not_found()),
+    };
+    if form.user_id == user.user_id && role != Role::Owner {
+        return Ok(HttpResponse::BadRequest().body("Owners can't change their own role"));
+    }
+    storage
+        .set_team_member(&team_id, &form.user_id, role)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(see_other(&format!("/teams/{}", team_id)))
+}
+
+/// Removes a member, owners remove others and any member can leave.
+/// Owners can't leave, so a team always has one.
+#[post("/teams/{team_id}/members/{member_id}/remove")]
+pub async fn remove_team_member(
+    web::Path((team_id, member_id)): web::Path<(i64, i64)>,
+    data: web::Data<AppState<'_>>,
+    user: AuthUser,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    user.require(Scope::Write)?;
+    let storage = &data.storage;
+    let role = match storage
+        .get_team(&user.user_id, &team_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some((_, role)) => role,
+        None => return Ok(team_not_found()),
+    };
+    let leaving = member_id == user.user_id;
+    if leaving && role == Role::Owner {
+        return Ok(HttpResponse::BadRequest().body("Owners can't leave the team"));
+    }
+    if !leaving && role != Role::Owner {
+        return Ok(HttpResponse::Forbidden().body("Only owners manage members"));
+    }
+    if !storage
+        .remove_team_member(&team_id, &member_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        return Ok(HttpResponse::NotFound().body("Member not found"));
+    }
+    if leaving {
+        Ok(see_other("/teams"))
+    } else {
+        Ok(see_other(&format!("/teams/{}", team_id)))
+    }
+}
@@ -1,3 +1,4 @@
+use crate::auth::generate_token;
 use actix_web::client::Client;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
@@ -11,7 +12,21 @@ pub struct TelegramResponse<T> {
 #[derive(Clone, Debug, Deserialize)]
 pub struct Update {
     pub update_id: i32,
-    pub message: Message,
+    #[serde(default)]
+    pub message: Option<Message>,
+    /// Sent when a button of an inline keyboard is pressed.
+    #[serde(default)]
+    pub callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: User,
+    #[serde(default)]
+    pub message: Option<Message>,
+    #[serde(default)]
+    pub data: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -24,14 +39,68 @@ pub struct Message {
     pub chat: Chat,
 }
 
+impl Message {
+    /// User who sent the message, the chat itself for messages without one, like channel posts.
+    pub fn sender_id(&self) -> i64 {
+        self.from.as_ref().map(|u| u.id).unwrap_or(self.chat.id)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Chat {
     pub id: i64,
+    #[serde(rename = "type")]
+    pub kind: ChatType,
+    /// Title of groups and channels.
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+impl Chat {
+    pub fn is_private(&self) -> bool {
+        self.kind == ChatType::Private
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatType {
+    Private,
+    Group,
+    Supergroup,
+    Channel,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Text of the message as addressed to the bot: commands lose the `@bot` suffix
+/// groups add to them and mentions of the bot are cut out. Returns the text and
+/// whether the bot was mentioned, or `None` for commands to other bots.
+pub fn addressed_text(text: &str, bot_username: &str) -> Option<(String, bool)> {
+    let text = text.trim();
+    if text.starts_with('/') {
+        let (command, args) = text.split_at(text.find(char::is_whitespace).unwrap_or(text.len()));
+        return match command.split_once('@') {
+            Some((command, bot)) if bot.eq_ignore_ascii_case(bot_username) => {
+                Some((format!("{}{}", command, args), false))
+            }
+            Some(_) => None,
+            None => Some((text.to_string(), false)),
+        };
+    }
+    let mention = format!("@{}", bot_username).to_ascii_lowercase();
+    match text.to_ascii_lowercase().find(&mention) {
+        Some(start) if !bot_username.is_empty() => {
+            let rest = format!("{}{}", &text[..start], &text[start + mention.len()..]);
+            Some((rest.trim().to_string(), true))
+        }
+        _ => Some((text.to_string(), false)),
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct User {
-    pub id: i32,
+    pub id: i64,
     pub is_bot: bool,
     pub first_name: String,
     #[serde(default)]
@@ -53,6 +122,17 @@ pub enum ParseMode {
     HTML,
 }
 
+#[derive(Debug, Serialize)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    pub callback_data: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct SendMessage<'a> {
     pub chat_id: String,
@@ -61,6 +141,62 @@ pub struct SendMessage<'a> {
     pub parse_mode: Option<ParseMode>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reply_to_message_id: Option<&'a i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+/// File sent to the chat, uploaded as multipart form data since it's not on the web.
+pub struct SendDocument<'a> {
+    pub chat_id: String,
+    pub reply_to_message_id: Option<&'a i64>,
+    pub caption: Option<String>,
+    pub file_name: String,
+    pub content_type: &'a str,
+    pub content: Vec<u8>,
+}
+
+impl SendDocument<'_> {
+    /// Form with the fields and the file as the `document` field.
+    fn multipart(&self, boundary: &str) -> Vec<u8> {
+        let mut fields = vec![("chat_id", self.chat_id.clone())];
+        if let Some(id) = self.reply_to_message_id {
+            fields.push(("reply_to_message_id", id.to_string()));
+        }
+        if let Some(ref caption) = self.caption {
+            fields.push(("caption", caption.clone()));
+        }
+        let mut body = vec![];
+        for (name, value) in fields {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                    boundary, name, value
+                )
+                .as_bytes(),
+            );
+        }
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"document\"; filename=\"{}\"\r\n\
+                Content-Type: {}\r\n\r\n",
+                boundary,
+                self.file_name.replace('"', ""),
+                self.content_type
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&self.content);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        body
+    }
+}
+
+/// Confirms a button press, the text is shown to the user as a notification.
+#[derive(Debug, Serialize)]
+pub struct AnswerCallbackQuery {
+    pub callback_query_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
 }
 
 pub struct TelegramClient<'a> {
@@ -87,6 +223,11 @@ impl<'a> TelegramClient<'a> {
         }
     }
 
+    /// Token of the bot, to make another client for work done in the background.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
     pub async fn get_updates(&self, update_id: i32) -> Result<TelegramResponse<Vec<Update>>> {
         Ok(self
             .async_http_client
@@ -109,6 +250,17 @@ impl<'a> TelegramClient<'a> {
             })?)
     }
 
+    pub async fn get_me(&self) -> Result<TelegramResponse<User>> {
+        self.async_http_client
+            .get(&self.api_url("getMe"))
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to get the bot from telegram server {}", e))?
+            .json()
+            .await
+            .context("Failed to get the bot from telegram server")
+    }
+
     pub async fn set_command(&self, message: &Vec<BotCommand<'_>>) -> Result<()> {
         let json_body = serde_json::to_string(message).with_context(|| {
             format!(
@@ -142,4 +294,102 @@ impl<'a> TelegramClient<'a> {
             .map_err(|e| anyhow::anyhow!("{}", e))
             .map(|_| ())?)
     }
+
+    pub async fn send_document(&self, document: SendDocument<'_>) -> Result<()> {
+        let boundary = format!("save2read{}", generate_token());
+        self.async_http_client
+            .post(&self.api_url("sendDocument"))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .send_body(document.multipart(&boundary))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send document {}: {}", document.file_name, e))
+            .map(|_| ())
+    }
+
+    pub async fn answer_callback_query(&self, answer: AnswerCallbackQuery) -> Result<()> {
+        let json_body = serde_json::to_string(&answer).with_context(|| {
+            format!(
+                "Failed to serialize body to json for answering callback {:?}",
+                answer
+            )
+        });
+        self.async_http_client
+            .post(&self.api_url("answerCallbackQuery"))
+            .header("Content-Type", "application/json")
+            .send_body(json_body?)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multipart() {
+        let document = SendDocument {
+            chat_id: "42".to_string(),
+            reply_to_message_id: None,
+            caption: Some("Book".to_string()),
+            file_name: "book.epub".to_string(),
+            content_type: "application/epub+zip",
+            content: b"zip".to_vec(),
+        };
+        let body = String::from_utf8(document.multipart("b")).unwrap();
+        assert_eq!(
+            "--b\r\nContent-Disposition: form-data; name=\"chat_id\"\r\n\r\n42\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"caption\"\r\n\r\nBook\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"document\"; filename=\"book.epub\"\r\n\
+            Content-Type: application/epub+zip\r\n\r\nzip\r\n--b--\r\n",
+            body
+        );
+    }
+
+    #[test]
+    fn test_addressed_text() {
+        let text = |t| addressed_text(t, "save_bot");
+        assert_eq!(Some(("/auth".to_string(), false)), text("/auth"));
+        assert_eq!(Some(("/auth".to_string(), false)), text("/auth@Save_Bot"));
+        assert_eq!(
+            Some(("/note 1 text @save_bot".to_string(), false)),
+            text("/note@save_bot 1 text @save_bot")
+        );
+        assert_eq!(None, text("/auth@other_bot"));
+        assert_eq!(
+            Some(("https://example.com".to_string(), true)),
+            text("@Save_bot https://example.com")
+        );
+        assert_eq!(
+            Some(("https://example.com".to_string(), false)),
+            text("https://example.com")
+        );
+    }
+
+    #[test]
+    fn test_chat_type() {
+        let update: Update = serde_json::from_str(
+            r#"{"update_id": 1, "message": {"message_id": 2, "text": "hi",
+                "from": {"id": 3, "is_bot": false, "first_name": "A"},
+                "chat": {"id": -4, "type": "supergroup", "title": "Team"}}}"#,
+        )
+        .unwrap();
+        let message = update.message.unwrap();
+        assert_eq!(ChatType::Supergroup, message.chat.kind);
+        assert!(!message.chat.is_private());
+        assert_eq!(3, message.sender_id());
+        // Ids of newer accounts don't fit in 32 bits.
+        let message: Message = serde_json::from_str(
+            r#"{"message_id": 6, "from": {"id": 5000000000, "is_bot": false, "first_name": "B"},
+                "chat": {"id": 5000000000, "type": "private"}}"#,
+        )
+        .unwrap();
+        assert_eq!(5_000_000_000, message.sender_id());
+        let chat: Chat = serde_json::from_str(r#"{"id": 5, "type": "forum"}"#).unwrap();
+        assert_eq!(ChatType::Unknown, chat.kind);
+    }
 }
@@ -0,0 +1,535 @@
+//! Subset of the Wallabag v2 api used by its mobile apps and browser extensions.
+//!
+//! There are no passwords in save2read, so clients log in with a personal api token
+//! as the password, and the same token is returned as their access and refresh token.
+use crate::api::{authorize, lenient_number, parse_body, ApiError, ApiUser};
+use crate::auth::{hash_token, Scope};
+use crate::extractor::readable;
+use crate::routes::{http_url, AppState};
+use crate::save_link;
+use crate::storage::{Article, ArticleFilter, ArticleStatus};
+use actix_web::*;
+use serde::*;
+use serde_json::{json, Value};
+use url::Url;
+
+const VERSION: &str = "2.4.2";
+const DEFAULT_PER_PAGE: i64 = 30;
+const MAX_PER_PAGE: i64 = 500;
+
+/// Wallabag tags have numeric ids, here they are derived from the label.
+pub fn tag_id(label: &str) -> i64 {
+    let hash = openssl::sha::sha256(label.as_bytes());
+    i64::from(u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) & 0x7fff_ffff)
+}
+
+fn tag(label: &str) -> Value {
+    json!({
+        "id": tag_id(label),
+        "label": label,
+        "slug": label.replace(' ', "-"),
+    })
+}
+
+fn date(ts: Option<i64>) -> Value {
+    match ts {
+        Some(ts) => json!(time::OffsetDateTime::from_unix_timestamp(ts).format("%FT%T%z")),
+        None => Value::Null,
+    }
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',').map(|t| t.to_string()).collect()
+}
+
+fn entry(article: &Article, content: Option<String>) -> Value {
+    let url = article.data.url.as_str();
+    json!({
+        "id": article.id,
+        "user_id": article.data.user_id,
+        "url": url,
+        "given_url": url,
+        "title": article.data.title.clone().unwrap_or_else(|| url.to_string()),
+        "content": content.unwrap_or_default(),
+        "is_archived": match article.status {
+            ArticleStatus::Pending => 0,
+            ArticleStatus::Archived => 1,
+        },
+        "is_starred": if article.starred { 1 } else { 0 },
+        "is_public": false,
+        "tags": article.tags.iter().map(|t| tag(t)).collect::<Vec<Value>>(),
+        "annotations": [],
+        "created_at": date(article.created_at),
+        "updated_at": date(article.updated_at.or(article.created_at)),
+        "reading_time": article.reading_minutes.unwrap_or(0),
+        "domain_name": article.data.url.host_str(),
+        "mimetype": "text/html",
+        "language": Value::Null,
+        "preview_picture": Value::Null,
+        "http_status": Value::Null,
+        "_links": {
+            "self": { "href": format!("/api/entries/{}", article.id) }
+        }
+    })
+}
+
+async fn entry_response(
+    data: &AppState<'_>,
+    user_id: &i64,
+    id: &i64,
+) -> Result<HttpResponse, ApiError> {
+    match data.storage.get_article(user_id, id).await? {
+        Some(article) => {
+            let content = data.storage.get_content(user_id, id).await?;
+            Ok(HttpResponse::Ok().json(entry(&article, content)))
+        }
+        None => Err(ApiError::not_found()),
+    }
+}
+
+/// Registered as plain resources: an `/api` scope would shadow other `/api/...` routes.
+/// Clients call each api path both with and without the `.json` suffix.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/oauth/v2/token").route(web::post().to(token)));
+    for suffix in &["", ".json"] {
+        let path = |p: &str| format!("/api{}{}", p, suffix);
+        cfg.service(web::resource(path("/version")).route(web::get().to(version)))
+            .service(web::resource(path("/info")).route(web::get().to(info)))
+            .service(web::resource(path("/tags")).route(web::get().to(user_tags)))
+            .service(web::resource(path("/entries/exists")).route(web::get().to(exists)))
+            .service(
+                web::resource(path("/entries"))
+                    .route(web::get().to(list_entries))
+                    .route(web::post().to(create_entry)),
+            )
+            .service(
+                web::resource(path(r"/entries/{id:\d+}"))
+                    .route(web::get().to(get_entry))
+                    .route(web::patch().to(update_entry))
+                    .route(web::delete().to(delete_entry)),
+            )
+            .service(
+                web::resource(path(r"/entries/{id:\d+}/tags"))
+                    .route(web::get().to(entry_tags))
+                    .route(web::post().to(add_entry_tags)),
+            )
+            .service(
+                web::resource(path(r"/entries/{id:\d+}/tags/{tag_id:\d+}"))
+                    .route(web::delete().to(delete_entry_tag)),
+            );
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+fn oauth_error(error: &str, description: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": error,
+        "error_description": description,
+    }))
+}
+
+pub async fn token(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState<'_>>,
+) -> Result<HttpResponse, ApiError> {
+    let request: TokenRequest = match parse_body(&req, &body) {
+        Ok(r) => r,
+        Err(e) => return Ok(oauth_error("invalid_request", &e)),
+    };
+    let secret = match request.grant_type.as_str() {
+        "password" => request.password,
+        "refresh_token" => request.refresh_token,
+        _ => {
+            return Ok(oauth_error(
+                "unsupported_grant_type",
+                "Unsupported grant type",
+            ))
+        }
+    };
+    let token = match secret {
+        Some(secret) => data
+            .storage
+            .use_api_token(&hash_token(&secret))
+            .await?
+            .map(|t| (secret, t)),
+        None => None,
+    };
+    // Clients require some username, numeric one must be the id of the token owner.
+    let username = request.username;
+    let username_matches = |user_id: i64| match username.as_deref().map(str::parse::<i64>) {
+        Some(Ok(id)) => id == user_id,
+        _ => true,
+    };
+    match token {
+        Some((secret, t)) if username_matches(t.user_id) => Ok(HttpResponse::Ok().json(json!({
+            "access_token": secret,
+            "refresh_token": secret,
+            "expires_in": 315_360_000,
+            "token_type": "bearer",
+            "scope": Value::Null,
+        }))),
+        _ => Ok(oauth_error(
+            "invalid_grant",
+            "Use personal api token of save2read as the password",
+        )),
+    }
+}
+
+pub async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(VERSION)
+}
+
+pub async fn info() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "appname": "wallabag",
+        "version": VERSION,
+        "allowed_registration": false,
+    }))
+}
+
+pub async fn user_tags(
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Read)?;
+    let tags: Vec<Value> = data
+        .storage
+        .user_tags(&user.user_id)
+        .await?
+        .iter()
+        .map(|t| tag(t))
+        .collect();
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExistsQuery {
+    pub url: String,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub return_id: Option<i64>,
+}
+
+pub async fn exists(
+    web::Query(q): web::Query<ExistsQuery>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Read)?;
+    let id = match Url::parse(&q.url) {
+        Ok(url) => data.storage.find_by_url(&user.user_id, &url).await?,
+        Err(_) => None,
+    };
+    if q.return_id == Some(1) {
+        Ok(HttpResponse::Ok().json(json!({ "exists": id })))
+    } else {
+        Ok(HttpResponse::Ok().json(json!({ "exists": id.is_some() })))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EntriesQuery {
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub archive: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub starred: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub page: Option<i64>,
+    #[serde(default, rename = "perPage", deserialize_with = "lenient_number")]
+    pub per_page: Option<i64>,
+    #[serde(default)]
+    pub tags: Option<String>,
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+/// Entries are always sorted by creation time, newest first.
+pub async fn list_entries(
+    web::Query(q): web::Query<EntriesQuery>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Read)?;
+    let page = q.page.unwrap_or(1).max(1);
+    let per_page = q
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let offset = (page - 1)
+        .checked_mul(per_page)
+        .ok_or_else(|| ApiError::bad_request("Page is out of range"))?;
+    let filter = ArticleFilter {
+        status: match q.archive {
+            Some(0) => Some(ArticleStatus::Pending),
+            Some(1) => Some(ArticleStatus::Archived),
+            _ => None,
+        },
+        starred: match q.starred {
+            Some(0) => Some(false),
+            Some(1) => Some(true),
+            _ => None,
+        },
+        tags: q
+            .tags
+            .as_deref()
+            .map(split_tags)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|t| !t.trim().is_empty())
+            .collect(),
+        limit: per_page,
+        offset,
+        ..ArticleFilter::default()
+    };
+    let total = data.storage.count_articles(&user.user_id, &filter).await?;
+    let with_content = q.detail.as_deref() != Some("metadata");
+    let mut items = vec![];
+    for article in data.storage.articles(&user.user_id, &filter).await? {
+        let content = if with_content {
+            data.storage.get_content(&user.user_id, &article.id).await?
+        } else {
+            None
+        };
+        items.push(entry(&article, content));
+    }
+    let pages = ((total + per_page - 1) / per_page).max(1);
+    let href = |p: i64| json!({ "href": format!("/api/entries?page={}&perPage={}", p, per_page) });
+    let mut links = json!({
+        "self": href(page),
+        "first": href(1),
+        "last": href(pages),
+    });
+    if page < pages {
+        links["next"] = href(page + 1);
+    }
+    Ok(HttpResponse::Ok().json(json!({
+        "page": page,
+        "limit": per_page,
+        "pages": pages,
+        "total": total,
+        "_links": links,
+        "_embedded": { "items": items },
+    })))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewEntry {
+    pub url: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Option<String>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub archive: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub starred: Option<i64>,
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+/// Like in Wallabag, saving an already saved url returns the existing entry.
+pub async fn create_entry(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Write)?;
+    let new_entry: NewEntry = parse_body(&req, &body).map_err(ApiError::bad_request)?;
+    let url = http_url(&new_entry.url)
+        .ok_or_else(|| ApiError::bad_request("Only http and https urls are saved"))?;
+    let storage = &data.storage;
+    if let Some(id) = storage.find_by_url(&user.user_id, &url).await? {
+        return entry_response(&data, &user.user_id, &id).await;
+    }
+    let title = new_entry.title.filter(|t| !t.trim().is_empty());
+    let content = match new_entry.content.filter(|_| title.is_some()) {
+        Some(content) => readable(&content, &url)?,
+        None => None,
+    };
+    let mut id = save_link(storage, user.user_id, url, title).await?;
+    if content.is_some() {
+        storage.set_content(&user.user_id, &id, content).await?;
+    }
+    if let Some(tags) = new_entry.tags {
+        storage
+            .set_tags(&user.user_id, &id, &split_tags(&tags))
+            .await?;
+    }
+    if new_entry.starred == Some(1) {
+        storage.set_starred(&user.user_id, &id, true).await?;
+    }
+    if new_entry.archive == Some(1) {
+        id = storage
+            .archive(&user.user_id, &id)
+            .await?
+            .ok_or_else(ApiError::not_found)?;
+    }
+    entry_response(&data, &user.user_id, &id).await
+}
+
+pub async fn get_entry(
+    web::Path(id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Read)?;
+    entry_response(&data, &user.user_id, &id).await
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EntryUpdate {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Option<String>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub archive: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    pub starred: Option<i64>,
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+pub async fn update_entry(
+    req: HttpRequest,
+    body: web::Bytes,
+    web::Path(id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Write)?;
+    let update: EntryUpdate = parse_body(&req, &body).map_err(ApiError::bad_request)?;
+    let storage = &data.storage;
+    let article = storage
+        .get_article(&user.user_id, &id)
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let id = match (update.archive, article.status) {
+        (Some(1), ArticleStatus::Pending) => storage.archive(&user.user_id, &id).await?,
+        (Some(0), ArticleStatus::Archived) => storage.unarchive(&user.user_id, &id).await?,
+        _ => Some(id),
+    }
+    .ok_or_else(ApiError::not_found)?;
+    if let Some(title) = update.title {
+        let title = Some(title).filter(|t| !t.trim().is_empty());
+        storage.update_title(&user.user_id, &id, title).await?;
+    }
+    if let Some(tags) = update.tags {
+        storage
+            .set_tags(&user.user_id, &id, &split_tags(&tags))
+            .await?;
+    }
+    if let Some(starred) = update.starred {
+        storage
+            .set_starred(&user.user_id, &id, starred == 1)
+            .await?;
+    }
+    if let Some(content) = update.content {
+        let content = readable(&content, &article.data.url)?;
+        storage.set_content(&user.user_id, &id, content).await?;
+    }
+    entry_response(&data, &user.user_id, &id).await
+}
+
+/// Responds with the deleted entry, like Wallabag does.
+pub async fn delete_entry(
+    web::Path(id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Write)?;
+    let storage = &data.storage;
+    let article = storage
+        .get_article(&user.user_id, &id)
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let content = storage.get_content(&user.user_id, &id).await?;
+    match article.status {
+        ArticleStatus::Pending => storage.delete_pending(&user.user_id, &id).await?,
+        ArticleStatus::Archived => storage.delete_archived(&user.user_id, &id).await?,
+    }
+    Ok(HttpResponse::Ok().json(entry(&article, content)))
+}
+
+pub async fn entry_tags(
+    web::Path(id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Read)?;
+    let article = data
+        .storage
+        .get_article(&user.user_id, &id)
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let tags: Vec<Value> = article.tags.iter().map(|t| tag(t)).collect();
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TagsForm {
+    pub tags: String,
+}
+
+pub async fn add_entry_tags(
+    req: HttpRequest,
+    body: web::Bytes,
+    web::Path(id): web::Path<i64>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Write)?;
+    let form: TagsForm = parse_body(&req, &body).map_err(ApiError::bad_request)?;
+    let article = data
+        .storage
+        .get_article(&user.user_id, &id)
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let mut tags = article.tags;
+    tags.extend(split_tags(&form.tags));
+    data.storage.set_tags(&user.user_id, &id, &tags).await?;
+    entry_response(&data, &user.user_id, &id).await
+}
+
+pub async fn delete_entry_tag(
+    web::Path((id, removed)): web::Path<(i64, i64)>,
+    data: web::Data<AppState<'_>>,
+    user: ApiUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = authorize(user, Scope::Write)?;
+    let article = data
+        .storage
+        .get_article(&user.user_id, &id)
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let tags: Vec<String> = article
+        .tags
+        .into_iter()
+        .filter(|t| tag_id(t) != removed)
+        .collect();
+    data.storage.set_tags(&user.user_id, &id, &tags).await?;
+    entry_response(&data, &user.user_id, &id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tag_id;
+
+    #[test]
+    fn test_tag_id() {
+        assert_eq!(tag_id("rust"), tag_id("rust"));
+        assert_ne!(tag_id("rust"), tag_id("go"));
+        assert!(tag_id("rust") > 0);
+    }
+}
@@ -0,0 +1,7 @@
+// Service worker is needed to install the app and get into the share menu,
+// pages are always loaded from the network.
+self.addEventListener("install", () => self.skipWaiting());
+
+self.addEventListener("activate", (event) => event.waitUntil(self.clients.claim()));
+
+self.addEventListener("fetch", (event) => event.respondWith(fetch(event.request)));
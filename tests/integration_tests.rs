@@ -4,6 +4,7 @@ use actix_web::body::MessageBody;
 use actix_web::http;
 use actix_web::{dev::ServiceResponse, test, App};
 use handlebars::Handlebars;
+use save2read::api::*;
 use save2read::auth::*;
 use save2read::routes::*;
 use save2read::storage::*;
@@ -98,8 +99,8 @@ async fn test_do_archive_correct_auth() {
     let result = test::call_service(&mut app, authorized_req).await;
 
     assert_eq!(http::StatusCode::OK, result.status());
-    assert_eq!(1, storage.archived_list(&1).await.unwrap().len());
-    assert_eq!(0, storage.pending_list(&1).await.unwrap().len());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Archived).await.len());
+    assert_eq!(0, list(&storage, 1, ArticleStatus::Pending).await.len());
 }
 
 #[actix_rt::test]
@@ -117,8 +118,8 @@ async fn test_do_archive_incorrect_auth() {
     let result = test::call_service(&mut app, authorized_req).await;
 
     assert_eq!(http::StatusCode::NOT_FOUND, result.status());
-    assert_eq!(0, storage.archived_list(&1).await.unwrap().len());
-    assert_eq!(1, storage.pending_list(&1).await.unwrap().len());
+    assert_eq!(0, list(&storage, 1, ArticleStatus::Archived).await.len());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Pending).await.len());
 }
 
 #[actix_rt::test]
@@ -136,7 +137,7 @@ async fn test_delete_pending_incorrect_auth() {
     let result = test::call_service(&mut app, authorized_req).await;
 
     assert_eq!(http::StatusCode::OK, result.status());
-    assert_eq!(1, storage.pending_list(&1).await.unwrap().len());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Pending).await.len());
 }
 
 #[actix_rt::test]
@@ -154,7 +155,7 @@ async fn test_delete_pending_correct_auth() {
     let result = test::call_service(&mut app, authorized_req).await;
 
     assert_eq!(http::StatusCode::OK, result.status());
-    assert_eq!(0, storage.pending_list(&1).await.unwrap().len());
+    assert_eq!(0, list(&storage, 1, ArticleStatus::Pending).await.len());
 }
 
 #[actix_rt::test]
@@ -172,7 +173,7 @@ async fn test_delete_archived_incorrect_auth() {
     let result = test::call_service(&mut app, authorized_req).await;
 
     assert_eq!(http::StatusCode::OK, result.status());
-    assert_eq!(1, storage.archived_list(&1).await.unwrap().len());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Archived).await.len());
 }
 
 #[actix_rt::test]
@@ -190,7 +191,7 @@ async fn test_delete_archived_correct_auth() {
     let result = test::call_service(&mut app, authorized_req).await;
 
     assert_eq!(http::StatusCode::OK, result.status());
-    assert_eq!(0, storage.archived_list(&1).await.unwrap().len());
+    assert_eq!(0, list(&storage, 1, ArticleStatus::Archived).await.len());
 }
 
 #[actix_rt::test]
@@ -217,8 +218,8 @@ async fn test_do_unarchive_correct_auth() {
     let result = test::call_service(&mut app, authorized_req).await;
 
     assert_eq!(http::StatusCode::OK, result.status());
-    assert_eq!(0, storage.archived_list(&1).await.unwrap().len());
-    assert_eq!(1, storage.pending_list(&1).await.unwrap().len());
+    assert_eq!(0, list(&storage, 1, ArticleStatus::Archived).await.len());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Pending).await.len());
 }
 
 #[actix_rt::test]
@@ -236,8 +237,2293 @@ async fn test_do_unarchive_incorrect_auth() {
     let result = test::call_service(&mut app, authorized_req).await;
 
     assert_eq!(http::StatusCode::NOT_FOUND, result.status());
-    assert_eq!(1, storage.archived_list(&1).await.unwrap().len());
-    assert_eq!(0, storage.pending_list(&1).await.unwrap().len());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Archived).await.len());
+    assert_eq!(0, list(&storage, 1, ArticleStatus::Pending).await.len());
+}
+
+#[actix_rt::test]
+async fn test_index_with_api_token() {
+    let state = init_state().await;
+    create_article(&state.storage, 1, "http://link", "Title").await;
+    let token = issue_api_token(&state.storage, &1, "script", &[Scope::Read])
+        .await
+        .unwrap();
+    let mut app = app(state).await;
+
+    let req = test::TestRequest::get()
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .uri("/")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+
+    assert_eq!(http::StatusCode::OK, result.status());
+    let body = String::from_utf8(test::read_body(result).await.to_vec()).unwrap();
+    assert!(body.contains("http://link"));
+}
+
+#[actix_rt::test]
+async fn test_unknown_api_token() {
+    let mut app = app(init_state().await).await;
+    let req = test::TestRequest::get()
+        .header(http::header::AUTHORIZATION, "Bearer unknown")
+        .uri("/")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::UNAUTHORIZED, result.status());
+}
+
+#[actix_rt::test]
+async fn test_api_token_scopes() {
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    create_article(&state.storage, 1, "http://linku1p", "Title").await;
+    let read_token = issue_api_token(&state.storage, &1, "reader", &[Scope::Read])
+        .await
+        .unwrap();
+    let write_token = issue_api_token(&state.storage, &1, "writer", &[Scope::Write])
+        .await
+        .unwrap();
+    let mut app = app(state).await;
+
+    let req = test::TestRequest::post()
+        .header(
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", read_token),
+        )
+        .uri("/archive/1")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::FORBIDDEN, result.status());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Pending).await.len());
+
+    let req = test::TestRequest::post()
+        .header(
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", write_token),
+        )
+        .uri("/archive/1")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::OK, result.status());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Archived).await.len());
+
+    let tokens = storage.api_tokens(&1).await.unwrap();
+    assert!(tokens.iter().all(|t| t.last_used_at.is_some()));
+}
+
+#[actix_rt::test]
+async fn test_create_token_from_settings() {
+    let state = init_state().await;
+    let token_storage = state.token_storage.clone();
+    let storage = state.storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1i64, &token_storage).await;
+
+    let req = test::TestRequest::post()
+        .cookie(cookie.clone())
+        .uri("/settings/tokens")
+        .set_form(&[("name", "ci"), ("read", "on")])
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::OK, result.status());
+
+    let tokens = storage.api_tokens(&1).await.unwrap();
+    assert_eq!(1, tokens.len());
+    assert_eq!("ci", tokens[0].name);
+    assert_eq!("read", tokens[0].scopes);
+
+    let token = issue_api_token(&storage, &1, "other", &[Scope::Read, Scope::Write])
+        .await
+        .unwrap();
+    let req = test::TestRequest::get()
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .uri("/settings")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::FORBIDDEN, result.status());
+}
+
+#[actix_rt::test]
+async fn test_api_list_pagination() {
+    let state = init_state().await;
+    for i in 0..3 {
+        create_article(&state.storage, 1, &format!("http://link{}", i), "Title").await;
+    }
+    create_article(&state.storage, 2, "http://other", "Title").await;
+    let token = api_token(&state, 1).await;
+    let mut app = app(state).await;
+
+    let page: ArticleList = api_call(
+        &mut app,
+        test::TestRequest::get(),
+        "/api/v1/articles?limit=2",
+        &token,
+    )
+    .await;
+    assert_eq!(
+        vec!["http://link2/", "http://link1/"],
+        page.articles
+            .iter()
+            .map(|a| a.url.as_str())
+            .collect::<Vec<&str>>()
+    );
+    let cursor = page.next_cursor.unwrap();
+
+    let page: ArticleList = api_call(
+        &mut app,
+        test::TestRequest::get(),
+        &format!("/api/v1/articles?limit=2&cursor={}", cursor),
+        &token,
+    )
+    .await;
+    assert_eq!(1, page.articles.len());
+    assert_eq!("http://link0/", page.articles[0].url);
+    assert_eq!(None, page.next_cursor);
+}
+
+#[actix_rt::test]
+async fn test_api_get_article_of_other_user() {
+    let state = init_state().await;
+    let id = create_article(&state.storage, 2, "http://other", "Title").await;
+    let token = api_token(&state, 1).await;
+    let mut app = app(state).await;
+
+    let req = test::TestRequest::get()
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .uri(&format!("/api/v1/articles/{}", id))
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, result.status());
+    let body: serde_json::Value = test::read_body_json(result).await;
+    assert_eq!("not_found", body["error"]["code"]);
+}
+
+#[actix_rt::test]
+async fn test_api_no_auth() {
+    let mut app = app(init_state().await).await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/articles")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::FORBIDDEN, result.status());
+    let body: serde_json::Value = test::read_body_json(result).await;
+    assert_eq!("forbidden", body["error"]["code"]);
+}
+
+#[actix_rt::test]
+async fn test_api_create_update_delete() {
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    let token = api_token(&state, 1).await;
+    let mut app = app(state).await;
+
+    let created: ArticleJson = api_call(
+        &mut app,
+        test::TestRequest::post().set_json(&serde_json::json!({
+            "url": "http://link",
+            "title": "Title",
+            "tags": ["Rust", "web"]
+        })),
+        "/api/v1/articles",
+        &token,
+    )
+    .await;
+    assert_eq!("pending", created.status);
+    assert_eq!(vec!["rust", "web"], created.tags);
+    assert!(created.created_at.is_some());
+
+    let updated: ArticleJson = api_call(
+        &mut app,
+        test::TestRequest::patch().set_json(&serde_json::json!({
+            "status": "archived",
+            "title": "New title",
+            "tags": ["later"]
+        })),
+        &format!("/api/v1/articles/{}", created.id),
+        &token,
+    )
+    .await;
+    assert_eq!(created.id, updated.id);
+    assert_eq!("archived", updated.status);
+    assert_eq!(Some("New title".to_string()), updated.title);
+    assert_eq!(vec!["later"], updated.tags);
+
+    let page: ArticleList = api_call(
+        &mut app,
+        test::TestRequest::get(),
+        "/api/v1/articles?status=archived&tag=later",
+        &token,
+    )
+    .await;
+    assert_eq!(1, page.articles.len());
+
+    let req = test::TestRequest::delete()
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .uri(&format!("/api/v1/articles/{}", created.id))
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NO_CONTENT, result.status());
+    assert_eq!(0, list(&storage, 1, ArticleStatus::Archived).await.len());
+}
+
+#[actix_rt::test]
+async fn test_api_invalid_payload() {
+    let state = init_state().await;
+    let token = api_token(&state, 1).await;
+    let mut app = app(state).await;
+
+    let req = test::TestRequest::post()
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .set_json(&serde_json::json!({"url": "not a url"}))
+        .uri("/api/v1/articles")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, result.status());
+    let body: serde_json::Value = test::read_body_json(result).await;
+    assert_eq!("bad_request", body["error"]["code"]);
+
+    let req = test::TestRequest::post()
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .set_json(&serde_json::json!({"url": "javascript:alert(1)"}))
+        .uri("/api/v1/articles")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, result.status());
+
+    let req = test::TestRequest::get()
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .uri("/api/v1/articles?status=unknown")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, result.status());
+}
+
+#[actix_rt::test]
+async fn test_openapi_document() {
+    let mut app = app(init_state().await).await;
+    let req = test::TestRequest::get()
+        .uri("/api/openapi.json")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::OK, result.status());
+    let body: serde_json::Value = test::read_body_json(result).await;
+    assert_eq!("3.0.3", body["openapi"]);
+    assert!(body["paths"]["/api/v1/articles/{id}"]["patch"].is_object());
+}
+
+#[actix_rt::test]
+async fn test_pocket_oauth_flow() {
+    let state = init_state().await;
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v3/oauth/request")
+        .header("X-Accept", "application/json")
+        .set_json(&serde_json::json!({"consumer_key": "koreader", "redirect_uri": "koreader://"}))
+        .to_request();
+    let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let code = body["code"].as_str().unwrap().to_string();
+
+    let req = test::TestRequest::post()
+        .uri("/v3/oauth/authorize")
+        .set_json(&serde_json::json!({"consumer_key": "koreader", "code": code}))
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::FORBIDDEN, result.status());
+
+    let cookie = auth(&mut app, &1i64, &token_storage).await;
+    let req = test::TestRequest::get()
+        .cookie(cookie.clone())
+        .uri("/auth/authorize?request_token=unknown&redirect_uri=https%3A%2F%2Fevil")
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, result.status());
+    let req = test::TestRequest::get()
+        .cookie(cookie.clone())
+        .uri(&format!(
+            "/auth/authorize?request_token={}&redirect_uri=https%3A%2F%2Fevil",
+            code
+        ))
+        .to_request();
+    let page = test::read_response(&mut app, req).await;
+    let page = String::from_utf8(page.to_vec()).unwrap();
+    assert!(page.contains("<b>koreader</b>") && page.contains("<code>koreader://</code>"));
+    assert!(!page.contains("evil"));
+
+    // Only the redirect uri given with the request is used.
+    let req = test::TestRequest::post()
+        .cookie(cookie.clone())
+        .uri("/auth/authorize")
+        .set_form(&[
+            ("request_token", code.as_str()),
+            ("redirect_uri", "https://evil"),
+        ])
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::FOUND, result.status());
+    assert_eq!(
+        "koreader://",
+        result.headers().get(http::header::LOCATION).unwrap()
+    );
+    let req = test::TestRequest::post()
+        .cookie(cookie)
+        .uri("/auth/authorize")
+        .set_form(&[("request_token", "unknown")])
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, result.status());
+
+    let exchange = |consumer_key: &str| {
+        test::TestRequest::post()
+            .uri("/v3/oauth/authorize")
+            .set_form(&[("consumer_key", consumer_key), ("code", code.as_str())])
+            .to_request()
+    };
+    let result = test::call_service(&mut app, exchange("other")).await;
+    assert_eq!(http::StatusCode::FORBIDDEN, result.status());
+    let body = test::read_response(&mut app, exchange("koreader")).await;
+    let fields: std::collections::HashMap<String, String> =
+        url::form_urlencoded::parse(&body).into_owned().collect();
+    assert_eq!("1", fields["username"]);
+    assert!(!fields["access_token"].is_empty());
+    let result = test::call_service(&mut app, exchange("koreader")).await;
+    assert_eq!(http::StatusCode::FORBIDDEN, result.status());
+}
+
+#[actix_rt::test]
+async fn test_pocket_add_get_send() {
+    let state = init_state().await;
+    create_article(&state.storage, 2, "http://other", "Title").await;
+    let token = api_token(&state, 1).await;
+    let mut app = app(state).await;
+
+    let req = test::TestRequest::post()
+        .uri("/v3/add")
+        .set_json(&serde_json::json!({
+            "consumer_key": "koreader",
+            "access_token": token,
+            "url": "http://link",
+            "title": "Title",
+            "tags": "a,b"
+        }))
+        .to_request();
+    let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let item_id = body["item"]["item_id"].as_str().unwrap().to_string();
+    assert_eq!("0", body["item"]["status"]);
+
+    let req = test::TestRequest::post()
+        .uri("/v3/add")
+        .set_json(&serde_json::json!({
+            "consumer_key": "koreader",
+            "access_token": token,
+            "url": "javascript:alert(1)"
+        }))
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, result.status());
+
+    let req = test::TestRequest::post()
+        .uri("/v3/get")
+        .set_json(&serde_json::json!({
+            "consumer_key": "koreader",
+            "access_token": token,
+            "state": "unread",
+            "count": "10"
+        }))
+        .to_request();
+    let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let list = body["list"].as_object().unwrap();
+    assert_eq!(1, list.len());
+    assert_eq!("http://link/", list[&item_id]["given_url"]);
+    assert_eq!("b", list[&item_id]["tags"]["b"]["tag"]);
+
+    let actions = serde_json::json!([{"action": "archive", "item_id": item_id}]).to_string();
+    let req = test::TestRequest::post()
+        .uri("/v3/send")
+        .set_form(&[
+            ("consumer_key", "koreader"),
+            ("access_token", token.as_str()),
+            ("actions", actions.as_str()),
+        ])
+        .to_request();
+    let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(serde_json::json!([true]), body["action_results"]);
+
+    let req = test::TestRequest::post()
+        .uri("/v3/get")
+        .set_json(&serde_json::json!({
+            "consumer_key": "koreader",
+            "access_token": token,
+            "state": "archive"
+        }))
+        .to_request();
+    let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!("1", body["list"][&item_id]["status"]);
+
+    let req = test::TestRequest::post()
+        .uri("/v3/add")
+        .set_json(&serde_json::json!({
+            "consumer_key": "koreader",
+            "access_token": token,
+            "url": "http://second"
+        }))
+        .to_request();
+    let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let second_id = body["item"]["item_id"].as_str().unwrap().to_string();
+    let req = test::TestRequest::post()
+        .uri("/v3/get")
+        .set_json(&serde_json::json!({
+            "consumer_key": "koreader",
+            "access_token": token,
+            "count": "-1",
+            "offset": "-5"
+        }))
+        .to_request();
+    let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let list = body["list"].as_object().unwrap();
+    assert_eq!(1, list.len());
+    assert!(list.contains_key(&second_id));
+
+    let req = test::TestRequest::post()
+        .uri("/v3/get")
+        .set_json(&serde_json::json!({"consumer_key": "koreader", "access_token": "wrong"}))
+        .to_request();
+    let result = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::UNAUTHORIZED, result.status());
+    assert_eq!("107", result.headers().get("X-Error-Code").unwrap());
+}
+
+#[actix_rt::test]
+async fn test_wallabag_android_app() {
+    replay_wallabag("tests/fixtures/wallabag/android_app.json").await;
+}
+
+#[actix_rt::test]
+async fn test_wallabag_browser_extension() {
+    replay_wallabag("tests/fixtures/wallabag/browser_extension.json").await;
+}
+
+#[actix_rt::test]
+async fn test_import_upload() {
+    let state = init_state().await;
+    create_article(&state.storage, 1, "https://example.com/saved", "Saved").await;
+    let storage = state.storage.clone();
+    let token = api_token(&state, 1).await;
+    let mut app = app(state).await;
+
+    let export = r#"<!DOCTYPE html><html><body>
+<h1>Unread</h1>
+<ul>
+<li><a href="https://example.com/saved" time_added="1600000000" tags="">Saved</a></li>
+<li><a href="https://example.com/new" time_added="1600000100" tags="rust">New</a></li>
+</ul>
+<h1>Read Archive</h1>
+<ul><li><a href="https://example.com/read" time_added="1500000000" tags="">Read</a></li></ul>
+</body></html>"#;
+    let body = format!(
+        "--boundary\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"ril_export.html\"\r\n\
+         Content-Type: text/html\r\n\r\n\
+         {}\r\n\
+         --boundary--\r\n",
+        export
+    );
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::post()
+            .uri("/import")
+            .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(
+                http::header::CONTENT_TYPE,
+                "multipart/form-data; boundary=boundary",
+            )
+            .set_payload(body)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let page = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(page.contains("Imported 2 links, skipped 1"), "{}", page);
+
+    let articles = storage
+        .articles(
+            &1,
+            &ArticleFilter {
+                limit: 10,
+                ..ArticleFilter::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(articles.len(), 3);
+    let new = articles
+        .iter()
+        .find(|a| a.data.url.as_str() == "https://example.com/new")
+        .unwrap();
+    assert_eq!(new.status, ArticleStatus::Pending);
+    assert_eq!(new.created_at, Some(1600000100));
+    assert_eq!(new.tags, vec!["rust"]);
+    let read = articles
+        .iter()
+        .find(|a| a.data.url.as_str() == "https://example.com/read")
+        .unwrap();
+    assert_eq!(read.status, ArticleStatus::Archived);
+    assert_eq!(read.created_at, Some(1500000000));
+}
+
+#[actix_rt::test]
+async fn test_export_round_trip() {
+    let state = init_state().await;
+    let pending = create_article(&state.storage, 1, "https://example.com/a", "A").await;
+    state
+        .storage
+        .set_tags(&1, &pending, &["rust".to_string()])
+        .await
+        .unwrap();
+    create_archived_article(&state.storage, 1, "https://example.com/b", "B").await;
+    create_article(&state.storage, 2, "https://example.com/other", "Other").await;
+    let storage = state.storage.clone();
+    let token = api_token(&state, 1).await;
+    let mut app = app(state).await;
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/export?format=json")
+            .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let articles: Vec<ArticleJson> = test::read_body_json(resp).await;
+    assert_eq!(
+        articles.iter().map(|a| a.url.as_str()).collect::<Vec<_>>(),
+        vec!["https://example.com/b", "https://example.com/a"]
+    );
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/export?format=csv")
+            .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let csv = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    let report = import::import(&storage, 3, &csv).await.unwrap();
+    assert_eq!(report.imported, 2);
+    let filter = ArticleFilter {
+        limit: 10,
+        ..ArticleFilter::default()
+    };
+    let exported = storage.articles(&1, &filter).await.unwrap();
+    let imported = storage.articles(&3, &filter).await.unwrap();
+    for (a, b) in exported.iter().zip(imported.iter()) {
+        assert_eq!(a.data.url, b.data.url);
+        assert_eq!(a.data.title, b.data.title);
+        assert_eq!(a.status, b.status);
+        assert_eq!(a.created_at, b.created_at);
+        assert_eq!(a.tags, b.tags);
+    }
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/export?format=pdf")
+            .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+}
+
+#[actix_rt::test]
+async fn test_atom_feeds() {
+    let state = init_state().await;
+    let id = create_article(&state.storage, 1, "https://example.com/a", "A & B").await;
+    state
+        .storage
+        .set_content(&1, &id, Some("<h1>A</h1><p>Some text</p>".to_string()))
+        .await
+        .unwrap();
+    create_article(&state.storage, 2, "https://example.com/other", "Other").await;
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::post()
+            .uri("/settings/feed")
+            .cookie(cookie)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::FOUND, resp.status());
+    let feed_token = storage.feed_token(&1).await.unwrap().unwrap();
+
+    let uri = format!("/feed/{}/pending.atom", feed_token);
+    let resp = test::call_service(&mut app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let etag = resp.headers().get(http::header::ETAG).unwrap().clone();
+    let last_modified = resp
+        .headers()
+        .get(http::header::LAST_MODIFIED)
+        .unwrap()
+        .clone();
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("<title>A &amp; B</title>"), "{}", body);
+    assert!(body.contains("<link href=\"https://example.com/a\"/>"));
+    assert!(body.contains("<summary>Some text</summary>"));
+    assert!(!body.contains("example.com/other"));
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri(&uri)
+            .header(http::header::IF_NONE_MATCH, etag.clone())
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::NOT_MODIFIED, resp.status());
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri(&uri)
+            .header(http::header::IF_MODIFIED_SINCE, last_modified)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::NOT_MODIFIED, resp.status());
+
+    storage.archive(&1, &id).await.unwrap();
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri(&uri)
+            .header(http::header::IF_NONE_MATCH, etag)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(!body.contains("<entry>"));
+
+    let uri = format!("/feed/{}/archived.atom", feed_token);
+    let resp = test::call_service(&mut app, test::TestRequest::get().uri(&uri).to_request()).await;
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("https://example.com/a"));
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/feed/wrong/pending.atom")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+}
+
+#[actix_rt::test]
+async fn test_add_and_bookmarklet() {
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::post()
+            .uri("/add")
+            .cookie(cookie.clone())
+            .set_form(&[("url", "https://example.com/form"), ("title", "From form")])
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/save?url=https%3A%2F%2Fexample.com%2Fpage&title=Page%20%26%20title")
+            .cookie(cookie.clone())
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("Saved <b>Page &amp; title</b>"), "{}", body);
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/save?url=not%20a%20url")
+            .cookie(cookie.clone())
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::post()
+            .uri("/add")
+            .cookie(cookie.clone())
+            .set_form(&[("url", "javascript:alert(document.cookie)")])
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/save?url=javascript%3Aalert(1)")
+            .cookie(cookie)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/save?url=https%3A%2F%2Fexample.com%2Fanon")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+
+    let titles: Vec<Option<String>> = list(&storage, 1, ArticleStatus::Pending)
+        .await
+        .into_iter()
+        .map(|a| a.data.title)
+        .collect();
+    assert_eq!(
+        titles,
+        vec![
+            Some("Page & title".to_string()),
+            Some("From form".to_string())
+        ]
+    );
+}
+
+#[actix_rt::test]
+async fn test_share_target() {
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/manifest.webmanifest")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let json: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!("/share", json["share_target"]["action"]);
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::post()
+            .uri("/share")
+            .cookie(cookie.clone())
+            .set_form(&[
+                ("title", "Shared page"),
+                (
+                    "text",
+                    "Look at this https://example.com/shared?a=1 it's great",
+                ),
+            ])
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::post()
+            .uri("/share")
+            .cookie(cookie)
+            .set_form(&[("title", "Only title"), ("text", "no links here")])
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+    let links = list(&storage, 1, ArticleStatus::Pending).await;
+    assert_eq!(1, links.len());
+    assert_eq!("https://example.com/shared?a=1", links[0].data.url.as_str());
+    assert_eq!(Some("Shared page".to_string()), links[0].data.title);
+}
+
+#[actix_rt::test]
+async fn test_pending_list_pages() {
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    let mut ids = vec![];
+    for i in 0..35 {
+        let url = format!("http://link{}", i);
+        ids.push(create_article(&state.storage, 1, &url, &format!("Title {}", i)).await);
+    }
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/")
+            .cookie(cookie.clone())
+            .to_request(),
+    )
+    .await;
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert_eq!(30, body.matches("class=\"card-text\"").count());
+    assert!(body.contains("Title 34"));
+    assert!(!body.contains("Title 4<"));
+    let next = format!("/?before={}", ids[5]);
+    assert!(body.contains(&format!("hx-get=\"{}\"", next)), "{}", body);
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri(&next)
+            .header("HX-Request", "true")
+            .cookie(cookie)
+            .to_request(),
+    )
+    .await;
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(!body.contains("<html"));
+    assert_eq!(5, body.matches("class=\"card-text\"").count());
+    assert!(body.contains("Title 4<"));
+    assert!(!body.contains("hx-trigger=\"revealed\""));
+
+    let filter = ArticleFilter {
+        status: Some(ArticleStatus::Pending),
+        before: Some(ids[5]),
+        limit: 3,
+        ..ArticleFilter::default()
+    };
+    let page: Vec<i64> = storage
+        .articles(&1, &filter)
+        .await
+        .unwrap()
+        .iter()
+        .map(|a| a.id)
+        .collect();
+    assert_eq!(vec![ids[4], ids[3], ids[2]], page);
+}
+
+#[actix_rt::test]
+async fn test_card_actions_undo() {
+    let state = init_state().await;
+    let archived = create_article(&state.storage, 1, "http://archived", "To archive").await;
+    let deleted = create_article(&state.storage, 1, "http://deleted", "To delete").await;
+    state
+        .storage
+        .set_tags(&1, &deleted, &["keep".to_string()])
+        .await
+        .unwrap();
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+
+    let (status, body) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri(&format!("/archive/{}", archived)),
+        &cookie,
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, status);
+    assert!(body.contains("Archived"));
+    assert!(body.contains(&format!("hx-post=\"/undo/{}\"", archived)));
+
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::delete().uri(&format!("/pending/delete/{}", deleted)),
+        &cookie,
+    )
+    .await;
+    assert!(body.contains("Moved to trash"));
+    assert_eq!(0, list(&storage, 1, ArticleStatus::Pending).await.len());
+
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri(&format!("/undo/{}", archived)),
+        &cookie,
+    )
+    .await;
+    assert!(body.contains(&format!("id=\"card-{}\"", archived)));
+    assert!(body.contains("To archive"));
+    assert!(body.contains(&format!("hx-post=\"/archive/{}\"", archived)));
+
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri(&format!("/undo/{}", deleted)),
+        &cookie,
+    )
+    .await;
+    assert!(body.contains("To delete"));
+    let restored = storage.get_article(&1, &deleted).await.unwrap().unwrap();
+    assert_eq!(ArticleStatus::Pending, restored.status);
+    assert_eq!(vec!["keep"], restored.tags);
+    assert_eq!(2, list(&storage, 1, ArticleStatus::Pending).await.len());
+
+    let (status, body) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri(&format!("/undo/{}", deleted)),
+        &cookie,
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, status);
+    assert!(body.contains("too late"));
+
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri("/archive/100"),
+        &cookie,
+    )
+    .await;
+    assert!(body.contains("alert-danger"));
+}
+
+#[actix_rt::test]
+async fn test_trash() {
+    let state = init_state().await;
+    let restored = create_article(&state.storage, 1, "http://restored", "To restore").await;
+    let emptied = create_archived_article(&state.storage, 1, "http://emptied", "To empty").await;
+    let purged = create_article(&state.storage, 2, "http://purged", "To purge").await;
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    storage.delete_pending(&1, &restored).await.unwrap();
+    storage.delete_archived(&1, &emptied).await.unwrap();
+    storage.delete_pending(&2, &purged).await.unwrap();
+    assert!(storage.get_article(&1, &restored).await.unwrap().is_none());
+
+    let (status, body) = htmx_call(&mut app, test::TestRequest::get().uri("/trash"), &cookie).await;
+    assert_eq!(http::StatusCode::OK, status);
+    assert!(body.contains("To restore"));
+    assert!(body.contains("To empty"));
+    assert!(!body.contains("To purge"));
+
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri(&format!("/trash/{}/restore", restored)),
+        &cookie,
+    )
+    .await;
+    assert!(body.contains("Restored to pending"));
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Pending).await.len());
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::post()
+            .uri("/trash/empty")
+            .cookie(cookie.clone())
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    assert!(storage.trash(&1).await.unwrap().is_empty());
+    assert_eq!(None, storage.restore(&1, &emptied).await.unwrap());
+
+    assert_eq!(0, storage.purge_trash(now() - 60).await.unwrap());
+    assert_eq!(1, storage.trash(&2).await.unwrap().len());
+    assert_eq!(1, storage.purge_trash(now() + 1).await.unwrap());
+    assert!(storage.trash(&2).await.unwrap().is_empty());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Pending).await.len());
+}
+
+#[actix_rt::test]
+async fn test_bulk_actions() {
+    let state = init_state().await;
+    let first = create_article(&state.storage, 1, "http://first", "First").await;
+    let second = create_article(&state.storage, 1, "http://second", "Second").await;
+    let third = create_archived_article(&state.storage, 1, "http://third", "Third").await;
+    let other = create_article(&state.storage, 2, "http://other", "Other").await;
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let bulk_request = |action: &str, body: String| {
+        test::TestRequest::post()
+            .uri(&format!("/bulk/{}", action))
+            .cookie(cookie.clone())
+            .header(
+                http::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .set_payload(body)
+            .to_request()
+    };
+
+    let req = bulk_request(
+        "archive",
+        format!("ids={}&ids={}&ids={}", first, second, other),
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    assert_eq!(2, list(&storage, 1, ArticleStatus::Pending).await.len());
+    assert_eq!(1, list(&storage, 2, ArticleStatus::Pending).await.len());
+
+    let req = bulk_request(
+        "archive",
+        format!("page=pending&ids={}&ids={}&ids={}", first, second, third),
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    assert_eq!("/", resp.headers().get(http::header::LOCATION).unwrap());
+    assert!(list(&storage, 1, ArticleStatus::Pending).await.is_empty());
+    assert_eq!(3, list(&storage, 1, ArticleStatus::Archived).await.len());
+
+    let req = bulk_request(
+        "tag",
+        format!("page=archived&tags=Rust%2C+web&ids={}&ids={}", first, third),
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(
+        "/archived",
+        resp.headers().get(http::header::LOCATION).unwrap()
+    );
+    let article = storage.get_article(&1, &third).await.unwrap().unwrap();
+    assert_eq!(vec!["rust", "web"], article.tags);
+    let req = bulk_request("tag", format!("tags=+&ids={}", first));
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+    let req = bulk_request("unarchive", format!("ids={}", second));
+    test::call_service(&mut app, req).await;
+    let req = bulk_request("delete", format!("ids={}&ids={}", first, second));
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    assert_eq!(2, storage.trash(&1).await.unwrap().len());
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Archived).await.len());
+
+    let req = bulk_request("delete", format!("ids={}", first));
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    let req = bulk_request("explode", format!("ids={}", third));
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+}
+
+#[actix_rt::test]
+async fn test_starred() {
+    let state = init_state().await;
+    let pending = create_article(&state.storage, 1, "http://pending", "Pending one").await;
+    let archived =
+        create_archived_article(&state.storage, 1, "http://archived", "Archived one").await;
+    let other = create_article(&state.storage, 2, "http://other", "Other").await;
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let token = api_token(&state, 1).await;
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+
+    for id in &[pending, archived] {
+        let (status, body) = htmx_call(
+            &mut app,
+            test::TestRequest::post().uri(&format!("/star/{}", id)),
+            &cookie,
+        )
+        .await;
+        assert_eq!(http::StatusCode::OK, status);
+        assert!(body.contains("title=\"Unstar\""), "{}", body);
+    }
+    let (status, _) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri(&format!("/star/{}", other)),
+        &cookie,
+    )
+    .await;
+    assert_eq!(http::StatusCode::NOT_FOUND, status);
+
+    let (_, body) = htmx_call(&mut app, test::TestRequest::get().uri("/starred"), &cookie).await;
+    assert!(body.contains("Pending one"));
+    assert!(body.contains("Archived one"));
+    let (_, body) = htmx_call(&mut app, test::TestRequest::get().uri("/"), &cookie).await;
+    assert!(body.contains("title=\"Unstar\""));
+
+    // Starred articles keep the star when they move between lists.
+    storage.archive(&1, &pending).await.unwrap();
+    let page: ArticleList = api_call(
+        &mut app,
+        test::TestRequest::get(),
+        "/api/v1/articles?starred=true",
+        &token,
+    )
+    .await;
+    assert_eq!(2, page.articles.len());
+    assert!(page.articles.iter().all(|a| a.starred));
+
+    let article: ArticleJson = api_call(
+        &mut app,
+        test::TestRequest::patch().set_json(&serde_json::json!({ "starred": false })),
+        &format!("/api/v1/articles/{}", archived),
+        &token,
+    )
+    .await;
+    assert!(!article.starred);
+    let (_, body) = htmx_call(&mut app, test::TestRequest::get().uri("/starred"), &cookie).await;
+    assert!(body.contains("Pending one"));
+    assert!(!body.contains("Archived one"));
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri("/export?format=csv")
+            .cookie(cookie)
+            .to_request(),
+    )
+    .await;
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("http://pending/,Pending one,archived,"));
+    assert!(body
+        .lines()
+        .any(|l| l.starts_with("http://pending/") && l.ends_with(",1")));
+    assert!(body
+        .lines()
+        .any(|l| l.starts_with("http://archived/") && l.ends_with(",0")));
+}
+
+#[actix_rt::test]
+async fn test_reader_annotations() {
+    let state = init_state().await;
+    let id = create_article(&state.storage, 1, "http://article", "Article").await;
+    state
+        .storage
+        .set_content(&1, &id, Some("<p>Quoted &amp; text</p>".to_string()))
+        .await
+        .unwrap();
+    let other = create_article(&state.storage, 2, "http://other", "Other").await;
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let post = |uri: String, form: &[(&str, &str)]| {
+        test::TestRequest::post()
+            .uri(&uri)
+            .cookie(cookie.clone())
+            .set_form(&form)
+            .to_request()
+    };
+
+    for (kind, text) in &[("highlight", "Quoted & text"), ("note", "My thought")] {
+        let req = post(
+            format!("/read/{}/annotations", id),
+            &[("kind", kind), ("text", text), ("anchor", "block-0")],
+        );
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    }
+    let req = post(
+        format!("/read/{}/annotations", other),
+        &[("kind", "note"), ("text", "Not mine")],
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    let req = post(
+        format!("/read/{}/annotations", id),
+        &[("kind", "note"), ("text", " ")],
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+    let annotations = storage.annotations(&1, &id).await.unwrap();
+    assert_eq!(2, annotations.len());
+    assert_eq!(Some("block-0".to_string()), annotations[0].anchor);
+    let note = annotations[1].id;
+
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri(&format!("/read/{}", id))
+            .cookie(cookie.clone())
+            .to_request(),
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("<p>Quoted &amp; text</p>"));
+    assert!(body.contains("My thought"));
+
+    let req = post(
+        format!("/annotations/{}", note),
+        &[("text", "Changed thought")],
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    let resp = test::call_service(
+        &mut app,
+        test::TestRequest::get()
+            .uri(&format!("/read/{}/highlights.md", id))
+            .cookie(cookie.clone())
+            .to_request(),
+    )
+    .await;
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert_eq!(
+        "# Article\n\n<http://article/>\n\n> Quoted & text\n\nChanged thought\n",
+        body
+    );
+
+    let req = post(format!("/annotations/{}/delete", note), &[]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    assert_eq!(1, storage.annotations(&1, &id).await.unwrap().len());
+
+    let foreign = storage
+        .add_annotation(&2, &other, AnnotationKind::Note, "Theirs", None)
+        .await
+        .unwrap()
+        .unwrap();
+    let req = post(format!("/annotations/{}", foreign), &[("text", "Mine now")]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    let req = post(format!("/annotations/{}/delete", foreign), &[]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    assert_eq!(
+        "Theirs",
+        storage.annotations(&2, &other).await.unwrap()[0].text
+    );
+}
+
+#[actix_rt::test]
+async fn test_reading_progress() {
+    let state = init_state().await;
+    let id = create_article(&state.storage, 1, "http://long-read", "Long read").await;
+    let other = create_article(&state.storage, 2, "http://other", "Other").await;
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let post = |uri: String, form: &[(&str, &str)]| {
+        test::TestRequest::post()
+            .uri(&uri)
+            .cookie(cookie.clone())
+            .set_form(&form)
+            .to_request()
+    };
+
+    let req = post(
+        format!("/read/{}/progress", id),
+        &[("progress", "40"), ("position", "1200")],
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NO_CONTENT, resp.status());
+    let req = post(
+        format!("/read/{}/progress", id),
+        &[("progress", "20"), ("position", "600")],
+    );
+    test::call_service(&mut app, req).await;
+    let article = storage.get_article(&1, &id).await.unwrap().unwrap();
+    assert_eq!(40, article.progress);
+    assert_eq!(Some(600), article.position);
+    let req = post(
+        format!("/read/{}/progress", other),
+        &[("progress", "40"), ("position", "1200")],
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+
+    let (_, body) = htmx_call(&mut app, test::TestRequest::get().uri("/"), &cookie).await;
+    assert!(body.contains("title=\"40% read\""), "{}", body);
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::get().uri(&format!("/read/{}", id)),
+        &cookie,
+    )
+    .await;
+    assert!(body.contains("window.scrollTo(0, 600)"));
+
+    // Reading to the end archives only when it's turned on.
+    let req = post(
+        format!("/read/{}/progress", id),
+        &[("progress", "100"), ("position", "5000")],
+    );
+    test::call_service(&mut app, req).await;
+    assert_eq!(1, list(&storage, 1, ArticleStatus::Pending).await.len());
+    let req = post("/settings/reading".to_string(), &[("auto_archive", "on")]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::FOUND, resp.status());
+    assert!(storage.auto_archive(&1).await.unwrap());
+    let req = post(
+        format!("/read/{}/progress", id),
+        &[("progress", "100"), ("position", "5000")],
+    );
+    test::call_service(&mut app, req).await;
+    assert!(list(&storage, 1, ArticleStatus::Pending).await.is_empty());
+    let article = storage.get_article(&1, &id).await.unwrap().unwrap();
+    assert_eq!(ArticleStatus::Archived, article.status);
+    assert_eq!(100, article.progress);
+
+    let req = post("/settings/reading".to_string(), &[]);
+    test::call_service(&mut app, req).await;
+    assert!(!storage.auto_archive(&1).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_reading_time() {
+    let state = init_state().await;
+    let short = create_article(&state.storage, 1, "http://short", "Short").await;
+    let long = create_article(&state.storage, 1, "http://long", "Long").await;
+    create_article(&state.storage, 1, "http://not-fetched", "Not fetched").await;
+    let storage = state.storage.clone();
+    storage
+        .set_content(&1, &short, Some("<p>Just a few words</p>".to_string()))
+        .await
+        .unwrap();
+    let long_text = format!("<p>{}</p>", "word ".repeat(1000));
+    storage
+        .set_content(&1, &long, Some(long_text))
+        .await
+        .unwrap();
+    let article = storage.get_article(&1, &long).await.unwrap().unwrap();
+    assert_eq!(Some(1000), article.word_count);
+    assert_eq!(Some(5), article.reading_minutes);
+
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let (_, body) = htmx_call(&mut app, test::TestRequest::get().uri("/"), &cookie).await;
+    assert_eq!(3, body.matches("class=\"card-text\"").count());
+    assert!(body.contains("5 min read · 1000 words"), "{}", body);
+    assert!(body.contains("1 min read · 4 words"), "{}", body);
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::get().uri("/?max_minutes=2"),
+        &cookie,
+    )
+    .await;
+    assert_eq!(1, body.matches("class=\"card-text\"").count());
+    assert!(body.contains("Short"));
+
+    let filter = ArticleFilter {
+        status: Some(ArticleStatus::Pending),
+        max_minutes: Some(2),
+        ..ArticleFilter::default()
+    };
+    let random = storage.random_article(&1, &filter).await.unwrap().unwrap();
+    assert_eq!(short, random.id);
+    let filter = ArticleFilter {
+        max_minutes: Some(0),
+        ..filter
+    };
+    assert!(storage.random_article(&1, &filter).await.unwrap().is_none());
+}
+
+#[actix_rt::test]
+async fn test_collections() {
+    let state = init_state().await;
+    let first = create_article(&state.storage, 1, "http://first", "First").await;
+    let second = create_article(&state.storage, 1, "http://second", "Second").await;
+    let third = create_archived_article(&state.storage, 1, "http://third", "Third").await;
+    let other = create_article(&state.storage, 2, "http://other", "Other").await;
+    let other_collection = state.storage.create_collection(&2, "Other").await.unwrap();
+    let storage = state.storage.clone();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let post = |uri: String, form: &[(&str, String)]| {
+        test::TestRequest::post()
+            .uri(&uri)
+            .cookie(cookie.clone())
+            .set_form(&form)
+            .to_request()
+    };
+
+    let req = post(
+        "/collections".to_string(),
+        &[("name", "Onboarding".to_string())],
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    let id = storage.collections(&1).await.unwrap()[0].id;
+    for article in &[first, second, third, first] {
+        let req = post(
+            format!("/collections/{}/articles", id),
+            &[("article_id", article.to_string())],
+        );
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    }
+    for (collection, article) in &[(id, other), (other_collection, first)] {
+        let req = post(
+            format!("/collections/{}/articles", collection),
+            &[("article_id", article.to_string())],
+        );
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    }
+    let order = |articles: Vec<Article>| articles.iter().map(|a| a.id).collect::<Vec<i64>>();
+    assert_eq!(
+        vec![first, second, third],
+        order(storage.collection_articles(&1, &id).await.unwrap())
+    );
+
+    let reorder = |ids: &[i64]| {
+        let body = ids
+            .iter()
+            .map(|id| format!("ids={}", id))
+            .collect::<Vec<String>>()
+            .join("&");
+        test::TestRequest::post()
+            .uri(&format!("/collections/{}/reorder", id))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .set_payload(body)
+    };
+    let (status, body) = htmx_call(&mut app, reorder(&[third, first, second]), &cookie).await;
+    assert_eq!(http::StatusCode::OK, status);
+    assert!(body.find("Third").unwrap() < body.find("First").unwrap());
+    assert_eq!(
+        vec![third, first, second],
+        order(storage.collection_articles(&1, &id).await.unwrap())
+    );
+    let (status, _) = htmx_call(&mut app, reorder(&[first, second]), &cookie).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, status);
+
+    // Published collections are readable without login until they are made private.
+    let req = post(format!("/collections/{}/publish", id), &[]);
+    test::call_service(&mut app, req).await;
+    let token = storage
+        .get_collection(&1, &id)
+        .await
+        .unwrap()
+        .unwrap()
+        .public_token
+        .unwrap();
+    let req = test::TestRequest::get()
+        .uri(&format!("/c/{}", token))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("Onboarding") && body.contains("http://second/"));
+    let req = post(format!("/collections/{}/unpublish", id), &[]);
+    test::call_service(&mut app, req).await;
+    let req = test::TestRequest::get()
+        .uri(&format!("/c/{}", token))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+
+    let req = post(
+        format!("/collections/{}/articles/{}/remove", id, first),
+        &[],
+    );
+    test::call_service(&mut app, req).await;
+    assert_eq!(
+        vec![third, second],
+        order(storage.collection_articles(&1, &id).await.unwrap())
+    );
+    let req = post(format!("/collections/{}/delete", other_collection), &[]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    let req = post(format!("/collections/{}/delete", id), &[]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    assert!(storage.collections(&1).await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn test_teams() {
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    let team = storage.create_team(&1, "Readers").await.unwrap();
+    storage
+        .set_team_member(&team.id, &2, Role::Viewer)
+        .await
+        .unwrap();
+    storage
+        .set_team_member(&team.id, &3, Role::Editor)
+        .await
+        .unwrap();
+    let first = create_article(&storage, team.account_id, "http://team/first", "First").await;
+    let second = create_article(&storage, team.account_id, "http://team/second", "Second").await;
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let owner = auth(&mut app, &1, &token_storage).await;
+    let viewer = auth(&mut app, &2, &token_storage).await;
+    let editor = auth(&mut app, &3, &token_storage).await;
+    let stranger = auth(&mut app, &4, &token_storage).await;
+    let team_page = format!("/teams/{}", team.id);
+
+    let (status, body) =
+        htmx_call(&mut app, test::TestRequest::get().uri(&team_page), &viewer).await;
+    assert_eq!(http::StatusCode::OK, status);
+    assert_eq!(2, body.matches("class=\"card-text\"").count());
+    assert!(!body.contains("hx-post=\"/archive/"));
+    let (status, _) = htmx_call(
+        &mut app,
+        test::TestRequest::get().uri(&team_page),
+        &stranger,
+    )
+    .await;
+    assert_eq!(http::StatusCode::NOT_FOUND, status);
+    // Team links are not in the personal lists of the members.
+    let (_, body) = htmx_call(&mut app, test::TestRequest::get().uri("/"), &owner).await;
+    assert_eq!(0, body.matches("class=\"card-text\"").count());
+
+    // Viewers and strangers can't change team links, editors can.
+    for cookie in &[&viewer, &stranger] {
+        let (_, body) = htmx_call(
+            &mut app,
+            test::TestRequest::post().uri(&format!("/archive/{}", first)),
+            cookie,
+        )
+        .await;
+        assert!(body.contains("Article not found"));
+        let (status, _) = htmx_call(
+            &mut app,
+            test::TestRequest::post().uri(&format!("/star/{}", first)),
+            cookie,
+        )
+        .await;
+        assert_eq!(http::StatusCode::NOT_FOUND, status);
+    }
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri(&format!("/archive/{}", first)),
+        &editor,
+    )
+    .await;
+    assert!(body.contains("Archived"));
+    let article = storage
+        .get_article(&team.account_id, &first)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ArticleStatus::Archived, article.status);
+    let (status, _) = htmx_call(
+        &mut app,
+        test::TestRequest::post().uri(&format!("/undo/{}", first)),
+        &editor,
+    )
+    .await;
+    assert_eq!(http::StatusCode::OK, status);
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::delete().uri(&format!("/pending/delete/{}", second)),
+        &viewer,
+    )
+    .await;
+    assert!(body.contains("Already deleted"));
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::delete().uri(&format!("/pending/delete/{}", second)),
+        &owner,
+    )
+    .await;
+    assert!(body.contains("Moved to trash"));
+    let pending = ArticleFilter {
+        status: Some(ArticleStatus::Pending),
+        limit: 10,
+        ..ArticleFilter::default()
+    };
+    let ids: Vec<i64> = storage
+        .articles(&team.account_id, &pending)
+        .await
+        .unwrap()
+        .iter()
+        .map(|a| a.id)
+        .collect();
+    assert_eq!(vec![first], ids);
+
+    let post = |uri: String, form: &[(&str, &str)], cookie: &Cookie| {
+        test::TestRequest::post()
+            .uri(&uri)
+            .cookie(cookie.clone())
+            .set_form(&form)
+            .to_request()
+    };
+    let req = post(
+        format!("/teams/{}/links", team.id),
+        &[("url", "http://team/third")],
+        &viewer,
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+    let req = post(
+        format!("/teams/{}/links", team.id),
+        &[("url", "javascript:alert(1)")],
+        &owner,
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+    let req = post(
+        format!("/teams/{}/members", team.id),
+        &[("user_id", "4"), ("role", "editor")],
+        &viewer,
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+    let req = post(
+        format!("/teams/{}/members", team.id),
+        &[("user_id", "4"), ("role", "editor")],
+        &owner,
+    );
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    assert_eq!(
+        Some(Role::Editor),
+        storage.role(&4, &team.account_id).await.unwrap()
+    );
+    let req = post(format!("/teams/{}/members/1/remove", team.id), &[], &owner);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+    let req = post(format!("/teams/{}/members/2/remove", team.id), &[], &viewer);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    assert_eq!(None, storage.role(&2, &team.account_id).await.unwrap());
+    assert_eq!(3, storage.team_members(&team.id).await.unwrap().len());
+}
+
+#[actix_rt::test]
+async fn test_chat_teams() {
+    let state = init_state().await;
+    let storage = state.storage;
+    // Links saved by a group before teams are kept by the id of the group chat.
+    let chat_id = -100_500;
+    let old = create_article(&storage, chat_id, "http://group/old", "Old").await;
+    let team = storage
+        .create_chat_team(&7, &chat_id, "Group")
+        .await
+        .unwrap();
+    assert_eq!(chat_id, team.account_id);
+    assert_eq!(Some(Role::Owner), storage.role(&7, &chat_id).await.unwrap());
+    assert!(storage
+        .get_article(&team.account_id, &old)
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        Some(team.id),
+        storage.chat_team(&chat_id).await.unwrap().map(|t| t.id)
+    );
+
+    let other = storage.create_team(&7, "Other").await.unwrap();
+    assert!(other.account_id < -(1 << 52));
+    storage.set_team_chat(&other.id, &chat_id).await.unwrap();
+    assert_eq!(
+        Some(other.id),
+        storage.chat_team(&chat_id).await.unwrap().map(|t| t.id)
+    );
+    let again = storage
+        .create_chat_team(&8, &chat_id, "Group")
+        .await
+        .unwrap();
+    assert_eq!(team.id, again.id);
+    assert_eq!(None, storage.role(&8, &chat_id).await.unwrap());
+    assert_eq!(
+        Some(team.id),
+        storage.chat_team(&chat_id).await.unwrap().map(|t| t.id)
+    );
+}
+
+#[actix_rt::test]
+async fn test_share_links() {
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    let first = create_article(&storage, 1, "http://first", "First").await;
+    create_archived_article(&storage, 1, "http://second", "Second").await;
+    let other = create_article(&storage, 2, "http://other", "Other").await;
+    storage
+        .set_content(
+            &1,
+            &first,
+            Some("<p>Opening words of the article</p>".to_string()),
+        )
+        .await
+        .unwrap();
+    for (kind, text) in &[
+        (AnnotationKind::Highlight, "Quoted line"),
+        (AnnotationKind::Note, "Private thought"),
+    ] {
+        storage
+            .add_annotation(&1, &first, *kind, text, None)
+            .await
+            .unwrap();
+    }
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let post = |form: &[(&str, String)]| {
+        test::TestRequest::post()
+            .uri("/shares")
+            .cookie(cookie.clone())
+            .set_form(&form)
+            .to_request()
+    };
+    let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+    let req = post(&[
+        ("article_id", first.to_string()),
+        ("expires_in_days", "".to_string()),
+    ]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    let req = post(&[("article_id", other.to_string())]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    let req = post(&[("status", "archived".to_string())]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    let req = post(&[("expires_in_days", "0".to_string())]);
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+    let shares = storage.share_links(&1).await.unwrap();
+    assert_eq!(2, shares.len());
+    let (list, article) = (&shares[0], &shares[1]);
+    assert_eq!(ShareTarget::Article(first), article.target);
+
+    // Anyone can read a share without login, notes stay private.
+    let resp = test::call_service(&mut app, get(format!("/s/{}", article.token))).await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("First") && body.contains("http://first/"));
+    assert!(body.contains("Opening words") && body.contains("Quoted line"));
+    assert!(!body.contains("Private thought"));
+    let resp = test::call_service(&mut app, get(format!("/s/{}", list.token))).await;
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("Second") && !body.contains("First"));
+
+    let req = test::TestRequest::get()
+        .uri("/shares")
+        .cookie(cookie.clone())
+        .to_request();
+    let body = String::from_utf8(
+        test::read_body(test::call_service(&mut app, req).await)
+            .await
+            .to_vec(),
+    )
+    .unwrap();
+    assert!(body.contains(&format!("/s/{}", list.token)));
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/shares/{}/revoke", article.id))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    let resp = test::call_service(&mut app, get(format!("/s/{}", article.token))).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+
+    // Expired links are kept for the owner but don't work anymore.
+    let expired = ShareTarget::List {
+        status: None,
+        tag: None,
+        starred: None,
+    };
+    storage
+        .add_share_link(&1, "expired", &expired, Some(now() - 1))
+        .await
+        .unwrap();
+    let resp = test::call_service(&mut app, get("/s/expired".to_string())).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+    assert_eq!(2, storage.share_links(&1).await.unwrap().len());
+}
+
+#[actix_rt::test]
+async fn test_link_health() {
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    let gone = create_article(&storage, 1, "http://127.0.0.1:1/gone", "Gone").await;
+    let moved = create_article(&storage, 1, "http://127.0.0.1:1/old", "Moved").await;
+    let alive = create_archived_article(&storage, 2, "http://127.0.0.1:1/alive", "Alive").await;
+    let missing =
+        create_archived_article(&storage, 1, "http://127.0.0.1:1/missing", "Missing").await;
+    let ids = |links: Vec<(i64, url::Url)>| {
+        let mut ids: Vec<i64> = links.iter().map(|(id, _)| *id).collect();
+        ids.sort();
+        ids
+    };
+    assert_eq!(
+        vec![gone, moved, alive, missing],
+        ids(storage.links_to_check(now(), 10).await.unwrap())
+    );
+    let article = |id: i64| {
+        let storage = storage.clone();
+        async move { storage.get_article(&1, &id).await.unwrap().unwrap() }
+    };
+
+    // Nothing listens on the port, when none of the links answers the server is likely offline.
+    assert_eq!(0, check_links(&storage, 10).await.unwrap());
+    assert_eq!(4, storage.links_to_check(now(), 10).await.unwrap().len());
+    assert_eq!(0, article(gone).await.failed_checks);
+
+    storage
+        .set_link_health(&moved, 200, Some("http://new.example/page"))
+        .await
+        .unwrap();
+    storage.set_link_health(&alive, 200, None).await.unwrap();
+    storage.set_link_health(&missing, 404, None).await.unwrap();
+    assert!(article(missing).await.is_dead());
+    assert!(!article(moved).await.is_dead());
+    // A link which doesn't answer is dead only after several checks in a row.
+    assert_eq!(1, check_links(&storage, 10).await.unwrap());
+    let unreachable = article(gone).await;
+    assert_eq!(1, unreachable.failed_checks);
+    assert!(!unreachable.is_dead() && unreachable.http_status.is_none());
+    for _ in 1..DEAD_AFTER_FAILED_CHECKS {
+        storage.set_link_unreachable(&gone).await.unwrap();
+    }
+    let unreachable = article(gone).await;
+    assert!(unreachable.is_dead());
+    assert!(unreachable
+        .archive_url()
+        .ends_with("/http://127.0.0.1:1/gone"));
+    assert!(storage
+        .links_to_check(now() - 60, 10)
+        .await
+        .unwrap()
+        .is_empty());
+    storage.set_link_health(&gone, 200, None).await.unwrap();
+    assert!(!article(gone).await.is_dead());
+    for _ in 0..DEAD_AFTER_FAILED_CHECKS {
+        storage.set_link_unreachable(&gone).await.unwrap();
+    }
+
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let (_, body) = htmx_call(&mut app, test::TestRequest::get().uri("/"), &cookie).await;
+    assert_eq!(1, body.matches("Dead link").count());
+    assert!(body.contains("https://web.archive.org/web/"));
+    let (_, body) = htmx_call(&mut app, test::TestRequest::get().uri("/archived"), &cookie).await;
+    assert_eq!(1, body.matches("Dead link").count());
+    let (_, body) = htmx_call(
+        &mut app,
+        test::TestRequest::get().uri(&format!("/read/{}", moved)),
+        &cookie,
+    )
+    .await;
+    assert!(body.contains("moved to") && body.contains("http://new.example/page"));
+}
+
+#[actix_rt::test]
+async fn test_snapshots() {
+    let fixture = test::start(|| {
+        let file = |content_type: &'static str, body: &'static [u8]| {
+            move || {
+                actix_web::HttpResponse::Ok()
+                    .content_type(content_type)
+                    .body(body)
+            }
+        };
+        App::new()
+            .route(
+                "/page",
+                actix_web::web::get().to(file(
+                    "text/html",
+                    br#"<html><head><link rel="stylesheet" href="/style.css"><script>alert(1)</script>
+                    </head><body><img src="img.png" srcset="img@2x.png 2x"><a href="/next">Next</a></body></html>"#,
+                )),
+            )
+            .route(
+                "/style.css",
+                actix_web::web::get().to(file("text/css", b"body { background: url('bg.png') }")),
+            )
+            .route("/img.png", actix_web::web::get().to(file("image/png", b"png")))
+            .route("/bg.png", actix_web::web::get().to(file("image/png", b"background")))
+    });
+    let tmp_dir = TempDir::new("sqlite").unwrap();
+    let state = init_state_in(&tmp_dir).await;
+    let storage = state.storage.clone();
+    let url = url::Url::parse(&fixture.url("/page")).unwrap();
+
+    let html = snapshot::snapshot(&url, snapshot::MAX_SNAPSHOT_BYTES)
+        .await
+        .unwrap();
+    assert!(!html.contains("<script"));
+    assert!(html.contains(&format!("data:image/png;base64,{}", "cG5n")));
+    assert!(!html.contains("srcset"));
+    assert!(html.contains(&format!("href=\"{}\"", fixture.url("/next"))));
+    let css = html
+        .split("data:text/css;base64,")
+        .nth(1)
+        .and_then(|s| s.split('"').next())
+        .unwrap();
+    let css = String::from_utf8(openssl::base64::decode_block(css).unwrap()).unwrap();
+    // "background" in base64.
+    assert!(css.contains("url(\"data:image/png;base64,YmFja2dyb3VuZA==\")"));
+    assert!(snapshot::snapshot(&url, 100).await.is_err());
+
+    // Snapshots are taken in the background of saves once the user turns them on.
+    let first = save_link(&storage, 1, url.clone(), Some("Page".to_string()))
+        .await
+        .unwrap();
+    storage.set_snapshots_enabled(&1, true).await.unwrap();
+    let second = save_link(&storage, 1, url, Some("Page".to_string()))
+        .await
+        .unwrap();
+    let mut taken = false;
+    for _ in 0..50 {
+        if storage.snapshot_info(&1, &second).await.unwrap().is_some() {
+            taken = true;
+            break;
+        }
+        actix_rt::time::delay_for(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(taken);
+    assert!(storage.snapshot_info(&1, &first).await.unwrap().is_none());
+
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let other = auth(&mut app, &2, &token_storage).await;
+    let req = test::TestRequest::post()
+        .uri(&format!("/snapshot/{}", first))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::SEE_OTHER, resp.status());
+    let req = test::TestRequest::get()
+        .uri(&format!("/snapshot/{}", first))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    let policy = resp
+        .headers()
+        .get(http::header::CONTENT_SECURITY_POLICY)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(policy.contains("sandbox"));
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("data:image/png;base64,"));
+    let req = test::TestRequest::get()
+        .uri(&format!("/snapshot/{}", first))
+        .cookie(other)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+}
+
+#[actix_rt::test]
+async fn test_epub() {
+    let fixture = test::start(|| {
+        App::new().route(
+            "/img.png",
+            actix_web::web::get().to(|| {
+                actix_web::HttpResponse::Ok()
+                    .content_type("image/png")
+                    .body("png")
+            }),
+        )
+    });
+    let state = init_state().await;
+    let storage = state.storage.clone();
+    let first = create_article(&storage, 1, "http://first", "First").await;
+    let second = create_article(&storage, 1, "http://second", "Second & more").await;
+    create_archived_article(&storage, 1, "http://archived", "Archived").await;
+    storage
+        .set_content(
+            &1,
+            &first,
+            Some(format!(
+                r#"<p>Text</p><p><img src="{}"></p>"#,
+                fixture.url("/img.png")
+            )),
+        )
+        .await
+        .unwrap();
+    let token_storage = state.token_storage.clone();
+    let mut app = app(state).await;
+    let cookie = auth(&mut app, &1, &token_storage).await;
+    let other = auth(&mut app, &2, &token_storage).await;
+    let get = |uri: String, cookie: &Cookie<'static>| {
+        test::TestRequest::get()
+            .uri(&uri)
+            .cookie(cookie.clone())
+            .to_request()
+    };
+
+    let resp = test::call_service(&mut app, get("/export/epub".to_string(), &cookie)).await;
+    assert_eq!(http::StatusCode::OK, resp.status());
+    assert_eq!(
+        "application/epub+zip",
+        resp.headers().get(http::header::CONTENT_TYPE).unwrap()
+    );
+    let book = epub_entries(&test::read_body(resp).await);
+    assert_eq!("application/epub+zip", book["mimetype"]);
+    assert!(book["OEBPS/content.opf"].contains(r#"href="images/1.png" media-type="image/png""#));
+    let nav = &book["OEBPS/nav.xhtml"];
+    assert!(nav.contains("Second &amp; more</a></li>") && nav.contains("First</a></li>"));
+    assert!(!nav.contains("Archived"));
+    // Newest first, the second article is the first chapter.
+    assert!(book["OEBPS/articles/1.xhtml"].contains("<h1>Second &amp; more</h1>"));
+    let first_chapter = &book["OEBPS/articles/2.xhtml"];
+    assert!(first_chapter.contains("<h1>First</h1>"));
+    assert!(first_chapter.contains(r#"<img src="../images/1.png" alt="" />"#));
+    assert_eq!("png", book["OEBPS/images/1.png"]);
+    assert!(!book.contains_key("OEBPS/articles/3.xhtml"));
+
+    let resp = test::call_service(
+        &mut app,
+        get(format!("/export/epub?ids={}", second), &cookie),
+    )
+    .await;
+    let book = epub_entries(&test::read_body(resp).await);
+    assert!(book["OEBPS/articles/1.xhtml"].contains("Second &amp; more"));
+    assert!(!book.contains_key("OEBPS/articles/2.xhtml"));
+    let resp = test::call_service(&mut app, get("/export/epub".to_string(), &other)).await;
+    assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+}
+
+#[actix_rt::test]
+async fn test_link_ids_migration() {
+    let tmp_dir = TempDir::new("sqlite").unwrap();
+    let dir = tmp_dir.path().join("sqlite.db");
+    File::create(dir.clone()).unwrap();
+    let db_pool = SqlitePoolOptions::new()
+        .connect(dir.to_str().unwrap())
+        .await
+        .unwrap();
+    // Before ids were kept on archiving, both tables numbered their links on their own.
+    sqlx::query(
+        "
+        CREATE TABLE pending_links (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            title TEXT NULL,
+            url TEXT NOT NULL
+        );
+        CREATE TABLE archived_links (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            title TEXT NULL,
+            url TEXT NOT NULL
+        );
+        CREATE TABLE article_tags (
+            article_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (article_id, tag)
+        );
+        INSERT INTO pending_links(user_id, title, url) values(1, 'Pending', 'http://pending');
+        INSERT INTO pending_links(user_id, title, url) values(1, 'Other', 'http://other');
+        INSERT INTO archived_links(user_id, title, url) values(1, 'Archived', 'http://archived');
+        INSERT INTO archived_links(user_id, title, url) values(2, 'Foreign', 'http://foreign');
+        INSERT INTO article_tags(article_id, user_id, tag) values(1, 1, 'mine');
+        INSERT INTO article_tags(article_id, user_id, tag) values(2, 2, 'theirs');
+        ",
+    )
+    .execute(&db_pool)
+    .await
+    .unwrap();
+    let storage = Storage::init(db_pool.clone()).await.unwrap();
+    let all = ArticleFilter {
+        limit: 100,
+        ..ArticleFilter::default()
+    };
+
+    let articles = storage.articles(&1, &all).await.unwrap();
+    let titles: Vec<(i64, String)> = articles
+        .iter()
+        .map(|a| (a.id, a.data.title.clone().unwrap()))
+        .collect();
+    assert_eq!(
+        vec![
+            (3, "Archived".to_string()),
+            (2, "Other".to_string()),
+            (1, "Pending".to_string())
+        ],
+        titles
+    );
+    let pending = storage.get_article(&1, &1).await.unwrap().unwrap();
+    assert_eq!(vec!["mine".to_string()], pending.tags);
+    assert!(storage
+        .get_article(&1, &3)
+        .await
+        .unwrap()
+        .unwrap()
+        .tags
+        .is_empty());
+    assert!(storage.get_article(&2, &2).await.unwrap().is_none());
+    let foreign = storage.get_article(&2, &4).await.unwrap().unwrap();
+    assert_eq!(vec!["theirs".to_string()], foreign.tags);
+    assert_eq!(Some(2), storage.link_owner(&4).await.unwrap());
+
+    // New links get ids above the moved ones, and keep them when archived.
+    let new = create_archived_article(&storage, 1, "http://new", "New").await;
+    assert_eq!(5, new);
+    assert_eq!(Some(1), storage.link_owner(&5).await.unwrap());
+    // Running it again changes nothing.
+    let storage = Storage::init(db_pool).await.unwrap();
+    assert_eq!(Some(5), storage.unarchive(&1, &5).await.unwrap());
+    assert_eq!(4, storage.articles(&1, &all).await.unwrap().len());
+}
+
+/// Entries of the book by their path, read back with a zip reader.
+fn epub_entries(book: &[u8]) -> std::collections::HashMap<String, String> {
+    use std::io::Read;
+    let mut zip_file = zip::ZipArchive::new(std::io::Cursor::new(book)).unwrap();
+    let mut entries = std::collections::HashMap::new();
+    for i in 0..zip_file.len() {
+        let mut entry = zip_file.by_index(i).unwrap();
+        let mut text = String::new();
+        entry.read_to_string(&mut text).unwrap();
+        entries.insert(entry.name().to_string(), text);
+    }
+    entries
+}
+
+/// Calls the app as htmx does, returns the status and the body of the response.
+async fn htmx_call(
+    app: &mut impl Service<
+        Request = Request,
+        Response = ServiceResponse<impl MessageBody + Unpin>,
+        Error = Error,
+    >,
+    req: test::TestRequest,
+    cookie: &Cookie<'_>,
+) -> (http::StatusCode, String) {
+    let resp = test::call_service(
+        app,
+        req.cookie(cookie.clone())
+            .header("HX-Request", "true")
+            .to_request(),
+    )
+    .await;
+    let status = resp.status();
+    let body = test::read_body(resp).await;
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// Replays recorded client requests, where `{name}` placeholders are filled with
+/// values saved from previous responses by json pointers in `save`.
+async fn replay_wallabag(fixture: &str) {
+    let state = init_state().await;
+    let mut vars = serde_json::Map::new();
+    vars.insert("password".to_string(), api_token(&state, 1).await.into());
+    let mut app = app(state).await;
+    let steps: Vec<serde_json::Value> =
+        serde_json::from_str(&std::fs::read_to_string(fixture).unwrap()).unwrap();
+
+    for step in steps {
+        let step = substitute(&step, &vars);
+        let request = &step["request"];
+        let uri = request["uri"].as_str().unwrap();
+        let mut req = test::TestRequest::default()
+            .method(
+                http::Method::from_bytes(request["method"].as_str().unwrap().as_bytes()).unwrap(),
+            )
+            .uri(uri);
+        for (name, value) in request["headers"].as_object().into_iter().flatten() {
+            req = req.header(name.as_str(), value.as_str().unwrap());
+        }
+        if let Some(body) = request["body"].as_str() {
+            req = req.set_payload(body.to_string());
+        }
+        let resp = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(
+            step["response"]["status"],
+            resp.status().as_u16(),
+            "{}",
+            uri
+        );
+        let body = test::read_body(resp).await;
+        if !step["response"]["json"].is_null() {
+            let actual: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_subset(&step["response"]["json"], &actual, uri);
+            for (name, pointer) in step["save"].as_object().into_iter().flatten() {
+                let value = actual.pointer(pointer.as_str().unwrap()).unwrap().clone();
+                vars.insert(name.clone(), value);
+            }
+        }
+    }
+}
+
+fn substitute(
+    value: &serde_json::Value,
+    vars: &serde_json::Map<String, serde_json::Value>,
+) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::String(s) => {
+            let whole = s.trim_start_matches('{').trim_end_matches('}');
+            match vars.get(whole) {
+                Some(v) if s == &format!("{{{}}}", whole) => v.clone(),
+                _ => Value::String(vars.iter().fold(s.clone(), |s, (name, v)| {
+                    let v = v.as_str().map(|v| v.to_string()).unwrap_or(v.to_string());
+                    s.replace(&format!("{{{}}}", name), &v)
+                })),
+            }
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|i| substitute(i, vars)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), substitute(v, vars)))
+                .collect(),
+        ),
+        v => v.clone(),
+    }
+}
+
+fn assert_subset(expected: &serde_json::Value, actual: &serde_json::Value, context: &str) {
+    use serde_json::Value;
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            for (k, v) in e {
+                assert_subset(v, a.get(k).unwrap_or(&Value::Null), context);
+            }
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            assert_eq!(e.len(), a.len(), "{}: {:?}", context, actual);
+            for (e, a) in e.iter().zip(a.iter()) {
+                assert_subset(e, a, context);
+            }
+        }
+        (e, a) => assert_eq!(e, a, "{}", context),
+    }
+}
+
+async fn api_token(state: &AppState<'_>, user_id: i64) -> String {
+    issue_api_token(
+        &state.storage,
+        &user_id,
+        "test",
+        &[Scope::Read, Scope::Write],
+    )
+    .await
+    .unwrap()
+}
+
+async fn api_call<T: serde::de::DeserializeOwned>(
+    app: &mut impl Service<
+        Request = Request,
+        Response = ServiceResponse<impl MessageBody + Unpin>,
+        Error = Error,
+    >,
+    req: test::TestRequest,
+    uri: &str,
+    token: &str,
+) -> T {
+    let resp = test::call_service(
+        app,
+        req.header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+            .uri(uri)
+            .to_request(),
+    )
+    .await;
+    assert!(resp.status().is_success(), "{:?}", resp.status());
+    test::read_body_json(resp).await
 }
 
 async fn auth<'a>(
@@ -276,12 +2562,17 @@ async fn app(
 }
 
 async fn init_state<'a>() -> AppState<'a> {
+    let tmp_dir = TempDir::new("sqlite").unwrap();
+    init_state_in(&tmp_dir).await
+}
+
+/// State with the database in the directory, to keep it while tasks open new connections.
+async fn init_state_in<'a>(tmp_dir: &TempDir) -> AppState<'a> {
     let mut handlebars = Handlebars::new();
     handlebars
         .register_templates_directory(".html", "./templates")
         .unwrap();
     let handlebars_ref = Arc::new(handlebars);
-    let tmp_dir = TempDir::new("sqlite").unwrap();
     let dir = tmp_dir.path().join("sqlite.db");
 
     File::create(dir.clone()).unwrap();
@@ -295,10 +2586,22 @@ async fn init_state<'a>() -> AppState<'a> {
     AppState {
         storage: storage.clone(),
         token_storage: token_storage.clone(),
+        oauth_codes: Arc::new(OauthCodes::new(100)),
+        trash_retention_days: 30,
         hb: handlebars_ref.clone(),
     }
 }
 
+/// Articles of the user with the status, newest first.
+async fn list(storage: &Storage, user_id: i64, status: ArticleStatus) -> Vec<Article> {
+    let filter = ArticleFilter {
+        status: Some(status),
+        limit: 100,
+        ..ArticleFilter::default()
+    };
+    storage.articles(&user_id, &filter).await.unwrap()
+}
+
 async fn create_article(storage: &Storage, user_id: i64, url: &str, title: &str) -> i64 {
     let article = ArticleData {
         user_id,